@@ -8,9 +8,14 @@ const PAGE_SIZE: Option<i32> = None;
 /// After one day a peer is considered invalid and it's deleted from the sqlite db.
 const PEERS_VALIDITY: i64 = 1;
 
-async fn db_sync(update_time: i64, first_sync: bool) -> Result<(), Box<dyn Error>> {
+async fn db_sync(
+    update_time: i64,
+    first_sync: bool,
+    since: Option<Duration>,
+    aws_profile: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
     // dynamoDB setup
-    let dynamo_db = AwsPeerDB::new().await;
+    let dynamo_db = AwsPeerDB::new_with_profile(aws_profile).await;
     // sqliteDB setup
     let sqlite_db = SqlPeerDB::new().await;
 
@@ -24,9 +29,11 @@ async fn db_sync(update_time: i64, first_sync: bool) -> Result<(), Box<dyn Error
 
     // scan table
     let peers = if first_sync {
-        dynamo_db.all_peers(PAGE_SIZE).await?
+        dynamo_db.all_peers_since(since, PAGE_SIZE, false).await?
     } else {
-        dynamo_db.all_last_peers(time_difference, PAGE_SIZE).await?
+        dynamo_db
+            .all_last_peers(time_difference, PAGE_SIZE, false)
+            .await?
     };
 
     // update sqliteDB from dynamoDB
@@ -35,18 +42,25 @@ async fn db_sync(update_time: i64, first_sync: bool) -> Result<(), Box<dyn Error
     }
 
     // prune data older than a day in the sqliteDB
-    sqlite_db.prune_peers(PEERS_VALIDITY).await?;
+    let pruned = sqlite_db.prune_peers(PEERS_VALIDITY).await?;
+    info!("Number of peers pruned: {}", pruned);
 
     Ok(())
 }
 
-pub async fn db_sync_handler(update_time: i64) -> Result<(), Box<dyn Error>> {
+/// Periodically sync the sqlite db from dynamoDB. The first sync fetches every peer seen within `since`.
+/// DynamoDB credentials are loaded from `aws_profile` when set.
+pub async fn db_sync_handler(
+    update_time: i64,
+    since: Option<Duration>,
+    aws_profile: Option<String>,
+) -> Result<(), Box<dyn Error>> {
     // we can unwrap because `update_time` is fixed to +5 minutes.
     let mut interval = tokio::time::interval(Duration::seconds(update_time).to_std().unwrap());
     let mut first_sync = true;
     loop {
         interval.tick().await;
-        db_sync(update_time, first_sync).await?;
+        db_sync(update_time, first_sync, since, aws_profile.as_deref()).await?;
         first_sync = false;
     }
 }
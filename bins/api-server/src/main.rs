@@ -4,7 +4,7 @@ mod peerdb;
 use axum::routing;
 use axum::Json;
 use axum::Router;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use db_sync::db_sync_handler;
 use peerdb::{rest_router, AppState};
 use std::net::SocketAddr;
@@ -30,21 +30,38 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Start crawling the network
-    StartApiServer,
+    StartApiServer(StartApiServerOpts),
+}
+
+#[derive(Args)]
+struct StartApiServerOpts {
+    #[arg(long, value_parser = parse_since)]
+    /// Only sync peers seen within this window on startup (e.g. `1h`, `7d`). Defaults to 24 hours.
+    since: Option<chrono::Duration>,
+    #[arg(long)]
+    /// Load AWS credentials from this named profile instead of the default provider chain.
+    aws_profile: Option<String>,
+}
+
+/// Parse a human readable duration such as `1h` or `7d`.
+fn parse_since(since: &str) -> Result<chrono::Duration, String> {
+    let since = humantime::parse_duration(since).map_err(|err| err.to_string())?;
+    chrono::Duration::from_std(since).map_err(|err| err.to_string())
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
     tracing_subscriber::fmt::init();
-    let start_api_server_futures = {
+    let (start_api_server_futures, db_sync_futures) = {
         match cli.command {
-            Commands::StartApiServer => start_api_server(),
+            Commands::StartApiServer(opts) => (
+                start_api_server(),
+                db_sync_handler(UPDATE_TIME, opts.since, opts.aws_profile),
+            ),
         }
     };
 
-    let db_sync_futures = { db_sync_handler(UPDATE_TIME) };
-
     let (_, _) = try_join!(start_api_server_futures, db_sync_futures)?;
 
     Ok(())
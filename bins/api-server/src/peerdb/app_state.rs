@@ -11,7 +11,7 @@ pub struct AppState {
 impl AppState {
     pub async fn new_aws() -> Self {
         Self {
-            store: Arc::new(AwsPeerDB::new().await),
+            store: Arc::new(AwsPeerDB::new(None).await),
         }
     }
 
@@ -2,10 +2,12 @@ use std::sync::Arc;
 
 use axum::{
     extract::{Path, State},
+    http::StatusCode,
     routing::get,
     Json, Router,
 };
 use reth_crawler_db::{types::ClientData, PeerDB, PeerData};
+use tracing::warn;
 
 use super::app_state::AppState;
 
@@ -15,16 +17,34 @@ pub fn rest_router() -> Router<AppState> {
         .route("/node/id/:id", get(get_node_by_id))
         .route("/node/ip/:ip", get(get_node_by_ip))
         .route("/clients", get(get_clients))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+}
+
+/// Liveness probe: the process is up and serving requests.
+async fn healthz() -> StatusCode {
+    StatusCode::OK
+}
+
+/// Readiness probe: the database answers a cheap query.
+async fn readyz(State(store): State<Arc<dyn PeerDB>>) -> StatusCode {
+    match store.count_peers().await {
+        Ok(_) => StatusCode::OK,
+        Err(err) => {
+            warn!("Readiness check failed: {}", err);
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    }
 }
 
 async fn get_nodes(State(store): State<Arc<dyn PeerDB>>) -> Json<Vec<PeerData>> {
-    Json(store.all_peers(Some(50)).await.unwrap())
+    Json(store.all_peers(Some(50), false).await.unwrap())
 }
 
 async fn get_clients(State(store): State<Arc<dyn PeerDB>>) -> Json<Vec<ClientData>> {
     Json(
         store
-            .all_peers(Some(50))
+            .all_peers(Some(50), false)
             .await
             .unwrap()
             .into_iter()
@@ -0,0 +1,107 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, Command};
+use eyre::{bail, eyre, WrapErr};
+use toml::{Table, Value};
+
+/// Append to `args` the options of the TOML file given with `--config`, if any, so that clap
+/// parses and validates them like command line flags.
+///
+/// Keys are option names, e.g. `dynamo_table` or `dynamo-table`, and apply to the command being
+/// run: keys of the other commands are ignored, so one file can serve `crawl` and `stats`, while
+/// keys of no command are rejected. Options set on the command line or through their environment
+/// variable take precedence over the file.
+pub fn args_with_config(command: Command, mut args: Vec<OsString>) -> eyre::Result<Vec<OsString>> {
+    // the file may supply options the command line misses, so this first parse is lenient, and
+    // the command line is left for the real parse to report its errors or print the help
+    let Ok(matches) = command
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches_from(&args)
+    else {
+        return Ok(args);
+    };
+    let Some(path) = matches.get_one::<PathBuf>("config") else {
+        return Ok(args);
+    };
+    let Some((name, sub_matches)) = matches.subcommand() else {
+        return Ok(args);
+    };
+    let subcommand = command
+        .find_subcommand(name)
+        .ok_or_else(|| eyre!("Unknown command {}", name))?;
+
+    let contents = fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read the config file {}", path.display()))?;
+    let table: Table = toml::from_str(&contents)
+        .wrap_err_with(|| format!("Failed to parse the config file {}", path.display()))?;
+    let other_commands: HashSet<&str> = command
+        .get_subcommands()
+        .flat_map(Command::get_arguments)
+        .map(|arg| arg.get_id().as_str())
+        .collect();
+
+    for (key, value) in &table {
+        let id = key.replace('-', "_");
+        let found = command
+            .get_arguments()
+            .map(|arg| (arg, &matches))
+            .chain(subcommand.get_arguments().map(|arg| (arg, sub_matches)))
+            .find(|(arg, _)| arg.get_id().as_str() == id && id != "config");
+        let Some((arg, arg_matches)) = found else {
+            if other_commands.contains(id.as_str()) {
+                continue;
+            }
+            bail!("Unknown option `{}` in {}", key, path.display());
+        };
+        if is_explicit(arg_matches, &id) {
+            continue;
+        }
+        let long = arg
+            .get_long()
+            .ok_or_else(|| eyre!("`{}` can't be set in a config file", key))?;
+        let option_args = option_args(long, arg.get_action(), value)
+            .wrap_err_with(|| format!("Invalid option `{}` in {}", key, path.display()))?;
+        args.extend(option_args);
+    }
+    Ok(args)
+}
+
+/// Whether the option `id` was set on the command line or through its environment variable.
+fn is_explicit(matches: &ArgMatches, id: &str) -> bool {
+    matches!(
+        matches.value_source(id),
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+    )
+}
+
+/// Command line arguments setting the option `--long` to `value`.
+fn option_args(long: &str, action: &ArgAction, value: &Value) -> eyre::Result<Vec<OsString>> {
+    let flag = format!("--{long}");
+    match (action, value) {
+        (ArgAction::SetTrue, Value::Boolean(true)) => Ok(vec![flag.into()]),
+        (ArgAction::SetTrue, Value::Boolean(false)) => Ok(Vec::new()),
+        (ArgAction::SetTrue, _) => bail!("expected true or false"),
+        (ArgAction::Append, Value::Array(values)) => values
+            .iter()
+            .map(|value| Ok(format!("{flag}={}", scalar(value)?).into()))
+            .collect(),
+        (_, Value::Array(_)) => bail!("expected a single value"),
+        (_, value) => Ok(vec![format!("{flag}={}", scalar(value)?).into()]),
+    }
+}
+
+/// `value` as written on the command line.
+fn scalar(value: &Value) -> eyre::Result<String> {
+    match value {
+        Value::String(value) => Ok(value.clone()),
+        Value::Integer(_) | Value::Float(_) | Value::Boolean(_) | Value::Datetime(_) => {
+            Ok(value.to_string())
+        }
+        Value::Array(_) | Value::Table(_) => bail!("expected a string, a number or a boolean"),
+    }
+}
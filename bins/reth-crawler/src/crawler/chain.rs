@@ -0,0 +1,37 @@
+use std::sync::Arc;
+
+use clap::ValueEnum;
+use reth_primitives::{
+    holesky_nodes, mainnet_nodes, sepolia_nodes, ChainSpec, NodeRecord, HOLESKY, MAINNET, SEPOLIA,
+};
+
+/// A network the crawler can crawl.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Chain {
+    /// Ethereum mainnet
+    Mainnet,
+    /// The Sepolia testnet
+    Sepolia,
+    /// The Holesky testnet
+    Holesky,
+}
+
+impl Chain {
+    /// Spec of the chain, whose genesis and fork id the ETH handshake advertises.
+    pub fn spec(self) -> Arc<ChainSpec> {
+        match self {
+            Self::Mainnet => MAINNET.clone(),
+            Self::Sepolia => SEPOLIA.clone(),
+            Self::Holesky => HOLESKY.clone(),
+        }
+    }
+
+    /// Canonical bootnodes of the chain, which discv4 starts from unless `--bootnodes` is set.
+    pub fn bootnodes(self) -> Vec<NodeRecord> {
+        match self {
+            Self::Mainnet => mainnet_nodes(),
+            Self::Sepolia => sepolia_nodes(),
+            Self::Holesky => holesky_nodes(),
+        }
+    }
+}
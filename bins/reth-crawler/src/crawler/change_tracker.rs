@@ -0,0 +1,45 @@
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+use lru::LruCache;
+use reth_crawler_db::types::peer_changed;
+use reth_crawler_db::PeerData;
+use tracing::info;
+
+/// Remembers the last observation of recently crawled peers and logs what changed when a peer
+/// is observed again, e.g. a client upgrade. Only peers observed since the crawler started are
+/// compared.
+pub struct ChangeTracker {
+    seen: Mutex<LruCache<String, PeerData>>,
+}
+
+impl ChangeTracker {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            seen: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    /// Whether a peer was observed recently, which doesn't count as using its entry.
+    pub fn contains(&self, id: &str) -> bool {
+        self.seen.lock().unwrap().contains(id)
+    }
+
+    /// Record an observation of a peer, logging its changes since the previous one.
+    pub fn observe(&self, peer_data: &PeerData) {
+        let previous = self
+            .seen
+            .lock()
+            .unwrap()
+            .put(peer_data.id.clone(), peer_data.clone());
+        let Some(previous) = previous else {
+            return;
+        };
+        for change in peer_changed(&previous, peer_data) {
+            info!(
+                "Peer {} changed its {} from {} to {}",
+                peer_data.id, change.field, change.old, change.new
+            );
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use tracing::info;
+
+/// Keeps or drops handshaked peers by client name, the first segment of their client version
+/// such as `geth` in `Geth/v1.13.4-stable/linux-amd64/go1.21.3`, lowercased.
+///
+/// A peer is dropped if its client name contains any of the `exclude` substrings, or if `only`
+/// isn't empty and its client name contains none of them.
+pub struct ClientFilter {
+    exclude: Vec<String>,
+    only: Vec<String>,
+    // number of dropped peers by rule
+    filtered: Mutex<BTreeMap<String, u64>>,
+}
+
+impl ClientFilter {
+    pub fn new(exclude: Vec<String>, only: Vec<String>) -> Self {
+        let lowercase = |substrings: Vec<String>| {
+            substrings
+                .into_iter()
+                .map(|substring| substring.to_lowercase())
+                .collect()
+        };
+        Self {
+            exclude: lowercase(exclude),
+            only: lowercase(only),
+            filtered: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Tell whether a peer running `client_version` must be dropped, returning the rule dropping
+    /// it and counting the peer under that rule.
+    pub fn rejects(&self, client_version: &str) -> Option<String> {
+        let name = client_version
+            .split('/')
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        let rule = match self
+            .exclude
+            .iter()
+            .find(|substring| name.contains(*substring))
+        {
+            Some(substring) => format!("--exclude-clients {}", substring),
+            None if !self.only.is_empty()
+                && !self.only.iter().any(|substring| name.contains(substring)) =>
+            {
+                "--only-clients".to_string()
+            }
+            None => return None,
+        };
+        *self
+            .filtered
+            .lock()
+            .unwrap()
+            .entry(rule.clone())
+            .or_default() += 1;
+        Some(rule)
+    }
+
+    /// Log how many peers each rule dropped.
+    pub fn log_counts(&self) {
+        for (rule, count) in self.filtered.lock().unwrap().iter() {
+            info!("Filtered out {} peers with {}", count, rule);
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::HashSet;
+use std::num::NonZeroUsize;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use lru::LruCache;
+use reth_primitives::PeerId;
+
+use crate::metrics::METRICS;
+
+/// Number of recently dialed peers remembered for the re-crawl cooldown.
+const RECENT_DIALS_CAPACITY: usize = 100_000;
+
+/// Keeps the crawler from dialing a peer that is already being dialed, or that was dialed less
+/// than `cooldown` ago.
+pub struct DialGuard {
+    cooldown: Duration,
+    state: Mutex<DialState>,
+}
+
+struct DialState {
+    in_flight: HashSet<PeerId>,
+    // when the last dial of a peer completed
+    recent: LruCache<PeerId, Instant>,
+    // when a dial last started or completed
+    last_activity: Instant,
+}
+
+impl DialGuard {
+    pub fn new(cooldown: Duration) -> Self {
+        Self {
+            cooldown,
+            state: Mutex::new(DialState {
+                in_flight: HashSet::new(),
+                recent: LruCache::new(NonZeroUsize::new(RECENT_DIALS_CAPACITY).unwrap()),
+                last_activity: Instant::now(),
+            }),
+        }
+    }
+
+    /// Reserve a dial of `peer_id`, returning `None` if it should be skipped. The peer is
+    /// released when the returned permit is dropped.
+    pub fn try_dial(self: &Arc<Self>, peer_id: PeerId) -> Option<DialPermit> {
+        let mut state = self.state.lock().unwrap();
+        if state.in_flight.contains(&peer_id) {
+            return None;
+        }
+        if let Some(dialed_at) = state.recent.peek(&peer_id) {
+            if dialed_at.elapsed() < self.cooldown {
+                return None;
+            }
+        }
+        state.in_flight.insert(peer_id);
+        state.last_activity = Instant::now();
+        METRICS.active_dials.fetch_add(1, Ordering::Relaxed);
+        Some(DialPermit {
+            guard: self.clone(),
+            peer_id,
+            cooldown: true,
+        })
+    }
+
+    /// How long no dial has started or completed, or `None` while dials are in flight.
+    pub fn idle_for(&self) -> Option<Duration> {
+        let state = self.state.lock().unwrap();
+        state
+            .in_flight
+            .is_empty()
+            .then(|| state.last_activity.elapsed())
+    }
+}
+
+/// An in-flight dial reserved with [`DialGuard::try_dial`].
+pub struct DialPermit {
+    guard: Arc<DialGuard>,
+    peer_id: PeerId,
+    cooldown: bool,
+}
+
+impl DialPermit {
+    /// Release the peer without starting its cooldown, e.g. because a retry is scheduled.
+    pub fn skip_cooldown(&mut self) {
+        self.cooldown = false;
+    }
+}
+
+impl Drop for DialPermit {
+    fn drop(&mut self) {
+        let mut state = self.guard.state.lock().unwrap();
+        state.in_flight.remove(&self.peer_id);
+        METRICS.active_dials.fetch_sub(1, Ordering::Relaxed);
+        if self.cooldown {
+            state.recent.put(self.peer_id, Instant::now());
+        }
+        state.last_activity = Instant::now();
+    }
+}
@@ -0,0 +1,69 @@
+use std::net::IpAddr;
+use std::path::Path;
+
+use eyre::WrapErr;
+use reth_crawler_db::Cidr;
+
+/// Addresses the crawler may dial, set by operators e.g. to honor the opt-outs of node operators.
+///
+/// An address is denied if it's within any of the `deny` ranges, or if there are `allow` ranges
+/// and it's within none of them.
+pub struct DialPolicy {
+    deny: Vec<Cidr>,
+    allow: Option<Vec<Cidr>>,
+}
+
+impl DialPolicy {
+    /// Read the denied and allowed ranges from the files at `deny` and `allow`, if set, with
+    /// [`load_ip_ranges`].
+    pub fn load(deny: Option<&Path>, allow: Option<&Path>) -> eyre::Result<Self> {
+        Ok(Self {
+            deny: deny.map(load_ip_ranges).transpose()?.unwrap_or_default(),
+            allow: allow.map(load_ip_ranges).transpose()?,
+        })
+    }
+
+    /// Tell whether `ip` must not be dialed, returning the option denying it.
+    pub fn rejects(&self, ip: IpAddr) -> Option<&'static str> {
+        let ip = ip.to_string();
+        if self.deny.iter().any(|cidr| cidr.contains(&ip)) {
+            Some("--deny-ips-file")
+        } else if self
+            .allow
+            .as_ref()
+            .is_some_and(|allow| !allow.iter().any(|cidr| cidr.contains(&ip)))
+        {
+            Some("--allow-ips-file")
+        } else {
+            None
+        }
+    }
+}
+
+/// Read the ranges listed in `path`, one IP address or CIDR range per line, e.g. `203.0.113.7`
+/// or `2001:db8::/32`. Blank lines and lines starting with `#` are ignored. Unlike the static
+/// peers, an invalid entry fails the whole file, since skipping it could dial a denied host.
+pub fn load_ip_ranges(path: &Path) -> eyre::Result<Vec<Cidr>> {
+    let contents = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+    contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line_number, line)| {
+            let range = match line.parse::<IpAddr>() {
+                Ok(IpAddr::V4(ip)) => format!("{ip}/32"),
+                Ok(IpAddr::V6(ip)) => format!("{ip}/128"),
+                Err(_) => line.to_string(),
+            };
+            Cidr::parse(&range).wrap_err_with(|| {
+                format!(
+                    "Invalid IP address or range on line {} of {}",
+                    line_number,
+                    path.display()
+                )
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use reth_crawler_db::PeerData;
+use tracing::{debug, error};
+
+/// Keeps a file listing the enode URLs of the live peers, one per line, e.g. for a node to use
+/// as static peers. A peer is live if it was crawled within `max_age`.
+///
+/// The file is rewritten every `interval` by writing a temporary file next to it and renaming it
+/// over the previous one, so readers never see a partially written list.
+pub struct EnodeList {
+    path: PathBuf,
+    interval: Duration,
+    max_age: Duration,
+    // enode URL and time of the last crawl, by peer id
+    live: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl EnodeList {
+    pub fn new(path: PathBuf, interval: Duration, max_age: Duration) -> Self {
+        Self {
+            path,
+            interval,
+            max_age,
+            live: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record that a peer was just crawled.
+    pub fn record(&self, peer_data: &PeerData) {
+        if peer_data.enode_url.is_empty() {
+            return;
+        }
+        self.live.lock().unwrap().insert(
+            peer_data.id.clone(),
+            (peer_data.enode_url.clone(), Instant::now()),
+        );
+    }
+
+    /// Rewrite the file every `interval`, forever.
+    pub async fn run(&self) {
+        let mut ticker = tokio::time::interval(self.interval);
+        // the first tick completes immediately, before any peer is crawled
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            match self.write().await {
+                Ok(count) => debug!("Wrote {} live enodes to {}", count, self.path.display()),
+                Err(e) => error!(
+                    "Failed to write the live enodes to {}: {}",
+                    self.path.display(),
+                    e
+                ),
+            }
+        }
+    }
+
+    /// Forget the peers that aren't live anymore and write the others, returning how many.
+    async fn write(&self) -> std::io::Result<usize> {
+        let mut enodes: Vec<String> = {
+            let mut live = self.live.lock().unwrap();
+            live.retain(|_, (_, crawled_at)| crawled_at.elapsed() <= self.max_age);
+            live.values().map(|(enode, _)| enode.clone()).collect()
+        };
+        enodes.sort_unstable();
+        let contents: String = enodes.iter().map(|enode| format!("{enode}\n")).collect();
+
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        tokio::fs::write(&tmp_path, contents).await?;
+        tokio::fs::rename(&tmp_path, &self.path).await?;
+        Ok(enodes.len())
+    }
+}
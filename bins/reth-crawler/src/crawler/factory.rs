@@ -1,20 +1,19 @@
-use once_cell::sync::Lazy;
+use reth_crawler_db::{AwsPeerDB, CompositePeerDB, InMemoryPeerDB, PeerDB, SqlPeerDB};
 use reth_discv4::{Discv4, Discv4ConfigBuilder, DEFAULT_DISCOVERY_ADDRESS};
 use reth_dns_discovery::{
     DnsDiscoveryConfig, DnsDiscoveryHandle, DnsDiscoveryService, DnsResolver,
 };
-
-use reth_network::config::rng_secret_key;
+use reth_ecies::util::pk2id;
+use reth_eth_wire::HelloMessage;
 use reth_network::{NetworkConfig, NetworkHandle, NetworkManager, PeersConfig};
-use reth_primitives::{mainnet_nodes, NodeRecord};
+use reth_primitives::NodeRecord;
 use reth_provider::test_utils::NoopProvider;
-use secp256k1::SecretKey;
+use secp256k1::{SecretKey, SECP256K1};
 use std::sync::Arc;
 use std::time::Duration;
+use tracing::info;
 
-use crate::crawler::CrawlerService;
-
-pub static MAINNET_BOOT_NODES: Lazy<Vec<NodeRecord>> = Lazy::new(mainnet_nodes);
+use crate::crawler::{Backend, CrawlerConfig, CrawlerService, DbConfig};
 
 pub struct CrawlerFactory {
     key: SecretKey,
@@ -24,15 +23,22 @@ pub struct CrawlerFactory {
 }
 
 impl CrawlerFactory {
-    pub async fn new() -> Self {
-        // Setup configs related to this 'node' by creating a new random
-        let key = rng_secret_key();
+    /// Start discovery from `bootnodes`, looking up new nodes every `lookup_interval`, and the
+    /// network with the node key `key`, whose `Hello` to inbound peers advertises `crawler_id` as
+    /// client version.
+    pub async fn new(
+        key: SecretKey,
+        crawler_id: &str,
+        bootnodes: Vec<NodeRecord>,
+        lookup_interval: Duration,
+    ) -> Self {
         let enr = NodeRecord::from_secret_key(DEFAULT_DISCOVERY_ADDRESS, &key);
+        info!("Crawling with node id {:x}", enr.id);
         // Setup discovery v4 protocol to find peers to talk to
         let mut discv4_cfg = Discv4ConfigBuilder::default();
         discv4_cfg
-            .add_boot_nodes(MAINNET_BOOT_NODES.clone())
-            .lookup_interval(Duration::from_secs(3));
+            .add_boot_nodes(bootnodes)
+            .lookup_interval(lookup_interval);
 
         let peer_config = PeersConfig::default()
             .with_max_outbound(0)
@@ -40,9 +46,13 @@ impl CrawlerFactory {
 
         // disable discovery here since we already handle outbound connections (devp2p/eth handshakes in our case) for newly discovered peers "manually", and do not need Swarm/NetworkState to handle those outbound handshakes for us
         // we do however want inbound TCP (note: discv4 listens only for udp disc proto messages) connections to be handled
+        let hello = HelloMessage::builder(pk2id(&key.public_key(SECP256K1)))
+            .client_version(crawler_id)
+            .build();
         let builder = NetworkConfig::<()>::builder(key)
             .disable_discovery()
-            .peer_config(peer_config);
+            .peer_config(peer_config)
+            .hello_message(hello);
 
         let net_conf = builder.build(Arc::from(NoopProvider::default()));
         let network = NetworkManager::new(net_conf).await.unwrap();
@@ -68,14 +78,56 @@ impl CrawlerFactory {
         }
     }
 
-    pub async fn make(&self, local_db: bool) -> CrawlerService {
-        CrawlerService::new(
+    /// Build a crawler. The db, and for DynamoDB its client, is created once here and shared by
+    /// every task the crawler spawns. Fails if the DynamoDB table doesn't exist.
+    pub async fn make(&self, config: CrawlerConfig) -> eyre::Result<CrawlerService> {
+        let mut backends = Vec::with_capacity(config.backends.len());
+        for backend in &config.backends {
+            backends.push(Self::open_backend(*backend, &config.db, true).await?);
+        }
+        let db: Arc<dyn PeerDB> = if backends.len() == 1 {
+            backends.remove(0)
+        } else {
+            Arc::new(CompositePeerDB::new(backends))
+        };
+        Ok(CrawlerService::new(
             self.discv4.clone(),
             self.dnsdisc.clone(),
             self.network.clone(),
             self.key,
-            local_db,
+            db,
+            config,
         )
-        .await
+        .await)
+    }
+
+    /// Open the db of `backend`, failing if it's DynamoDB and the table doesn't exist. With
+    /// `record_schema`, the DynamoDB table also records that this version of the crate writes it.
+    pub async fn open_backend(
+        backend: Backend,
+        config: &DbConfig,
+        record_schema: bool,
+    ) -> eyre::Result<Arc<dyn PeerDB>> {
+        Ok(match backend {
+            Backend::Sqlite => {
+                Arc::new(SqlPeerDB::open_with_pool(&config.db_path, config.sqlite_pool_size).await)
+            }
+            Backend::Dynamodb => {
+                let db = AwsPeerDB::new_with_regions(
+                    config.aws_profile.as_deref(),
+                    &config.dynamo_regions,
+                    config.dynamo_endpoint.as_deref(),
+                )
+                .await
+                .with_table(&config.dynamo_table)
+                .with_consistent_reads(config.consistent_reads);
+                db.check_table().await?;
+                if record_schema {
+                    db.write_schema_info().await?;
+                }
+                Arc::new(db)
+            }
+            Backend::Memory => Arc::new(InMemoryPeerDB::new()),
+        })
     }
 }
@@ -0,0 +1,45 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Whether `ip` can be reached on the public internet, i.e. it isn't in a private, loopback,
+/// link-local or otherwise reserved (bogon) range.
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ipv4(ip),
+            None => is_public_ipv6(ip),
+        },
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // "this network" 0.0.0.0/8
+        || a == 0
+        // shared address space 100.64.0.0/10
+        || (a == 100 && (b & 0b1100_0000) == 64)
+        // benchmarking 198.18.0.0/15
+        || (a == 198 && (b & 0b1111_1110) == 18)
+        // reserved 240.0.0.0/4
+        || a >= 240)
+}
+
+fn is_public_ipv6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // unique local fc00::/7
+        || (first & 0xfe00) == 0xfc00
+        // link-local fe80::/10
+        || (first & 0xffc0) == 0xfe80
+        // documentation 2001:db8::/32
+        || (first == 0x2001 && ip.segments()[1] == 0x0db8))
+}
@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+use eyre::WrapErr;
+use rdkafka::config::ClientConfig;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use reth_crawler_db::PeerData;
+use tracing::error;
+
+/// How long a message may wait in the producer queue, e.g. while the brokers are unreachable.
+const MESSAGE_TIMEOUT_MS: &str = "5000";
+
+/// Publishes crawled peers as JSON messages to a Kafka topic, keyed by peer id, so that
+/// downstream consumers can react to peers as soon as they are crawled. It's write only: peers
+/// are still stored in and queried from the db.
+pub struct KafkaSink {
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl KafkaSink {
+    /// Connect to the comma separated `brokers`.
+    pub fn new(brokers: &str, topic: String) -> eyre::Result<Self> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("message.timeout.ms", MESSAGE_TIMEOUT_MS)
+            .create()
+            .wrap_err_with(|| format!("Failed to create a Kafka producer for {brokers}"))?;
+        Ok(Self { producer, topic })
+    }
+
+    /// Publish a peer, logging failures rather than returning them so that they don't stop the
+    /// peer from being written to the db.
+    pub async fn publish(&self, peer_data: &PeerData) {
+        let payload = match serde_json::to_string(peer_data) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Failed to serialize peer {}: {}", peer_data.id, e);
+                return;
+            }
+        };
+        let record = FutureRecord::to(&self.topic)
+            .key(&peer_data.id)
+            .payload(&payload);
+        if let Err((e, _)) = self.producer.send(record, Duration::from_secs(0)).await {
+            error!(
+                "Failed to publish peer {} to Kafka topic {}: {}",
+                peer_data.id, self.topic, e
+            );
+        }
+    }
+}
@@ -1,29 +1,81 @@
 use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::Ordering;
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
-use crate::p2p::{handshake_eth, handshake_p2p};
-use chrono::{Days, Utc};
+use crate::crawler::{
+    is_public_ip, ClientFilter, CrawlerConfig, DialGuard, DialPolicy, EnodeList, LocationFilter,
+    PeerCounter, PeerSampler, PeerWriter, RetryQueue, RoleDetector, SubnetLimiter,
+};
+use crate::geo::{GeoInfo, GeoLocator};
+use crate::metrics::METRICS;
+use crate::p2p::{
+    best_block_number, encode_handshake, handshake_eth, handshake_p2p, HandshakeError,
+};
+use chrono::Utc;
 use futures::StreamExt;
-use ipgeolocate::{Locator, Service};
-use reth_crawler_db::{save_peer, AwsPeerDB, PeerDB, PeerData, SqlPeerDB};
+use reth_crawler_db::types::same_hash;
+use reth_crawler_db::{PeerDB, PeerData};
 use reth_discv4::{DiscoveryUpdate, Discv4};
 use reth_dns_discovery::{DnsDiscoveryHandle, DnsNodeRecordUpdate};
+use reth_eth_wire::{DisconnectReason, HelloMessage};
 use reth_network::{NetworkEvent, NetworkHandle};
-use reth_primitives::{NodeRecord, PeerId};
+use reth_primitives::{ChainSpec, NodeRecord, PeerId};
 use secp256k1::SecretKey;
 use tokio::sync::mpsc::UnboundedSender;
 use tracing::{debug, info};
 
+/// Log a discovery stage event, at `info` level with `--trace-discovery` and at `debug` otherwise.
+macro_rules! discovery_event {
+    ($trace:expr, $($arg:tt)+) => {
+        if $trace {
+            info!($($arg)+)
+        } else {
+            debug!($($arg)+)
+        }
+    };
+}
+
 pub struct UpdateListener {
     discv4: Discv4,
     dnsdisc: DnsDiscoveryHandle,
     network: NetworkHandle,
     key: SecretKey,
-    db: Arc<dyn PeerDB>,
+    writer: Arc<PeerWriter>,
+    enode_list: Option<Arc<EnodeList>>,
+    geo: Option<Arc<dyn GeoLocator>>,
     p2p_failures: Arc<RwLock<HashMap<PeerId, u64>>>,
+    dials: Arc<DialGuard>,
+    crawled: Arc<PeerCounter>,
+    subnet_limiter: Arc<SubnetLimiter>,
+    retries: Arc<RetryQueue>,
+    skip_private_ips: bool,
+    dial_policy: DialPolicy,
+    sampler: PeerSampler,
+    socks5: Option<Arc<str>>,
+    crawler_id: Arc<str>,
+    session_id: Arc<str>,
+    chain_spec: Arc<ChainSpec>,
+    trace_discovery: bool,
+    expected_genesis: Option<Arc<str>>,
+    client_filter: Arc<ClientFilter>,
+    location_filter: Arc<LocationFilter>,
+    store_raw_handshake: bool,
+    record_disconnects: bool,
+    roles: Arc<RoleDetector>,
+    static_peers: Vec<NodeRecord>,
+    static_peers_interval: Duration,
+    recrawl_chain_status_only: bool,
 }
 
 const P2P_FAILURE_THRESHOLD: u8 = 5;
+/// `discovery_source` of the known peers dialed again by [`UpdateListener::recrawl`].
+const RECRAWL_SOURCE: &str = "recrawl";
+/// `discovery_source` of the peers dialed by [`UpdateListener::start_static_peers`].
+const STATIC_SOURCE: &str = "static";
+/// How often [`UpdateListener::wait_idle`] checks for dial activity.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
 
 impl UpdateListener {
     pub async fn new(
@@ -32,190 +84,363 @@ impl UpdateListener {
         network: NetworkHandle,
         key: SecretKey,
         node_tx: UnboundedSender<Vec<NodeRecord>>,
-        local_db: bool,
+        db: Arc<dyn PeerDB>,
+        config: CrawlerConfig,
     ) -> Self {
         let p2p_failures = Arc::from(RwLock::from(HashMap::new()));
+        let enode_list = config.enode_list.map(Arc::new);
 
-        if local_db {
-            UpdateListener {
-                discv4,
-                dnsdisc,
-                key,
-                db: Arc::new(SqlPeerDB::new().await),
-                network,
-                p2p_failures,
-            }
-        } else {
-            UpdateListener {
-                discv4,
-                dnsdisc,
-                key,
-                db: Arc::new(AwsPeerDB::new().await),
-                network,
-                p2p_failures,
+        UpdateListener {
+            discv4,
+            dnsdisc,
+            key,
+            writer: Arc::new(PeerWriter::new(
+                db,
+                config.kafka,
+                enode_list.clone(),
+                config.stdout_json,
+                config.flush_size,
+                config.write_buffer,
+                config.db_writer_tasks,
+                config.ttl_days,
+            )),
+            enode_list,
+            network,
+            geo: config.geo,
+            p2p_failures,
+            dials: Arc::new(DialGuard::new(config.recrawl_cooldown)),
+            crawled: Arc::new(PeerCounter::new(config.max_peers)),
+            subnet_limiter: Arc::new(config.subnet_limiter),
+            retries: Arc::new(config.retry_queue),
+            skip_private_ips: config.skip_private_ips,
+            dial_policy: config.dial_policy,
+            sampler: config.sampler,
+            socks5: config.socks5.map(Arc::from),
+            crawler_id: Arc::from(config.crawler_id),
+            session_id: Arc::from(config.session_id),
+            chain_spec: config.chain.spec(),
+            trace_discovery: config.trace_discovery,
+            expected_genesis: config.expected_genesis.map(Arc::from),
+            client_filter: Arc::new(config.client_filter),
+            location_filter: Arc::new(config.location_filter),
+            store_raw_handshake: config.store_raw_handshake,
+            record_disconnects: config.record_disconnects,
+            roles: Arc::new(config.role_detector),
+            static_peers: config.static_peers,
+            static_peers_interval: config.static_peers_interval,
+            recrawl_chain_status_only: config.recrawl_chain_status_only,
+        }
+    }
+
+    pub fn writer(&self) -> &PeerWriter {
+        &self.writer
+    }
+
+    pub fn client_filter(&self) -> &ClientFilter {
+        &self.client_filter
+    }
+
+    pub fn location_filter(&self) -> &LocationFilter {
+        &self.location_filter
+    }
+
+    /// Wait until `max_peers` distinct peers have been handshaked.
+    pub async fn wait_max_peers(&self) {
+        self.crawled.wait_reached().await
+    }
+
+    /// Wait until no peer has been dialed for `timeout`, meaning discovery has run dry.
+    pub async fn wait_idle(&self, timeout: Duration) {
+        let mut ticker = tokio::time::interval(IDLE_CHECK_INTERVAL);
+        loop {
+            ticker.tick().await;
+            if self.dials.idle_for().is_some_and(|idle| idle >= timeout) {
+                return;
             }
         }
     }
 
     pub async fn start_discv4(&self) -> eyre::Result<()> {
         let mut discv4_stream = self.discv4.update_stream().await?;
-        let key = self.key;
         while let Some(update) = discv4_stream.next().await {
-            let db = self.db.clone();
-            let captured_discv4 = self.discv4.clone();
-            let p2p_failures = self.p2p_failures.clone();
             if let DiscoveryUpdate::Added(peer) | DiscoveryUpdate::DiscoveredAtCapacity(peer) =
                 update
             {
-                tokio::spawn(async move {
-                    // kick a forced lookup
-                    captured_discv4.send_lookup(peer.id);
-                    let mut p2p_failure_count: u64;
-                    {
-                        let rlock = p2p_failures.read().unwrap();
-                        p2p_failure_count = *rlock.get(&peer.id).unwrap_or(&0);
-                    }
-                    let (p2p_stream, their_hello) = match handshake_p2p(peer, key).await {
-                        Ok(s) => s,
-                        Err(e) => {
-                            info!("Failed P2P handshake with peer {}, {}", peer.address, e);
-                            if e.to_string().contains("Too many peers") {
-                                debug!("Skip counting p2p_failure for peer: {}", peer.address);
-                                return;
-                            }
-                            p2p_failure_count = p2p_failure_count + 1;
-                            if p2p_failure_count >= P2P_FAILURE_THRESHOLD as u64 {
-                                // ban this peer - TODO: we probably want Discv4Service::ban_until() semantics here, but that isn't exposed to us
-                                // for now - permaban
-                                debug!(
-                                    "PeerId {} has failed p2p handshake {} times, banning",
-                                    peer.id, p2p_failure_count
-                                );
-                                captured_discv4.ban_ip(peer.address);
-                                // scope guard to drop wlock
-                                {
-                                    // reset count to 0 since we've now banned
-                                    let mut wlock = p2p_failures.write().unwrap();
-                                    wlock.insert(peer.id, 0);
-                                }
-                                return;
-                            }
-                            // scope guard to drop wlock
-                            {
-                                // increment failure count
-                                let mut wlock = p2p_failures.write().unwrap();
-                                wlock.insert(peer.id, p2p_failure_count);
-                            }
-                            return;
-                        }
-                    };
+                self.spawn_crawl(peer, "discv4", 0);
+            }
+        }
+        Ok(())
+    }
 
-                    let (_, their_status) = match handshake_eth(p2p_stream).await {
-                        Ok(s) => s,
-                        Err(e) => {
-                            info!("Failed ETH handshake with peer {}, {}", peer.address, e);
-                            // ban the peer permanently - we never want to process another disc packet for this again since we know its not on the same network
-                            captured_discv4.ban_ip(peer.address);
-                            return;
-                        }
-                    };
-                    if their_hello.client_version.is_empty() {
-                        info!(
-                            "Peer {} with empty client_version - returning",
-                            peer.address
-                        );
-                        // ban their IP - since our results show that we have multiple PeerIDs with the same IPs and no ClientVersion
-                        captured_discv4.ban_ip(peer.address);
-                        return;
-                    }
+    pub async fn start_dnsdisc(&self) -> eyre::Result<()> {
+        let mut dnsdisc_update_stream = self.dnsdisc.node_record_stream().await?;
+        while let Some(update) = dnsdisc_update_stream.next().await {
+            let DnsNodeRecordUpdate {
+                node_record: peer, ..
+            } = update;
+            self.spawn_crawl(peer, "dns", 0);
+        }
+        Ok(())
+    }
 
-                    let ttl = Utc::now()
-                        .checked_add_days(Days::new(1))
-                        .unwrap()
-                        .timestamp();
-                    let last_seen = Utc::now().to_string();
+    /// Keep the enode list file up to date, forever. Never returns if there is no enode list.
+    pub async fn start_enode_list(&self) {
+        match &self.enode_list {
+            Some(enode_list) => enode_list.run().await,
+            None => futures::future::pending().await,
+        }
+    }
 
-                    info!(
-                        "Successfully connected to a peer at {}:{} ({}) using eth-wire version eth/{:#?}",
-                        peer.address, peer.tcp_port, their_hello.client_version, their_hello.protocol_version
-                    );
+    /// Dial again the peers whose handshake failed transiently.
+    pub async fn start_retries(&self) {
+        loop {
+            let retry = self.retries.next().await;
+            debug!(
+                "Retrying handshake with peer {} (retry {})",
+                retry.peer.address, retry.attempt
+            );
+            self.spawn_crawl(retry.peer, retry.discovery_source, retry.attempt);
+        }
+    }
+
+    /// Dial the static peers every `static_peers_interval`, forever. Never returns if there are no
+    /// static peers.
+    pub async fn start_static_peers(&self) {
+        if self.static_peers.is_empty() {
+            return futures::future::pending().await;
+        }
+        let mut ticker = tokio::time::interval(self.static_peers_interval);
+        loop {
+            ticker.tick().await;
+            debug!("Dialing {} static peers", self.static_peers.len());
+            for peer in &self.static_peers {
+                self.spawn_crawl(*peer, STATIC_SOURCE, 0);
+            }
+        }
+    }
 
-                    // get peer location
-                    let service = Service::IpApi;
-                    let ip_addr = peer.address.to_string();
+    /// Dial again known peers, to refresh their record. Peers whose enode URL can't be parsed are
+    /// skipped.
+    pub fn recrawl(&self, peers: Vec<PeerData>) {
+        for peer_data in peers {
+            match peer_data.enode_url.parse::<NodeRecord>() {
+                Ok(peer) => self.spawn_crawl(peer, RECRAWL_SOURCE, 0),
+                Err(e) => debug!(
+                    "Skipping re-crawl of peer {} with enode {:?}, {}",
+                    peer_data.id, peer_data.enode_url, e
+                ),
+            }
+        }
+    }
 
-                    let mut country = String::default();
-                    let mut city = String::default();
+    /// Whether a peer at `ip` must be dropped because its address isn't publicly routable.
+    fn skip_ip(&self, ip: IpAddr) -> bool {
+        if !self.skip_private_ips || is_public_ip(ip) {
+            return false;
+        }
+        let skipped = METRICS
+            .skipped_private_peers
+            .fetch_add(1, Ordering::Relaxed)
+            + 1;
+        discovery_event!(
+            self.trace_discovery,
+            "Dropped peer {}: non public address ({} skipped so far)",
+            ip,
+            skipped
+        );
+        true
+    }
 
-                    match Locator::get(&ip_addr, service).await {
-                        Ok(loc) => {
-                            country = loc.country;
-                            city = loc.city;
-                        }
-                        Err(_) => {
-                            // leave `country` and `city` empty if not able to get them
-                        }
-                    }
+    /// Whether a peer at `ip` must not be dialed because the operator's IP lists deny it.
+    fn denied(&self, ip: IpAddr) -> bool {
+        let Some(rule) = self.dial_policy.rejects(ip) else {
+            return false;
+        };
+        METRICS.denied_dials.fetch_add(1, Ordering::Relaxed);
+        discovery_event!(
+            self.trace_discovery,
+            "Dropped peer {}: dial denied by {}",
+            ip,
+            rule
+        );
+        true
+    }
 
-                    let capabilities: Vec<String> = their_hello
-                        .capabilities
-                        .iter()
-                        .map(|cap| cap.to_string())
-                        .collect();
-
-                    let chain = their_status.chain.to_string();
-
-                    let total_difficulty = their_status.total_difficulty.to_string();
-                    let best_block = their_status.blockhash.to_string();
-                    let genesis_block_hash = their_status.genesis.to_string();
-
-                    // collect data into `PeerData`
-                    let peer_data = PeerData {
-                        enode_url: peer.to_string(),
-                        id: peer.id.to_string(),
-                        address: ip_addr,
-                        tcp_port: peer.tcp_port,
-                        client_version: their_hello.client_version.clone(),
-                        eth_version: their_status.version,
-                        capabilities,
-                        total_difficulty,
-                        chain,
-                        best_block,
-                        genesis_block_hash,
-                        last_seen,
-                        country,
-                        city,
-                    };
-                    save_peer(peer_data, db, ttl).await;
-                });
+    /// Whether a discovered peer must be dropped because it isn't part of the `--sample-rate`
+    /// sample.
+    fn sampled_out(&self, peer: PeerId, ip: IpAddr) -> bool {
+        if self.sampler.keeps(peer) {
+            return false;
+        }
+        METRICS.sampled_out_peers.fetch_add(1, Ordering::Relaxed);
+        discovery_event!(self.trace_discovery, "Dropped peer {}: not sampled", ip);
+        true
+    }
+
+    /// Whether a peer with the genesis hash `genesis` is on the expected network, counting it if
+    /// not. Every peer is when no genesis is expected.
+    fn expected_genesis(expected: Option<&str>, genesis: &str, trace: bool, peer: IpAddr) -> bool {
+        match expected {
+            Some(expected) if !same_hash(expected, genesis) => {
+                let mismatches = METRICS.genesis_mismatches.fetch_add(1, Ordering::Relaxed) + 1;
+                discovery_event!(
+                    trace,
+                    "Dropped peer {}: genesis {} isn't the expected one ({} mismatches so far)",
+                    peer,
+                    genesis,
+                    mismatches
+                );
+                false
             }
+            _ => true,
         }
-        Ok(())
     }
 
-    pub async fn start_dnsdisc(&self) -> eyre::Result<()> {
-        let mut dnsdisc_update_stream = self.dnsdisc.node_record_stream().await?;
+    /// Partial record of a peer that accepted the connection but disconnected with `reason`
+    /// during the handshake, with the client version and capabilities of its `hello` if it got
+    /// that far. `None` if the peer's record is malformed.
+    fn partial_peer(
+        peer: NodeRecord,
+        reason: DisconnectReason,
+        hello: Option<&HelloMessage>,
+        discovery_source: &str,
+        session_id: &str,
+    ) -> Option<PeerData> {
+        let (client_version, capabilities) = match hello {
+            Some(hello) => (
+                hello.client_version.clone(),
+                hello
+                    .capabilities
+                    .iter()
+                    .map(|cap| cap.to_string())
+                    .collect(),
+            ),
+            None => (String::new(), vec![]),
+        };
+        match PeerData::builder()
+            .enode_url(peer.to_string())
+            .id(format!("{:x}", peer.id))
+            .address(peer.address.to_string())
+            .tcp_port(peer.tcp_port)
+            .udp_port(Some(peer.udp_port))
+            .client_version(client_version)
+            .capabilities(capabilities)
+            .last_seen(Utc::now().to_string())
+            .discovery_source(discovery_source)
+            .last_disconnect_reason(Some(reason.to_string()))
+            .session_id(session_id)
+            .build()
+        {
+            Ok(peer_data) => Some(peer_data),
+            Err(e) => {
+                info!("Dropping malformed peer {}, {}", peer.address, e);
+                None
+            }
+        }
+    }
+
+    /// Handshake with a discovered peer in the background and buffer its data, unless the peer
+    /// is already being dialed or was dialed less than a cooldown ago. `discovery_source` tells
+    /// how the peer was found, and `attempt` how many dials of it already failed transiently.
+    fn spawn_crawl(&self, peer: NodeRecord, discovery_source: &'static str, attempt: u32) {
+        let trace = self.trace_discovery;
+        discovery_event!(trace, "Discovered peer {} via {}", peer, discovery_source);
+        METRICS.discovered_peers.fetch_add(1, Ordering::Relaxed);
+        if self.crawled.is_reached() {
+            discovery_event!(trace, "Dropped peer {}: max peers reached", peer.address);
+            return;
+        }
+        if self.skip_ip(peer.address) || self.denied(peer.address) {
+            return;
+        }
+        // static and known peers are dialed on purpose, not discovered
+        if discovery_source != STATIC_SOURCE
+            && discovery_source != RECRAWL_SOURCE
+            && self.sampled_out(peer.id, peer.address)
+        {
+            return;
+        }
+        let Some(permit) = self.dials.try_dial(peer.id) else {
+            METRICS.suppressed_dials.fetch_add(1, Ordering::Relaxed);
+            discovery_event!(
+                trace,
+                "Dropped peer {}: duplicate, dialed recently",
+                peer.address
+            );
+            return;
+        };
         let key = self.key;
-        while let Some(update) = dnsdisc_update_stream.next().await {
-            let db = self.db.clone();
-            let p2p_failures = self.p2p_failures.clone();
-            let captured_discv4 = self.discv4.clone();
-            let DnsNodeRecordUpdate {
-                node_record: peer, ..
-            } = update;
-            tokio::spawn(async move {
-                // kick a forced lookup
-                captured_discv4.send_lookup(peer.id);
-                let mut p2p_failure_count: u64;
-                {
-                    let rlock = p2p_failures.read().unwrap();
-                    p2p_failure_count = *rlock.get(&peer.id).unwrap_or(&0);
-                }
-                let (p2p_stream, their_hello) = match handshake_p2p(peer, key).await {
+        let writer = self.writer.clone();
+        let geo = self.geo.clone();
+        let captured_discv4 = self.discv4.clone();
+        let p2p_failures = self.p2p_failures.clone();
+        let crawled = self.crawled.clone();
+        let subnet_limiter = self.subnet_limiter.clone();
+        let retries = self.retries.clone();
+        let socks5 = self.socks5.clone();
+        let crawler_id = self.crawler_id.clone();
+        let session_id = self.session_id.clone();
+        let chain_spec = self.chain_spec.clone();
+        let expected_genesis = self.expected_genesis.clone();
+        let client_filter = self.client_filter.clone();
+        let location_filter = self.location_filter.clone();
+        let store_raw_handshake = self.store_raw_handshake;
+        let record_disconnects = self.record_disconnects;
+        let roles = self.roles.clone();
+        let chain_status_only =
+            discovery_source == RECRAWL_SOURCE && self.recrawl_chain_status_only;
+        tokio::spawn(async move {
+            // the peer can be dialed again once this attempt is over
+            let mut permit = permit;
+            let delay = subnet_limiter.reserve(peer.address);
+            if !delay.is_zero() {
+                debug!(
+                    "Throttling dials to subnet {}, dialing peer {} in {:?}",
+                    subnet_limiter.subnet(peer.address),
+                    peer.address,
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+            }
+            // kick a forced lookup
+            captured_discv4.send_lookup(peer.id);
+            let mut p2p_failure_count: u64;
+            {
+                let rlock = p2p_failures.read().unwrap();
+                p2p_failure_count = *rlock.get(&peer.id).unwrap_or(&0);
+            }
+            let started = Instant::now();
+            let (p2p_stream, their_hello, tcp_connect) =
+                match handshake_p2p(peer, key, socks5.as_deref(), &crawler_id).await {
                     Ok(s) => s,
                     Err(e) => {
                         info!("Failed P2P handshake with peer {}, {}", peer.address, e);
-                        if e.to_string().contains("Too many peers") {
+                        e.counter().fetch_add(1, Ordering::Relaxed);
+                        if let (true, HandshakeError::Disconnected(reason)) =
+                            (record_disconnects, &e)
+                        {
+                            if let Some(peer_data) = Self::partial_peer(
+                                peer,
+                                *reason,
+                                None,
+                                discovery_source,
+                                &session_id,
+                            ) {
+                                writer.record_partial(peer_data).await;
+                            }
+                        }
+                        if discovery_source == RECRAWL_SOURCE {
+                            METRICS.recrawl_failures.fetch_add(1, Ordering::Relaxed);
+                        }
+                        if e.is_transient() && retries.push(peer, discovery_source, attempt + 1) {
+                            METRICS.handshake_retries.fetch_add(1, Ordering::Relaxed);
+                            permit.skip_cooldown();
+                            return;
+                        }
+                        if matches!(
+                            e,
+                            HandshakeError::Disconnected(DisconnectReason::TooManyPeers)
+                        ) {
                             debug!("Skip counting p2p_failure for peer: {}", peer.address);
                             return;
                         }
@@ -246,84 +471,176 @@ impl UpdateListener {
                     }
                 };
 
-                let (_eth_stream, their_status) = match handshake_eth(p2p_stream).await {
-                    Ok(s) => s,
-                    Err(e) => {
-                        info!("Failed ETH handshake with peer {}, {}", peer.address, e);
-                        // ban the peer permanently - we never want to process another disc packet for this again since we know its not on the same network
-                        captured_discv4.ban_ip(peer.address);
+            let (mut eth_stream, their_status) = match handshake_eth(p2p_stream, &chain_spec).await
+            {
+                Ok(s) => s,
+                Err(e) => {
+                    info!("Failed ETH handshake with peer {}, {}", peer.address, e);
+                    e.counter().fetch_add(1, Ordering::Relaxed);
+                    if let (true, HandshakeError::Disconnected(reason)) = (record_disconnects, &e) {
+                        if let Some(peer_data) = Self::partial_peer(
+                            peer,
+                            *reason,
+                            Some(&their_hello),
+                            discovery_source,
+                            &session_id,
+                        ) {
+                            writer.record_partial(peer_data).await;
+                        }
+                    }
+                    if discovery_source == RECRAWL_SOURCE {
+                        METRICS.recrawl_failures.fetch_add(1, Ordering::Relaxed);
+                    }
+                    if e.is_transient() {
+                        // the connection dropped midway, which says nothing of the peer's network
+                        if retries.push(peer, discovery_source, attempt + 1) {
+                            METRICS.handshake_retries.fetch_add(1, Ordering::Relaxed);
+                            permit.skip_cooldown();
+                        }
                         return;
                     }
-                };
-                if their_hello.client_version.is_empty() {
-                    debug!(
-                        "Peer {} with empty client_version - returning",
-                        peer.address
-                    );
-                    // ban their IP - since our results show that we have multiple PeerIDs with the same IP and no ClientVersion
+                    // ban the peer permanently - we never want to process another disc packet for this again since we know its not on the same network
                     captured_discv4.ban_ip(peer.address);
                     return;
                 }
-                let ttl = Utc::now()
-                    .checked_add_days(Days::new(1))
-                    .unwrap()
-                    .timestamp();
-                let last_seen = Utc::now().to_string();
-
-                info!(
-                        "Successfully connected to a peer at {}:{} ({}) using eth-wire version eth/{:#?}",
-                        peer.address, peer.tcp_port, their_hello.client_version, their_hello.protocol_version
-                    );
-                // get peer location
-                let service = Service::IpApi;
-                let ip_addr = peer.address.to_string();
+            };
+            let handshake = started.elapsed().saturating_sub(tcp_connect);
+            if their_hello.client_version.is_empty() {
+                discovery_event!(trace, "Dropped peer {}: empty client version", peer.address);
+                // ban their IP - since our results show that we have multiple PeerIDs with the same IPs and no ClientVersion
+                captured_discv4.ban_ip(peer.address);
+                return;
+            }
+            if let Some(rule) = client_filter.rejects(&their_hello.client_version) {
+                discovery_event!(
+                    trace,
+                    "Dropped peer {}: client {} filtered out by {}",
+                    peer.address,
+                    their_hello.client_version,
+                    rule
+                );
+                return;
+            }
 
-                let mut country = String::default();
-                let mut city = String::default();
+            let genesis_block_hash = format!("{:#x}", their_status.genesis);
+            if !Self::expected_genesis(
+                expected_genesis.as_deref(),
+                &genesis_block_hash,
+                trace,
+                peer.address,
+            ) {
+                return;
+            }
 
-                match Locator::get(&ip_addr, service).await {
-                    Ok(loc) => {
-                        country = loc.country;
-                        city = loc.city;
-                    }
-                    Err(_) => {
-                        // leave `country` and `city` empty if not able to get them
-                    }
-                }
+            let last_seen = Utc::now().to_string();
 
-                let capabilities: Vec<String> = their_hello
-                    .capabilities
-                    .iter()
-                    .map(|cap| cap.to_string())
-                    .collect();
-
-                let chain = their_status.chain.to_string();
-
-                let total_difficulty = their_status.total_difficulty.to_string();
-                let best_block = their_status.blockhash.to_string();
-                let genesis_block_hash = their_status.genesis.to_string();
-
-                // collect data into `PeerData`
-                let peer_data = PeerData {
-                    enode_url: peer.to_string(),
-                    id: peer.id.to_string(),
-                    address: ip_addr,
-                    tcp_port: peer.tcp_port,
-                    client_version: their_hello.client_version.clone(),
-                    eth_version: their_status.version,
-                    capabilities,
-                    total_difficulty,
-                    chain,
-                    best_block,
-                    genesis_block_hash,
-                    last_seen,
-                    country,
-                    city,
-                };
-                save_peer(peer_data, db, ttl).await;
-            });
-        }
-        Ok(())
+            info!(
+                "Successfully connected to a peer at {}:{} ({}) using eth-wire version eth/{:#?}",
+                peer.address,
+                peer.tcp_port,
+                their_hello.client_version,
+                their_hello.protocol_version
+            );
+
+            let best_block_number = best_block_number(&mut eth_stream, &their_status).await;
+            if chain_status_only
+                && writer
+                    .update_chain_status(
+                        format!("{:x}", peer.id),
+                        their_status.blockhash.to_string(),
+                        best_block_number,
+                        their_status.total_difficulty.to_string(),
+                        last_seen.clone(),
+                    )
+                    .await
+            {
+                METRICS.crawled_peers.fetch_add(1, Ordering::Relaxed);
+                crawled.record(peer.id);
+                return;
+            }
+            // otherwise the peer was deleted since it was read, so it's recorded in full
+
+            // get peer location, leaving `country` and `city` empty if not able to get them
+            let ip_addr = peer.address.to_string();
+            let location = match &geo {
+                Some(geo) => geo.locate(peer.address).await.unwrap_or_default(),
+                None => GeoInfo::default(),
+            };
+            if let Some(rule) = location_filter.rejects(&location) {
+                discovery_event!(
+                    trace,
+                    "Dropped peer {}: location {:?} filtered out by {}",
+                    peer.address,
+                    location.country,
+                    rule
+                );
+                return;
+            }
+            let GeoInfo {
+                country,
+                city,
+                asn,
+                org,
+                ..
+            } = location;
+
+            let capabilities: Vec<String> = their_hello
+                .capabilities
+                .iter()
+                .map(|cap| cap.to_string())
+                .collect();
+
+            let chain = their_status.chain.to_string();
+
+            let total_difficulty = their_status.total_difficulty.to_string();
+            let best_block = their_status.blockhash.to_string();
+            let raw_handshake =
+                store_raw_handshake.then(|| encode_handshake(&their_hello, &their_status));
+            let node_role = roles.classify(
+                peer.address,
+                peer.id,
+                &their_hello.client_version,
+                &capabilities,
+            );
+
+            // collect data into `PeerData`
+            let peer_data = match PeerData::builder()
+                .enode_url(peer.to_string())
+                .id(format!("{:x}", peer.id))
+                .address(ip_addr)
+                .tcp_port(peer.tcp_port)
+                .udp_port(Some(peer.udp_port))
+                .client_version(their_hello.client_version.clone())
+                .eth_version(their_status.version)
+                .capabilities(capabilities)
+                .total_difficulty(total_difficulty)
+                .chain(chain)
+                .best_block(best_block)
+                .best_block_number(best_block_number)
+                .genesis_block_hash(genesis_block_hash)
+                .last_seen(last_seen)
+                .country(country)
+                .city(city)
+                .asn(asn)
+                .org(org)
+                .discovery_source(discovery_source)
+                .raw_handshake(raw_handshake)
+                .node_role(node_role)
+                .tcp_connect_ms(Some(tcp_connect.as_millis() as u64))
+                .handshake_ms(Some(handshake.as_millis() as u64))
+                .session_id(&*session_id)
+                .build()
+            {
+                Ok(peer_data) => peer_data,
+                Err(e) => {
+                    info!("Dropping malformed peer {}, {}", peer.address, e);
+                    return;
+                }
+            };
+            METRICS.crawled_peers.fetch_add(1, Ordering::Relaxed);
+            crawled.record(peer.id);
+            writer.push(peer_data).await;
+        });
     }
 
     pub async fn start_network(&self) {
@@ -344,13 +661,31 @@ impl UpdateListener {
                         "Session Established with peer {}",
                         remote_addr.ip().to_string()
                     );
-                    let db = self.db.clone();
+                    discovery_event!(
+                        self.trace_discovery,
+                        "Discovered peer {} via inbound",
+                        NodeRecord::new(remote_addr, peer_id)
+                    );
+                    if self.skip_ip(remote_addr.ip()) || self.sampled_out(peer_id, remote_addr.ip())
+                    {
+                        self.network.peers_handle().remove_peer(peer_id);
+                        continue;
+                    }
+                    let writer = self.writer.clone();
+                    let geo = self.geo.clone();
+                    let crawled = self.crawled.clone();
                     let peer_handle = self.network.peers_handle().clone();
+                    let trace = self.trace_discovery;
+                    let expected_genesis = self.expected_genesis.clone();
+                    let client_filter = self.client_filter.clone();
+                    let location_filter = self.location_filter.clone();
+                    let roles = self.roles.clone();
+                    let session_id = self.session_id.clone();
                     tokio::spawn(async move {
                         // immediately disconnect the peer since we don't need any data from it
                         peer_handle.remove_peer(peer_id);
                         let enode_url = NodeRecord::new(remote_addr.clone(), peer_id);
-                        let capabilities = capabilities
+                        let capabilities: Vec<String> = capabilities
                             .as_ref()
                             .capabilities()
                             .to_vec()
@@ -360,50 +695,102 @@ impl UpdateListener {
                         let chain = status.chain.to_string();
                         let total_difficulty = status.total_difficulty.to_string();
                         let best_block = status.blockhash.to_string();
-                        let genesis_block_hash = status.genesis.to_string();
-                        let ttl = Utc::now()
-                            .checked_add_days(Days::new(1))
-                            .unwrap()
-                            .timestamp();
+                        // the session is owned by the network manager, so we can't ask for the header
+                        let best_block_number = None;
+                        let genesis_block_hash = format!("{:#x}", status.genesis);
+                        if !Self::expected_genesis(
+                            expected_genesis.as_deref(),
+                            &genesis_block_hash,
+                            trace,
+                            remote_addr.ip(),
+                        ) {
+                            return;
+                        }
                         let last_seen = Utc::now().to_string();
-                        let mut country = String::default();
-                        let mut city = String::default();
-                        let service = Service::IpApi;
                         let ip_addr = remote_addr.ip().to_string();
-
-                        match Locator::get(&ip_addr, service).await {
-                            Ok(loc) => {
-                                country = loc.country;
-                                city = loc.city;
-                            }
-                            Err(_) => {
-                                // leave `country` and `city` empty if not able to get them
-                            }
-                        }
+                        // leave `country` and `city` empty if not able to get them
+                        let location = match &geo {
+                            Some(geo) => geo.locate(remote_addr.ip()).await.unwrap_or_default(),
+                            None => GeoInfo::default(),
+                        };
                         // these peers inflate our numbers, same IP multiple generated ID
                         // TODO: ban them, but this isn't controlled by disc, and ban_ip semantics don't seem public to peers/network handles (?) - maybe peer_handle::reputation_change
                         if client_version.is_empty() {
-                            debug!("Peer {} with empty client_version - returning", ip_addr);
+                            discovery_event!(
+                                trace,
+                                "Dropped peer {}: empty client version",
+                                ip_addr
+                            );
                             return;
                         }
-
-                        let peer_data = PeerData {
-                            enode_url: enode_url.to_string(),
-                            id: peer_id.to_string(),
-                            tcp_port: remote_addr.port(),
-                            address: remote_addr.ip().to_string(),
-                            client_version: client_version.to_string(),
-                            capabilities,
-                            eth_version: u8::from(version),
-                            chain,
-                            total_difficulty,
-                            best_block,
-                            genesis_block_hash,
-                            last_seen,
+                        if let Some(rule) = client_filter.rejects(&client_version) {
+                            discovery_event!(
+                                trace,
+                                "Dropped peer {}: client {} filtered out by {}",
+                                ip_addr,
+                                client_version,
+                                rule
+                            );
+                            return;
+                        }
+                        if let Some(rule) = location_filter.rejects(&location) {
+                            discovery_event!(
+                                trace,
+                                "Dropped peer {}: location {:?} filtered out by {}",
+                                ip_addr,
+                                location.country,
+                                rule
+                            );
+                            return;
+                        }
+                        let GeoInfo {
                             country,
                             city,
+                            asn,
+                            org,
+                            ..
+                        } = location;
+                        let node_role = roles.classify(
+                            remote_addr.ip(),
+                            peer_id,
+                            &client_version,
+                            &capabilities,
+                        );
+
+                        let peer_data = match PeerData::builder()
+                            .enode_url(enode_url.to_string())
+                            .id(format!("{:x}", peer_id))
+                            .tcp_port(remote_addr.port())
+                            // only the TCP endpoint of inbound sessions is known
+                            .udp_port(None)
+                            .address(remote_addr.ip().to_string())
+                            .client_version(client_version.to_string())
+                            .capabilities(capabilities)
+                            .eth_version(u8::from(version))
+                            .chain(chain)
+                            .total_difficulty(total_difficulty)
+                            .best_block(best_block)
+                            .best_block_number(best_block_number)
+                            .genesis_block_hash(genesis_block_hash)
+                            .last_seen(last_seen)
+                            .country(country)
+                            .city(city)
+                            .asn(asn)
+                            .org(org)
+                            .discovery_source("inbound")
+                            .node_role(node_role)
+                            .session_id(&*session_id)
+                            .build()
+                        {
+                            Ok(peer_data) => peer_data,
+                            Err(e) => {
+                                info!("Dropping malformed peer {}, {}", ip_addr, e);
+                                return;
+                            }
                         };
-                        save_peer(peer_data, db, ttl).await;
+                        METRICS.crawled_peers.fetch_add(1, Ordering::Relaxed);
+                        crawled.record(peer_id);
+                        writer.push(peer_data).await;
                     });
                 }
                 NetworkEvent::PeerAdded(_) | NetworkEvent::PeerRemoved(_) => {}
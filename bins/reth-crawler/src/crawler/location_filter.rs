@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use tracing::info;
+
+use crate::geo::GeoInfo;
+
+/// Keeps or drops located peers by country and autonomous system, e.g. for regional studies.
+///
+/// A peer is dropped if `countries` isn't empty and its country is none of them, by name or ISO
+/// code ignoring case, or if `asns` isn't empty and its address isn't announced by one of them.
+/// Peers whose country or autonomous system is unknown are dropped by the matching allowlist.
+pub struct LocationFilter {
+    countries: Vec<String>,
+    asns: Vec<u32>,
+    // number of dropped peers by rule
+    filtered: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl LocationFilter {
+    pub fn new(countries: Vec<String>, asns: Vec<u32>) -> Self {
+        Self {
+            countries: countries
+                .into_iter()
+                .map(|country| country.trim().to_string())
+                .filter(|country| !country.is_empty())
+                .collect(),
+            asns,
+            filtered: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Tell whether a peer located at `geo` must be dropped, returning the rule dropping it and
+    /// counting the peer under that rule.
+    pub fn rejects(&self, geo: &GeoInfo) -> Option<&'static str> {
+        let rule = if !self.countries.is_empty()
+            && !self.countries.iter().any(|country| {
+                country.eq_ignore_ascii_case(&geo.country)
+                    || country.eq_ignore_ascii_case(&geo.country_code)
+            }) {
+            "--only-countries"
+        } else if !self.asns.is_empty() && !geo.asn.is_some_and(|asn| self.asns.contains(&asn)) {
+            "--only-asns"
+        } else {
+            return None;
+        };
+        *self.filtered.lock().unwrap().entry(rule).or_default() += 1;
+        Some(rule)
+    }
+
+    /// Log how many peers each rule dropped.
+    pub fn log_counts(&self) {
+        for (rule, count) in self.filtered.lock().unwrap().iter() {
+            info!("Filtered out {} peers with {}", count, rule);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::IpAddr;
+
+    use async_trait::async_trait;
+
+    use super::*;
+    use crate::geo::GeoLocator;
+
+    const BERLIN: &str = "203.0.113.1";
+    const PARIS: &str = "203.0.113.2";
+    const UNLOCATED: &str = "203.0.113.3";
+
+    /// Locates a few known addresses, like the crawler's locators would.
+    struct StubLocator(HashMap<IpAddr, GeoInfo>);
+
+    impl StubLocator {
+        fn new() -> Self {
+            let located = |country: &str, country_code: &str, asn| GeoInfo {
+                country: country.to_string(),
+                city: String::new(),
+                country_code: country_code.to_string(),
+                asn,
+                org: None,
+            };
+            Self(HashMap::from([
+                (
+                    BERLIN.parse().unwrap(),
+                    located("Germany", "DE", Some(3320)),
+                ),
+                (PARIS.parse().unwrap(), located("France", "FR", None)),
+            ]))
+        }
+    }
+
+    #[async_trait]
+    impl GeoLocator for StubLocator {
+        async fn locate(&self, ip: IpAddr) -> Option<GeoInfo> {
+            self.0.get(&ip).cloned()
+        }
+    }
+
+    /// Locate `ip` and filter it the way the crawler does, an unknown location being empty.
+    async fn rejects(filter: &LocationFilter, ip: &str) -> Option<&'static str> {
+        let location = StubLocator::new()
+            .locate(ip.parse().unwrap())
+            .await
+            .unwrap_or_default();
+        filter.rejects(&location)
+    }
+
+    #[tokio::test]
+    async fn keeps_every_peer_without_rules() {
+        let filter = LocationFilter::new(vec![], vec![]);
+        for ip in [BERLIN, PARIS, UNLOCATED] {
+            assert_eq!(rejects(&filter, ip).await, None, "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn matches_countries_by_name_or_iso_code_ignoring_case() {
+        let filter = LocationFilter::new(vec!["germany".to_string()], vec![]);
+        assert_eq!(rejects(&filter, BERLIN).await, None);
+        assert_eq!(rejects(&filter, PARIS).await, Some("--only-countries"));
+
+        let filter = LocationFilter::new(vec![" fr ".to_string(), String::new()], vec![]);
+        assert_eq!(rejects(&filter, PARIS).await, None);
+        assert_eq!(rejects(&filter, BERLIN).await, Some("--only-countries"));
+    }
+
+    #[tokio::test]
+    async fn drops_unknown_countries_and_asns() {
+        let filter = LocationFilter::new(vec!["DE".to_string(), "FR".to_string()], vec![]);
+        assert_eq!(rejects(&filter, UNLOCATED).await, Some("--only-countries"));
+
+        let filter = LocationFilter::new(vec![], vec![3320]);
+        assert_eq!(rejects(&filter, BERLIN).await, None);
+        // located, but not found in the ASN db
+        assert_eq!(rejects(&filter, PARIS).await, Some("--only-asns"));
+        assert_eq!(rejects(&filter, UNLOCATED).await, Some("--only-asns"));
+    }
+
+    #[tokio::test]
+    async fn counts_dropped_peers_by_rule() {
+        let filter = LocationFilter::new(vec!["DE".to_string(), "FR".to_string()], vec![3320]);
+        for ip in [BERLIN, PARIS, PARIS, UNLOCATED] {
+            rejects(&filter, ip).await;
+        }
+        let counts = filter.filtered.lock().unwrap().clone();
+        assert_eq!(
+            counts,
+            BTreeMap::from([("--only-asns", 2), ("--only-countries", 1)])
+        );
+    }
+}
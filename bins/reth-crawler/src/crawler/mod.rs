@@ -1,6 +1,168 @@
+mod chain;
+mod change_tracker;
+mod client_filter;
+mod dial_guard;
+mod dial_policy;
+mod enode_list;
 mod factory;
+mod ip_filter;
+mod kafka;
 mod listener;
+mod location_filter;
+mod node_key;
+mod peer_counter;
+mod retry;
+mod role_detector;
+mod sampler;
 mod service;
+mod static_peers;
+mod subnet_limiter;
+mod writer;
 
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use reth_primitives::NodeRecord;
+
+use crate::geo::GeoLocator;
+
+pub use self::chain::Chain;
+pub(crate) use self::change_tracker::ChangeTracker;
+pub(crate) use self::client_filter::ClientFilter;
+pub(crate) use self::dial_guard::DialGuard;
+pub(crate) use self::dial_policy::DialPolicy;
+pub(crate) use self::enode_list::EnodeList;
 pub use self::factory::CrawlerFactory;
+pub(crate) use self::ip_filter::is_public_ip;
+pub(crate) use self::kafka::KafkaSink;
+pub(crate) use self::location_filter::LocationFilter;
+pub(crate) use self::node_key::load_or_create_node_key;
+pub(crate) use self::peer_counter::PeerCounter;
+pub(crate) use self::retry::RetryQueue;
+pub(crate) use self::role_detector::RoleDetector;
+pub(crate) use self::sampler::PeerSampler;
 pub use self::service::CrawlerService;
+pub(crate) use self::static_peers::load_static_peers;
+pub(crate) use self::subnet_limiter::SubnetLimiter;
+pub(crate) use self::writer::PeerWriter;
+
+/// A db the crawled peers are written to.
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Backend {
+    /// The local sqlite db at `--db-path`
+    Sqlite,
+    /// The DynamoDB table `--dynamo-table`
+    #[value(alias = "aws")]
+    Dynamodb,
+    /// A db held in memory, lost when the crawler stops, for quick tests and dry runs
+    Memory,
+}
+
+/// Settings of the dbs a [`Backend`] is opened with.
+pub struct DbConfig {
+    /// Path of the sqlite db, `:memory:` to keep it in memory.
+    pub db_path: PathBuf,
+    /// Number of connections to the sqlite db.
+    pub sqlite_pool_size: usize,
+    /// Named AWS profile to load DynamoDB credentials from.
+    pub aws_profile: Option<String>,
+    /// DynamoDB table the peers are written to.
+    pub dynamo_table: String,
+    /// AWS regions the DynamoDB table is written to, the first one also serving reads. Empty for
+    /// the default region.
+    pub dynamo_regions: Vec<String>,
+    /// Endpoint the DynamoDB requests are sent to instead of AWS, if any.
+    pub dynamo_endpoint: Option<String>,
+    /// Read DynamoDB with strongly consistent reads.
+    pub consistent_reads: bool,
+}
+
+/// Settings of a crawler run.
+pub struct CrawlerConfig {
+    /// Where the crawled peers are written. Reads go to the first one.
+    pub backends: Vec<Backend>,
+    /// Settings the backends are opened with.
+    pub db: DbConfig,
+    /// Locates the crawled peers. Without it, peers are recorded with an empty location.
+    pub geo: Option<Arc<dyn GeoLocator>>,
+    /// Number of buffered peers that triggers a write to the db.
+    pub flush_size: usize,
+    /// Longest time a crawled peer stays buffered before being written to the db.
+    pub flush_interval: Duration,
+    /// Number of crawled peers that can wait for the db writer before crawling slows down.
+    pub write_buffer: usize,
+    /// Number of tasks writing batches of crawled peers to the db concurrently.
+    pub db_writer_tasks: NonZeroUsize,
+    /// Days after their last write that DynamoDB expires the peers.
+    pub ttl_days: u64,
+    /// Shortest time between two dials of the same peer.
+    pub recrawl_cooldown: Duration,
+    /// Network whose genesis and fork id the ETH handshakes advertise.
+    pub chain: Chain,
+    /// Stop once discovery runs dry instead of crawling forever.
+    pub once: bool,
+    /// Stop once this many distinct peers have been handshaked.
+    pub max_peers: Option<usize>,
+    /// Limits the dials to each subnet.
+    pub subnet_limiter: SubnetLimiter,
+    /// Peers to dial again after a transient handshake failure.
+    pub retry_queue: RetryQueue,
+    /// Drop peers with private, loopback, link-local or bogon addresses.
+    pub skip_private_ips: bool,
+    /// Addresses allowed or denied to be dialed.
+    pub dial_policy: DialPolicy,
+    /// Keeps a fraction of the discovered peers.
+    pub sampler: PeerSampler,
+    /// `host:port` of a SOCKS5 proxy the handshakes connect through.
+    pub socks5: Option<String>,
+    /// Client version advertised in the crawler's `Hello`.
+    pub crawler_id: String,
+    /// Id of this crawl, recorded on every crawled peer.
+    pub session_id: String,
+    /// How often to prune old peers from the db, never if `None`.
+    pub prune_interval: Option<Duration>,
+    /// Age in days of the peers deleted when pruning.
+    pub prune_older_than_days: i64,
+    /// How often to record the number of stored peers in the peer count history, never if
+    /// `None`.
+    pub count_history_interval: Option<Duration>,
+    /// How often to log a summary of the crawler metrics, never if `None`.
+    pub metrics_log_interval: Option<Duration>,
+    /// Where crawled peers are published, in addition to the db.
+    pub kafka: Option<KafkaSink>,
+    /// File listing the enode URLs of the live peers, if any.
+    pub enode_list: Option<EnodeList>,
+    /// Write every crawled peer to stdout as a JSON line, in addition to the db.
+    pub stdout_json: bool,
+    /// Log every discovered peer and why it was dropped at `info` level instead of `debug`.
+    pub trace_discovery: bool,
+    /// Only record the peers with this genesis hash.
+    pub expected_genesis: Option<String>,
+    /// Drops peers by client name.
+    pub client_filter: ClientFilter,
+    /// Drops located peers by country and autonomous system.
+    pub location_filter: LocationFilter,
+    /// Record the raw `Hello` and `Status` messages of the dialed peers.
+    pub store_raw_handshake: bool,
+    /// Record the peers that disconnect during the handshake, with their reason.
+    pub record_disconnects: bool,
+    /// How often to dial again the peers of the db seen the longest time ago, never if `None`.
+    pub recrawl_interval: Option<Duration>,
+    /// Number of peers dialed again every `recrawl_interval`.
+    pub recrawl_batch_size: i32,
+    /// Only refresh the `best_block`, `total_difficulty` and `last_seen` of the re-crawled peers
+    /// instead of rewriting their whole record.
+    pub recrawl_chain_status_only: bool,
+    /// Flags likely sentry and proxy nodes.
+    pub role_detector: RoleDetector,
+    /// Peers dialed every `static_peers_interval`, in addition to the discovered ones.
+    pub static_peers: Vec<NodeRecord>,
+    /// How often the static peers are dialed.
+    pub static_peers_interval: Duration,
+    /// Crawl the peers found by discv4, DNS discovery and inbound sessions. Without it only the
+    /// static peers are dialed.
+    pub discovery: bool,
+}
@@ -0,0 +1,38 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use eyre::WrapErr;
+use reth_network::config::rng_secret_key;
+use secp256k1::SecretKey;
+use tracing::info;
+
+/// Read the node key stored as hex in `path`, or generate one and save it there if the file
+/// doesn't exist, so that the crawler keeps the same node id across restarts.
+///
+/// A new key file is only readable by its owner on unix.
+pub fn load_or_create_node_key(path: &Path) -> eyre::Result<SecretKey> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => SecretKey::from_str(contents.trim())
+            .map_err(|e| eyre::eyre!("Invalid node key in {}: {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let key = rng_secret_key();
+            let write_failed = || format!("Failed to write the node key to {}", path.display());
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).wrap_err_with(write_failed)?;
+            }
+            let mut options = OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            options
+                .open(path)
+                .and_then(|mut file| file.write_all(key.display_secret().to_string().as_bytes()))
+                .wrap_err_with(write_failed)?;
+            info!("Saved a new node key to {}", path.display());
+            Ok(key)
+        }
+        Err(e) => Err(e).wrap_err_with(|| format!("Failed to read {}", path.display())),
+    }
+}
@@ -0,0 +1,56 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use reth_primitives::PeerId;
+use tokio::sync::Notify;
+
+/// Counts the distinct peers successfully handshaked, notifying when `max_peers` is reached.
+pub struct PeerCounter {
+    max_peers: Option<usize>,
+    seen: Mutex<HashSet<PeerId>>,
+    count: AtomicUsize,
+    reached: Notify,
+}
+
+impl PeerCounter {
+    pub fn new(max_peers: Option<usize>) -> Self {
+        Self {
+            max_peers,
+            seen: Mutex::new(HashSet::new()),
+            count: AtomicUsize::new(0),
+            reached: Notify::new(),
+        }
+    }
+
+    /// Record a handshaked peer. Without a cap, nothing is recorded, so that a crawler running
+    /// forever doesn't remember every peer it ever handshaked.
+    pub fn record(&self, peer_id: PeerId) {
+        if self.max_peers.is_none() {
+            return;
+        }
+        if !self.seen.lock().unwrap().insert(peer_id) {
+            return;
+        }
+        let count = self.count.fetch_add(1, Ordering::Relaxed) + 1;
+        if Some(count) == self.max_peers {
+            self.reached.notify_one();
+        }
+    }
+
+    /// Whether enough peers have been handshaked to stop crawling.
+    pub fn is_reached(&self) -> bool {
+        self.max_peers
+            .is_some_and(|max_peers| self.count.load(Ordering::Relaxed) >= max_peers)
+    }
+
+    /// Wait until `max_peers` distinct peers have been handshaked. Never returns without a cap.
+    pub async fn wait_reached(&self) {
+        if self.max_peers.is_none() {
+            return futures::future::pending().await;
+        }
+        if !self.is_reached() {
+            self.reached.notified().await;
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::time::Duration;
+
+use reth_primitives::NodeRecord;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::Instant;
+
+/// Number of peers that can wait for a retry at the same time.
+const RETRY_QUEUE_CAPACITY: usize = 1024;
+
+/// A peer to dial again after a transient handshake failure.
+pub struct Retry {
+    pub peer: NodeRecord,
+    pub discovery_source: &'static str,
+    /// Number of dials of the peer that already failed.
+    pub attempt: u32,
+    due: Instant,
+}
+
+/// Bounded queue of peers to dial again, `delay` after a transient handshake failure.
+pub struct RetryQueue {
+    max_retries: u32,
+    delay: Duration,
+    tx: mpsc::Sender<Retry>,
+    rx: Mutex<mpsc::Receiver<Retry>>,
+}
+
+impl RetryQueue {
+    pub fn new(max_retries: u32, delay: Duration) -> Self {
+        let (tx, rx) = mpsc::channel(RETRY_QUEUE_CAPACITY);
+        Self {
+            max_retries,
+            delay,
+            tx,
+            rx: Mutex::new(rx),
+        }
+    }
+
+    /// Queue a retry of `peer`, whose `attempt`-th dial just failed. Returns `false` if the peer
+    /// is out of retries or the queue is full.
+    pub fn push(&self, peer: NodeRecord, discovery_source: &'static str, attempt: u32) -> bool {
+        if attempt > self.max_retries {
+            return false;
+        }
+        self.tx
+            .try_send(Retry {
+                peer,
+                discovery_source,
+                attempt,
+                due: Instant::now() + self.delay,
+            })
+            .is_ok()
+    }
+
+    /// Wait for the next peer due for a retry.
+    pub async fn next(&self) -> Retry {
+        let retry = self
+            .rx
+            .lock()
+            .await
+            .recv()
+            .await
+            .expect("the queue owns its sender");
+        tokio::time::sleep_until(retry.due).await;
+        retry
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+use lru::LruCache;
+use reth_primitives::PeerId;
+
+/// Number of (address, client version) pairs whose peer ids are remembered.
+const TRACKED_HOSTS: usize = 100_000;
+
+/// `node_role` of the peers sharing their address and client version with many other peer ids.
+pub const SENTRY_ROLE: &str = "sentry";
+/// `node_role` of the peers advertising exactly the configured capability set.
+pub const PROXY_ROLE: &str = "proxy";
+
+/// Flags crawled peers that likely are sentry or proxy nodes, with two heuristics:
+///
+/// - a peer is a `sentry` once at least `min_ids_per_ip` distinct peer ids have been seen at its
+///   address with its client version, as operators often run many identical nodes on one host
+///   in front of their validators. Peers seen before the threshold was reached keep no role.
+/// - a peer is a `proxy` if it advertises exactly `proxy_capabilities`, in any order.
+///
+/// Either heuristic is disabled by a `min_ids_per_ip` of 0 or empty `proxy_capabilities`.
+pub struct RoleDetector {
+    min_ids_per_ip: usize,
+    proxy_capabilities: HashSet<String>,
+    ids: Mutex<LruCache<(IpAddr, String), HashSet<PeerId>>>,
+}
+
+impl RoleDetector {
+    pub fn new(min_ids_per_ip: usize, proxy_capabilities: Vec<String>) -> Self {
+        Self {
+            min_ids_per_ip,
+            proxy_capabilities: proxy_capabilities.into_iter().collect(),
+            ids: Mutex::new(LruCache::new(NonZeroUsize::new(TRACKED_HOSTS).unwrap())),
+        }
+    }
+
+    /// Record a crawled peer and return its likely role, if any.
+    pub fn classify(
+        &self,
+        ip: IpAddr,
+        id: PeerId,
+        client_version: &str,
+        capabilities: &[String],
+    ) -> Option<String> {
+        if self.min_ids_per_ip > 0 {
+            let mut ids = self.ids.lock().unwrap();
+            let host_ids = ids.get_or_insert_mut((ip, client_version.to_string()), HashSet::new);
+            host_ids.insert(id);
+            if host_ids.len() >= self.min_ids_per_ip {
+                return Some(SENTRY_ROLE.to_string());
+            }
+        }
+        if !self.proxy_capabilities.is_empty()
+            && capabilities.len() == self.proxy_capabilities.len()
+            && capabilities
+                .iter()
+                .all(|cap| self.proxy_capabilities.contains(cap))
+        {
+            return Some(PROXY_ROLE.to_string());
+        }
+        None
+    }
+}
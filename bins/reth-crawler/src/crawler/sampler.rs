@@ -0,0 +1,52 @@
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+use lru::LruCache;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use reth_primitives::PeerId;
+
+/// Number of peers whose sampling decision is remembered.
+const TRACKED_PEERS: usize = 100_000;
+
+/// Keeps a random fraction `rate` of the discovered peers, drawing once per peer id so that peers
+/// discovered many times aren't more likely to be kept.
+///
+/// The draws come from an RNG seeded with `seed` if set, so two crawls with the same seed keep
+/// the same peers only if they discover them in the same order.
+pub struct PeerSampler {
+    rate: f64,
+    state: Mutex<SamplerState>,
+}
+
+struct SamplerState {
+    rng: StdRng,
+    // whether each peer was kept
+    decisions: LruCache<PeerId, bool>,
+}
+
+impl PeerSampler {
+    pub fn new(rate: f64, seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self {
+            rate,
+            state: Mutex::new(SamplerState {
+                rng,
+                decisions: LruCache::new(NonZeroUsize::new(TRACKED_PEERS).unwrap()),
+            }),
+        }
+    }
+
+    /// Whether the peer `id` is part of the sample. Every peer is at a rate of 1.
+    pub fn keeps(&self, id: PeerId) -> bool {
+        if self.rate >= 1.0 {
+            return true;
+        }
+        let mut state = self.state.lock().unwrap();
+        let SamplerState { rng, decisions } = &mut *state;
+        *decisions.get_or_insert(id, || rng.gen_bool(self.rate))
+    }
+}
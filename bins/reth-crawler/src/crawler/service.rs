@@ -1,15 +1,37 @@
+use chrono::Utc;
 use futures::join;
+use rand::Rng;
+use reth_crawler_db::{PeerCountSnapshot, PeerDB};
 use reth_discv4::Discv4;
 use reth_dns_discovery::DnsDiscoveryHandle;
 use reth_network::NetworkHandle;
 use reth_primitives::NodeRecord;
 use secp256k1::SecretKey;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc;
+use tracing::{debug, error, info};
 
 use crate::crawler::listener::UpdateListener;
+use crate::crawler::CrawlerConfig;
+use crate::metrics::METRICS;
+
+/// How long discovery must stay idle for a `--once` crawl to be over.
+const ONCE_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
 
 pub struct CrawlerService {
     updates: UpdateListener,
+    db: Arc<dyn PeerDB>,
+    flush_interval: Duration,
+    once: bool,
+    prune_interval: Option<Duration>,
+    prune_older_than_days: i64,
+    count_history_interval: Option<Duration>,
+    metrics_log_interval: Option<Duration>,
+    recrawl_interval: Option<Duration>,
+    recrawl_batch_size: i32,
+    discovery: bool,
 }
 
 impl CrawlerService {
@@ -18,18 +40,190 @@ impl CrawlerService {
         dnsdisc: DnsDiscoveryHandle,
         network: NetworkHandle,
         key: SecretKey,
-        local_db: bool,
+        db: Arc<dyn PeerDB>,
+        config: CrawlerConfig,
     ) -> Self {
         let (tx, rx) = mpsc::unbounded_channel::<Vec<NodeRecord>>();
-        let updates = UpdateListener::new(discv4, dnsdisc, network, key, tx, local_db).await;
-        Self { updates }
+        let flush_interval = config.flush_interval;
+        let once = config.once;
+        let prune_interval = config.prune_interval;
+        let prune_older_than_days = config.prune_older_than_days;
+        let count_history_interval = config.count_history_interval;
+        let metrics_log_interval = config.metrics_log_interval;
+        let recrawl_interval = config.recrawl_interval;
+        let recrawl_batch_size = config.recrawl_batch_size;
+        let discovery = config.discovery;
+        let updates =
+            UpdateListener::new(discv4, dnsdisc, network, key, tx, db.clone(), config).await;
+        Self {
+            updates,
+            db,
+            flush_interval,
+            once,
+            prune_interval,
+            prune_older_than_days,
+            count_history_interval,
+            metrics_log_interval,
+            recrawl_interval,
+            recrawl_batch_size,
+            discovery,
+        }
+    }
+
+    /// Crawl until the discovery streams end or ctrl-c is received, then let the db writes in
+    /// progress complete and flush the peers that are still buffered. The crawl also stops once
+    /// `max_peers` peers have been handshaked, and a `once` crawl when no peer has been dialed for
+    /// a while. Without `discovery`, only the static peers are dialed until ctrl-c is received. A
+    /// last peer count snapshot is recorded once the peers are flushed, if the peer count history
+    /// is enabled, and a last metrics summary is logged, if they are logged periodically.
+    pub async fn run(self) -> eyre::Result<()> {
+        let crawl = async {
+            let (discv4, dnsdisc, ()) = join!(
+                self.updates.start_discv4(),
+                self.updates.start_dnsdisc(),
+                self.updates.start_network(),
+            );
+            discv4.and(dnsdisc)
+        };
+        let crawl_until_stopped = async {
+            let result = tokio::select! {
+                result = crawl, if self.discovery => result,
+                _ = self.updates.start_static_peers() => Ok(()),
+                _ = self.updates.start_retries() => Ok(()),
+                _ = self.updates.start_enode_list() => Ok(()),
+                _ = self.prune_every() => Ok(()),
+                _ = self.record_count_every() => Ok(()),
+                _ = self.log_metrics_every() => Ok(()),
+                _ = self.recrawl_every() => Ok(()),
+                _ = self.updates.wait_max_peers() => {
+                    info!("Reached the maximum number of peers, stopping");
+                    Ok(())
+                }
+                _ = self.updates.wait_idle(ONCE_IDLE_TIMEOUT), if self.once => {
+                    info!("No new peers discovered for {:?}, stopping", ONCE_IDLE_TIMEOUT);
+                    Ok(())
+                }
+                _ = tokio::signal::ctrl_c() => {
+                    info!("Received ctrl-c, shutting down");
+                    Ok(())
+                }
+            };
+            // the writers run outside the select, so a batch being written isn't dropped midway
+            self.updates.writer().stop();
+            result
+        };
+        let (result, ()) = join!(
+            crawl_until_stopped,
+            self.updates.writer().run(self.flush_interval)
+        );
+        self.updates.writer().flush().await;
+        if self.count_history_interval.is_some() {
+            self.record_count().await;
+        }
+        if self.metrics_log_interval.is_some() {
+            log_metrics();
+        }
+        self.updates.client_filter().log_counts();
+        self.updates.location_filter().log_counts();
+        result
+    }
+
+    /// Prune the peers older than `prune_older_than_days` every `prune_interval`, forever. Never
+    /// returns if pruning is disabled.
+    async fn prune_every(&self) {
+        let Some(prune_interval) = self.prune_interval else {
+            return futures::future::pending().await;
+        };
+        let mut ticker = tokio::time::interval(prune_interval);
+        loop {
+            ticker.tick().await;
+            match self.db.prune_peers(self.prune_older_than_days).await {
+                Ok(pruned) => info!(
+                    "Pruned {} peers not seen for {} days",
+                    pruned, self.prune_older_than_days
+                ),
+                Err(e) => error!("Failed to prune peers: {}", e),
+            }
+        }
+    }
+
+    /// Record the number of stored peers in the peer count history every
+    /// `count_history_interval`, forever. Never returns if the history is disabled.
+    async fn record_count_every(&self) {
+        let Some(count_history_interval) = self.count_history_interval else {
+            return futures::future::pending().await;
+        };
+        let mut ticker = tokio::time::interval(count_history_interval);
+        loop {
+            ticker.tick().await;
+            self.record_count().await;
+        }
     }
 
-    pub async fn run(self) -> (eyre::Result<()>, eyre::Result<()>, ()) {
-        join!(
-            self.updates.start_discv4(),
-            self.updates.start_dnsdisc(),
-            self.updates.start_network(),
-        )
+    /// Snapshot the number of stored peers into the peer count history.
+    async fn record_count(&self) {
+        let count = match self.db.count_peers().await {
+            Ok(count) => count,
+            Err(e) => return error!("Failed to count peers: {}", e),
+        };
+        let snapshot = PeerCountSnapshot {
+            timestamp: Utc::now().to_string(),
+            count,
+        };
+        match self.db.record_peer_count(snapshot).await {
+            Ok(()) => debug!("Recorded a peer count of {}", count),
+            Err(e) => error!("Failed to record the peer count: {}", e),
+        }
     }
+
+    /// Log a summary of the crawler metrics every `metrics_log_interval`, forever. Never returns
+    /// if the summary is disabled.
+    async fn log_metrics_every(&self) {
+        let Some(metrics_log_interval) = self.metrics_log_interval else {
+            return futures::future::pending().await;
+        };
+        let mut ticker = tokio::time::interval(metrics_log_interval);
+        // the first tick completes immediately, before anything happened
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            log_metrics();
+        }
+    }
+
+    /// Dial again the `recrawl_batch_size` peers seen the longest time ago every
+    /// `recrawl_interval`, delayed by up to a tenth of the interval so that several crawlers
+    /// don't re-crawl in lockstep. Never returns if re-crawling is disabled.
+    async fn recrawl_every(&self) {
+        let Some(recrawl_interval) = self.recrawl_interval else {
+            return futures::future::pending().await;
+        };
+        let mut ticker = tokio::time::interval(recrawl_interval);
+        loop {
+            ticker.tick().await;
+            let jitter = rand::thread_rng().gen_range(Duration::ZERO..=recrawl_interval / 10);
+            tokio::time::sleep(jitter).await;
+            match self.db.stale_peers(self.recrawl_batch_size).await {
+                Ok(peers) => {
+                    debug!("Re-crawling {} known peers", peers.len());
+                    self.updates.recrawl(peers);
+                }
+                Err(e) => error!("Failed to read the peers to re-crawl: {}", e),
+            }
+        }
+    }
+}
+
+/// Log a one line summary of the crawler metrics.
+fn log_metrics() {
+    info!(
+        "Discovered {} peers, crawled {}, wrote {} ({} write failures, {} failed handshakes, {} active dials, {} peers queued)",
+        METRICS.discovered_peers.load(Ordering::Relaxed),
+        METRICS.crawled_peers.load(Ordering::Relaxed),
+        METRICS.written_peers.load(Ordering::Relaxed),
+        METRICS.write_failures.load(Ordering::Relaxed),
+        METRICS.handshake_failures(),
+        METRICS.active_dials.load(Ordering::Relaxed),
+        METRICS.write_queue_depth.load(Ordering::Relaxed)
+    );
 }
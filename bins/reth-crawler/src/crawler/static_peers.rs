@@ -0,0 +1,55 @@
+use std::net::IpAddr;
+use std::path::Path;
+
+use enr::Enr;
+use eyre::WrapErr;
+use reth_ecies::util::pk2id;
+use reth_primitives::NodeRecord;
+use secp256k1::SecretKey;
+use tracing::warn;
+
+/// Read the peers listed in `path`, one enode URL or ENR per line. Blank lines and lines starting
+/// with `#` are ignored, and invalid entries are logged and skipped.
+pub fn load_static_peers(path: &Path) -> eyre::Result<Vec<NodeRecord>> {
+    let contents = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+    let peers = contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|(line_number, line)| match parse_static_peer(line) {
+            Ok(peer) => Some(peer),
+            Err(e) => {
+                warn!(
+                    "Skipping invalid static peer on line {} of {}: {}",
+                    line_number,
+                    path.display(),
+                    e
+                );
+                None
+            }
+        })
+        .collect();
+    Ok(peers)
+}
+
+/// Parse an `enode://` URL or an `enr:` record.
+fn parse_static_peer(peer: &str) -> eyre::Result<NodeRecord> {
+    if !peer.starts_with("enr:") {
+        return Ok(peer.parse::<NodeRecord>()?);
+    }
+    let enr: Enr<SecretKey> = peer.parse().map_err(|e: String| eyre::eyre!(e))?;
+    let (address, tcp_port, udp_port) = match (enr.ip4(), enr.ip6()) {
+        (Some(ip), _) => (IpAddr::V4(ip), enr.tcp4(), enr.udp4()),
+        (None, Some(ip)) => (IpAddr::V6(ip), enr.tcp6(), enr.udp6()),
+        (None, None) => eyre::bail!("the ENR has no IP address"),
+    };
+    let tcp_port = tcp_port.ok_or_else(|| eyre::eyre!("the ENR has no TCP port"))?;
+    Ok(NodeRecord {
+        address,
+        tcp_port,
+        udp_port: udp_port.unwrap_or(tcp_port),
+        id: pk2id(&enr.public_key()),
+    })
+}
@@ -0,0 +1,75 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use lru::LruCache;
+
+/// Number of subnets whose buckets are remembered.
+const SUBNETS_CAPACITY: usize = 100_000;
+/// Dials to a subnet allowed in a row before throttling kicks in.
+const SUBNET_BURST: f64 = 4.0;
+
+/// Token bucket rate limiter of the dials to each subnet, so the crawler doesn't hammer the hosts
+/// of a single operator or cloud provider.
+pub struct SubnetLimiter {
+    v4_prefix: u8,
+    v6_prefix: u8,
+    // tokens per second
+    rate: f64,
+    buckets: Mutex<LruCache<IpAddr, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl SubnetLimiter {
+    /// Allow `dials_per_min` dials per minute to each IPv4 /`v4_prefix` and IPv6 /`v6_prefix`.
+    pub fn new(v4_prefix: u8, v6_prefix: u8, dials_per_min: u64) -> Self {
+        Self {
+            v4_prefix,
+            v6_prefix,
+            rate: dials_per_min as f64 / 60.0,
+            buckets: Mutex::new(LruCache::new(NonZeroUsize::new(SUBNETS_CAPACITY).unwrap())),
+        }
+    }
+
+    /// Reserve a dial to `ip`, returning how long to wait before dialing.
+    pub fn reserve(&self, ip: IpAddr) -> Duration {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.get_or_insert_mut(self.subnet(ip), || Bucket {
+            tokens: SUBNET_BURST,
+            updated_at: now,
+        });
+        let refill = now.duration_since(bucket.updated_at).as_secs_f64() * self.rate;
+        bucket.tokens = (bucket.tokens + refill).min(SUBNET_BURST) - 1.0;
+        bucket.updated_at = now;
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            // the bucket goes negative so that waiting dials queue up behind each other
+            Duration::from_secs_f64(-bucket.tokens / self.rate)
+        }
+    }
+
+    /// The subnet `ip` belongs to, i.e. `ip` with its host bits cleared.
+    pub fn subnet(&self, ip: IpAddr) -> IpAddr {
+        match ip {
+            IpAddr::V4(ip) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.v4_prefix as u32)
+                    .unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask))
+            }
+            IpAddr::V6(ip) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.v6_prefix as u32)
+                    .unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+            }
+        }
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::mem;
+use std::num::NonZeroUsize;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{Days, Utc};
+use futures::future::join_all;
+use reth_crawler_db::{PeerDB, PeerData};
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, error};
+
+use crate::crawler::{ChangeTracker, EnodeList, KafkaSink};
+use crate::metrics::METRICS;
+
+/// Number of peers whose last observation is kept to detect their changes.
+const TRACKED_PEERS: usize = 100_000;
+
+/// Writes crawled peers to the db in batches, once `flush_size` peers are pending or every
+/// `flush_interval`, whichever comes first. Peers are also published to the Kafka sink and
+/// recorded in the enode list, if any, as soon as they are pushed, and compared with their
+/// previous observation. With `stdout_json`, they are also written to stdout as JSON lines.
+///
+/// Pushed peers go through a queue of `capacity` peers drained by the `tasks` concurrent writers
+/// of [`PeerWriter::run`], each with its own buffer, so that up to `tasks` batches are written at
+/// once. When the db can't keep up and the queue is full, pushing waits, which slows the crawl
+/// down instead of buffering an unbounded number of peers.
+///
+/// [`PeerWriter::stop`] ends the writers once their current write completes, so that no batch
+/// is dropped midway on shutdown, and [`PeerWriter::flush`] then writes what's left.
+pub struct PeerWriter {
+    db: Arc<dyn PeerDB>,
+    kafka: Option<KafkaSink>,
+    enode_list: Option<Arc<EnodeList>>,
+    stdout_json: bool,
+    changes: ChangeTracker,
+    flush_size: usize,
+    capacity: usize,
+    ttl_days: u64,
+    sender: mpsc::Sender<PeerData>,
+    receiver: tokio::sync::Mutex<mpsc::Receiver<PeerData>>,
+    stop: watch::Sender<bool>,
+    // one per writer, keyed by peer id, so a peer seen twice before a flush is only written once
+    buffers: Vec<Mutex<HashMap<String, PeerData>>>,
+}
+
+impl PeerWriter {
+    pub fn new(
+        db: Arc<dyn PeerDB>,
+        kafka: Option<KafkaSink>,
+        enode_list: Option<Arc<EnodeList>>,
+        stdout_json: bool,
+        flush_size: usize,
+        capacity: usize,
+        tasks: NonZeroUsize,
+        ttl_days: u64,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(capacity);
+        Self {
+            db,
+            kafka,
+            enode_list,
+            stdout_json,
+            changes: ChangeTracker::new(NonZeroUsize::new(TRACKED_PEERS).unwrap()),
+            flush_size,
+            capacity,
+            ttl_days,
+            sender,
+            receiver: tokio::sync::Mutex::new(receiver),
+            stop: watch::channel(false).0,
+            buffers: (0..tasks.get())
+                .map(|_| Mutex::new(HashMap::new()))
+                .collect(),
+        }
+    }
+
+    /// Queue a peer for writing, waiting while the queue is full.
+    pub async fn push(&self, peer_data: PeerData) {
+        self.changes.observe(&peer_data);
+        if let Some(kafka) = &self.kafka {
+            kafka.publish(&peer_data).await;
+        }
+        if let Some(enode_list) = &self.enode_list {
+            enode_list.record(&peer_data);
+        }
+        if self.stdout_json {
+            if let Err(e) = print_json_line(&peer_data) {
+                error!("Failed to write peer {} to stdout: {}", peer_data.id, e);
+            }
+        }
+        // the receiver lives as long as `self`, so sending can't fail
+        let _ = self.sender.send(peer_data).await;
+        self.record_depth();
+    }
+
+    /// Queue the partial record of a peer that disconnected during the handshake, unless the
+    /// peer was fully handshaked recently, whose record is more useful. Only the peers pushed
+    /// since the crawler started are known, so no db read is needed. Partial records aren't
+    /// published to the sinks, and don't replace a buffered full record of the same peer.
+    pub async fn record_partial(&self, peer_data: PeerData) {
+        if self.changes.contains(&peer_data.id) {
+            return;
+        }
+        // the receiver lives as long as `self`, so sending can't fail
+        let _ = self.sender.send(peer_data).await;
+        self.record_depth();
+    }
+
+    /// Set the chain status of a recorded peer in the db right away, bypassing the queue and the
+    /// sinks. Returns whether the peer is recorded, or `false` if the db failed to update it.
+    pub async fn update_chain_status(
+        &self,
+        id: String,
+        best_block: String,
+        best_block_number: Option<u64>,
+        total_difficulty: String,
+        last_seen: String,
+    ) -> bool {
+        match self
+            .db
+            .update_chain_status(
+                id.clone(),
+                best_block,
+                best_block_number,
+                total_difficulty,
+                last_seen,
+            )
+            .await
+        {
+            Ok(found) => found,
+            Err(e) => {
+                error!("Failed to update the chain status of peer {}: {}", id, e);
+                false
+            }
+        }
+    }
+
+    /// Receive the queued peers and write them to the db with every writer, until
+    /// [`PeerWriter::stop`] is called and every writer finished its current write.
+    pub async fn run(&self, flush_interval: Duration) {
+        join_all((0..self.buffers.len()).map(|writer| self.run_writer(writer, flush_interval)))
+            .await;
+    }
+
+    /// Receive queued peers into the buffer of `writer` and write it to the db, until stopped.
+    /// The queue is only locked while waiting for a peer, so the other writers keep receiving
+    /// peers while this one writes. A write in progress isn't interrupted by a stop: the writer
+    /// only returns while waiting, leaving the peers it received in its buffer.
+    async fn run_writer(&self, writer: usize, flush_interval: Duration) {
+        let mut stop = self.stop.subscribe();
+        let mut ticker = tokio::time::interval(flush_interval);
+        // the first tick completes immediately
+        ticker.tick().await;
+        loop {
+            tokio::select! {
+                _ = stop.wait_for(|stop| *stop) => return,
+                Some(peer_data) = self.recv() => {
+                    self.record_depth();
+                    if self.buffer(writer, peer_data) {
+                        self.write_buffer(writer).await;
+                    }
+                }
+                _ = ticker.tick() => self.write_buffer(writer).await,
+            }
+        }
+    }
+
+    /// Make [`PeerWriter::run`] return once the current writes complete.
+    pub fn stop(&self) {
+        self.stop.send_replace(true);
+    }
+
+    async fn recv(&self) -> Option<PeerData> {
+        self.receiver.lock().await.recv().await
+    }
+
+    /// Write all queued and buffered peers to the db. Meant for shutdown, once
+    /// [`PeerWriter::run`] returned.
+    pub async fn flush(&self) {
+        {
+            let mut receiver = self.receiver.lock().await;
+            while let Ok(peer_data) = receiver.try_recv() {
+                self.buffer(0, peer_data);
+            }
+        }
+        self.record_depth();
+        join_all((0..self.buffers.len()).map(|writer| self.write_buffer(writer))).await;
+    }
+
+    /// Buffer a peer for `writer`, returning whether its buffer is full. A partial record
+    /// doesn't replace a full one.
+    fn buffer(&self, writer: usize, peer_data: PeerData) -> bool {
+        let mut buffer = self.buffers[writer].lock().unwrap();
+        let keeps_full_record = peer_data.last_disconnect_reason.is_some()
+            && buffer
+                .get(&peer_data.id)
+                .is_some_and(|buffered| buffered.last_disconnect_reason.is_none());
+        if !keeps_full_record {
+            buffer.insert(peer_data.id.clone(), peer_data);
+        }
+        buffer.len() >= self.flush_size
+    }
+
+    /// Unix timestamp after which DynamoDB may expire the peers written now.
+    fn ttl(&self) -> i64 {
+        Utc::now()
+            .checked_add_days(Days::new(self.ttl_days))
+            .unwrap()
+            .timestamp()
+    }
+
+    /// Publish how many peers wait in the queue.
+    fn record_depth(&self) {
+        let depth = self.capacity - self.sender.capacity();
+        METRICS
+            .write_queue_depth
+            .store(depth as u64, Ordering::Relaxed);
+    }
+
+    /// Write the peers buffered for `writer` to the db.
+    async fn write_buffer(&self, writer: usize) {
+        let peers: Vec<PeerData> = {
+            let mut buffer = self.buffers[writer].lock().unwrap();
+            mem::take(&mut *buffer).into_values().collect()
+        };
+        if peers.is_empty() {
+            return;
+        }
+
+        let ttl = self.ttl();
+        let count = peers.len();
+        match self.db.add_peers(peers.clone(), Some(ttl)).await {
+            Ok(()) => {
+                METRICS
+                    .written_peers
+                    .fetch_add(count as u64, Ordering::Relaxed);
+                debug!("Flushed {} peers to the db", count);
+            }
+            Err(e) => {
+                error!(
+                    "Failed to flush {} peers to the db, writing them one by one: {}",
+                    count, e
+                );
+                // isolates the peers that can't be written, whose errors name them
+                for peer_data in peers {
+                    match self.db.add_peer(peer_data, Some(ttl)).await {
+                        Ok(()) => {
+                            METRICS.written_peers.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Err(e) => {
+                            error!("{}", e);
+                            METRICS.write_failures.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Write `peer_data` to stdout as one JSON line. Stdout stays locked until the line is flushed,
+/// so lines written concurrently don't interleave.
+fn print_json_line(peer_data: &PeerData) -> std::io::Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    serde_json::to_writer(&mut stdout, peer_data)?;
+    stdout.write_all(b"\n")?;
+    stdout.flush()
+}
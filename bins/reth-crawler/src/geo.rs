@@ -0,0 +1,229 @@
+use crate::metrics::METRICS;
+use async_trait::async_trait;
+use clap::ValueEnum;
+use eyre::WrapErr;
+use ipgeolocate::{Locator, Service};
+use lru::LruCache;
+use maxminddb::{geoip2, Reader};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Location of a peer.
+#[derive(Clone, Debug, Default)]
+pub struct GeoInfo {
+    pub country: String,
+    pub city: String,
+    /// ISO 3166-1 code of the country, e.g. `DE`, empty if the locator doesn't provide it.
+    pub country_code: String,
+    /// Autonomous system announcing the address, found in the `--asn-db` if any.
+    pub asn: Option<u32>,
+    /// Organization running `asn`.
+    pub org: Option<String>,
+}
+
+/// A source of geolocation data for peer addresses.
+#[async_trait]
+pub trait GeoLocator: Send + Sync {
+    /// Locate `ip`, returning `None` if its location is unknown.
+    async fn locate(&self, ip: IpAddr) -> Option<GeoInfo>;
+}
+
+/// Which [`GeoLocator`] the crawler uses.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum GeoLocatorKind {
+    /// Query the ip-api.com service
+    IpApi,
+    /// Read a local MaxMind GeoLite2 City database (see `--geoip-db`)
+    Maxmind,
+    /// Don't locate peers
+    None,
+}
+
+/// Build the locator of the given kind, caching up to `cache_size` results per IP for `cache_ttl`,
+/// and also looking up the autonomous system of the peers in `asn_db` if set.
+pub fn new_locator(
+    kind: GeoLocatorKind,
+    geoip_db: Option<&Path>,
+    asn_db: Option<&Path>,
+    cache_size: NonZeroUsize,
+    cache_ttl: Duration,
+) -> eyre::Result<Arc<dyn GeoLocator>> {
+    let locator: Arc<dyn GeoLocator> = match kind {
+        GeoLocatorKind::IpApi => Arc::new(CachedLocator::new(IpApiLocator, cache_size, cache_ttl)),
+        GeoLocatorKind::Maxmind => {
+            let path = geoip_db.ok_or_else(|| eyre::eyre!("--geoip-db is required by maxmind"))?;
+            Arc::new(CachedLocator::new(
+                MaxMindLocator::open(path)?,
+                cache_size,
+                cache_ttl,
+            ))
+        }
+        GeoLocatorKind::None => Arc::new(NullLocator),
+    };
+    match asn_db {
+        // the ASN db is local, so its lookups aren't worth caching
+        Some(path) => Ok(Arc::new(AsnLocator {
+            inner: locator,
+            asn_db: AsnDb::open(path)?,
+        })),
+        None => Ok(locator),
+    }
+}
+
+/// Locate peers with the ip-api.com service.
+pub struct IpApiLocator;
+
+#[async_trait]
+impl GeoLocator for IpApiLocator {
+    async fn locate(&self, ip: IpAddr) -> Option<GeoInfo> {
+        match Locator::get(&ip.to_string(), Service::IpApi).await {
+            Ok(loc) => Some(GeoInfo {
+                country: loc.country,
+                city: loc.city,
+                ..GeoInfo::default()
+            }),
+            Err(_) => None,
+        }
+    }
+}
+
+/// Locate peers with a local MaxMind GeoLite2 City database, without any network call.
+pub struct MaxMindLocator {
+    reader: Reader<Vec<u8>>,
+}
+
+impl MaxMindLocator {
+    pub fn open(path: &Path) -> eyre::Result<Self> {
+        Ok(Self {
+            reader: Reader::open_readfile(path)
+                .wrap_err_with(|| format!("Failed to open {}", path.display()))?,
+        })
+    }
+}
+
+#[async_trait]
+impl GeoLocator for MaxMindLocator {
+    async fn locate(&self, ip: IpAddr) -> Option<GeoInfo> {
+        let city: geoip2::City<'_> = self.reader.lookup(ip).ok()?;
+        let country_code = city
+            .country
+            .as_ref()
+            .and_then(|country| country.iso_code)
+            .unwrap_or_default()
+            .to_string();
+        Some(GeoInfo {
+            country: english_name(city.country.and_then(|country| country.names)),
+            city: english_name(city.city.and_then(|city| city.names)),
+            country_code,
+            ..GeoInfo::default()
+        })
+    }
+}
+
+fn english_name(names: Option<BTreeMap<&str, &str>>) -> String {
+    names
+        .and_then(|names| names.get("en").map(|name| name.to_string()))
+        .unwrap_or_default()
+}
+
+/// Look up the autonomous system of peer addresses in a local MaxMind GeoLite2 ASN database.
+pub struct AsnDb {
+    reader: Reader<Vec<u8>>,
+}
+
+impl AsnDb {
+    pub fn open(path: &Path) -> eyre::Result<Self> {
+        Ok(Self {
+            reader: Reader::open_readfile(path)
+                .wrap_err_with(|| format!("Failed to open {}", path.display()))?,
+        })
+    }
+
+    /// Number and organization of the autonomous system announcing `ip`, `None` if it's unknown.
+    pub fn lookup(&self, ip: IpAddr) -> Option<(u32, Option<String>)> {
+        let asn: geoip2::Asn<'_> = self.reader.lookup(ip).ok()?;
+        Some((
+            asn.autonomous_system_number?,
+            asn.autonomous_system_organization.map(String::from),
+        ))
+    }
+}
+
+/// Wrap a locator to also look up the autonomous system of the peers in an [`AsnDb`].
+pub struct AsnLocator {
+    inner: Arc<dyn GeoLocator>,
+    asn_db: AsnDb,
+}
+
+#[async_trait]
+impl GeoLocator for AsnLocator {
+    async fn locate(&self, ip: IpAddr) -> Option<GeoInfo> {
+        let geo = self.inner.locate(ip).await;
+        let Some((asn, org)) = self.asn_db.lookup(ip) else {
+            return geo;
+        };
+        Some(GeoInfo {
+            asn: Some(asn),
+            org,
+            ..geo.unwrap_or_default()
+        })
+    }
+}
+
+/// A locator that never knows where a peer is.
+pub struct NullLocator;
+
+#[async_trait]
+impl GeoLocator for NullLocator {
+    async fn locate(&self, _: IpAddr) -> Option<GeoInfo> {
+        None
+    }
+}
+
+/// Wrap a locator with an LRU cache of successful lookups, so re-observed addresses are served
+/// from memory until their entry is older than `ttl`.
+pub struct CachedLocator<L> {
+    inner: L,
+    ttl: Duration,
+    cache: Mutex<LruCache<IpAddr, (GeoInfo, Instant)>>,
+}
+
+impl<L: GeoLocator> CachedLocator<L> {
+    pub fn new(inner: L, size: NonZeroUsize, ttl: Duration) -> Self {
+        Self {
+            inner,
+            ttl,
+            cache: Mutex::new(LruCache::new(size)),
+        }
+    }
+}
+
+#[async_trait]
+impl<L: GeoLocator> GeoLocator for CachedLocator<L> {
+    async fn locate(&self, ip: IpAddr) -> Option<GeoInfo> {
+        let cached = self
+            .cache
+            .lock()
+            .unwrap()
+            .get(&ip)
+            .filter(|(_, cached_at)| cached_at.elapsed() < self.ttl)
+            .map(|(geo, _)| geo.clone());
+        if let Some(geo) = cached {
+            METRICS.geo_cache_hits.fetch_add(1, Ordering::Relaxed);
+            return Some(geo);
+        }
+        METRICS.geo_cache_misses.fetch_add(1, Ordering::Relaxed);
+
+        let geo = self.inner.locate(ip).await?;
+        self.cache
+            .lock()
+            .unwrap()
+            .put(ip, (geo.clone(), Instant::now()));
+        Some(geo)
+    }
+}
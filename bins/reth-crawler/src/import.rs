@@ -0,0 +1,216 @@
+use std::ffi::OsStr;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::mem;
+use std::path::Path;
+use std::str::FromStr;
+
+use clap::ValueEnum;
+use csv::StringRecord;
+use eyre::{eyre, WrapErr};
+use flate2::read::GzDecoder;
+use reth_crawler_db::{PeerDB, PeerData, PeerDataBuilder};
+use tracing::warn;
+
+/// Number of peers written to the db at once.
+const IMPORT_BATCH_SIZE: usize = 1_000;
+
+/// Format of a file of peers read by [`import_peers`].
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ImportFormat {
+    /// One JSON object per line, as written by `export`
+    Jsonl,
+    /// A header row naming the peer fields, then one peer per row
+    Csv,
+}
+
+impl ImportFormat {
+    /// Format of `path` from its extension, ignoring a trailing `.gz`. `.json` and `.ndjson`
+    /// files are read as JSON lines too, since that's what `export` writes.
+    pub fn detect(path: &Path) -> Option<Self> {
+        let path = if path.extension() == Some(OsStr::new("gz")) {
+            Path::new(path.file_stem()?)
+        } else {
+            path
+        };
+        match path.extension()?.to_str()? {
+            "jsonl" | "json" | "ndjson" => Some(Self::Jsonl),
+            "csv" => Some(Self::Csv),
+            _ => None,
+        }
+    }
+}
+
+/// Outcome of [`import_peers`].
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// Records written to the db.
+    pub imported: usize,
+    /// Records that couldn't be read or aren't valid peers.
+    pub skipped: usize,
+}
+
+/// Open `path` for reading, decompressing it if it ends with `.gz`, like the files written by
+/// `export --gzip`.
+pub fn open(path: &Path) -> eyre::Result<Box<dyn BufRead>> {
+    let file = File::open(path).wrap_err_with(|| format!("Failed to open {}", path.display()))?;
+    if path.extension() == Some(OsStr::new("gz")) {
+        Ok(Box::new(BufReader::new(GzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+/// Read the peers of `input` and write them to `db` in batches of [`IMPORT_BATCH_SIZE`]. Every
+/// record goes through [`PeerDataBuilder::build`], and the records that can't be read or aren't
+/// valid peers are logged and skipped. Fails on the first I/O or db error.
+pub async fn import_peers(
+    db: &dyn PeerDB,
+    input: impl BufRead,
+    format: ImportFormat,
+) -> eyre::Result<ImportReport> {
+    let mut importer = Importer {
+        db,
+        batch: Vec::with_capacity(IMPORT_BATCH_SIZE),
+        report: ImportReport::default(),
+    };
+    match format {
+        ImportFormat::Jsonl => {
+            for (index, line) in input.lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let peer_data = serde_json::from_str::<PeerData>(&line)
+                    .map_err(eyre::Report::from)
+                    .and_then(|peer_data| Ok(PeerDataBuilder::from(peer_data).build()?));
+                importer.push(index as u64 + 1, peer_data).await?;
+            }
+        }
+        ImportFormat::Csv => {
+            let mut reader = csv::Reader::from_reader(input);
+            let headers = reader.headers()?.clone();
+            for record in reader.records() {
+                let (line, peer_data) = match record {
+                    Ok(record) => (
+                        record.position().map_or(0, |position| position.line()),
+                        peer_from_csv(&headers, &record),
+                    ),
+                    Err(err) if err.is_io_error() => return Err(err.into()),
+                    Err(err) => (
+                        err.position().map_or(0, |position| position.line()),
+                        Err(err.into()),
+                    ),
+                };
+                importer.push(line, peer_data).await?;
+            }
+        }
+    }
+    importer.flush().await?;
+    Ok(importer.report)
+}
+
+/// Batches the peers read by [`import_peers`].
+struct Importer<'a> {
+    db: &'a dyn PeerDB,
+    batch: Vec<PeerData>,
+    report: ImportReport,
+}
+
+impl Importer<'_> {
+    /// Queue the peer read at `line`, writing the batch once full, or skip it if it's invalid.
+    async fn push(&mut self, line: u64, peer_data: eyre::Result<PeerData>) -> eyre::Result<()> {
+        match peer_data {
+            Ok(peer_data) => {
+                self.batch.push(peer_data);
+                if self.batch.len() >= IMPORT_BATCH_SIZE {
+                    self.flush().await?;
+                }
+            }
+            Err(err) => {
+                warn!("Skipping the record at line {}: {}", line, err);
+                self.report.skipped += 1;
+            }
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> eyre::Result<()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let count = self.batch.len();
+        // imported peers never expire on DynamoDB, like the peers of the other backends
+        self.db.add_peers(mem::take(&mut self.batch), None).await?;
+        self.report.imported += count;
+        Ok(())
+    }
+}
+
+/// Read a CSV record whose columns are named after the [`PeerData`] fields by `headers`, in any
+/// order. Missing columns and empty optional fields are unset, and `capabilities` are comma
+/// separated.
+fn peer_from_csv(headers: &StringRecord, record: &StringRecord) -> eyre::Result<PeerData> {
+    let field = |name: &str| {
+        headers
+            .iter()
+            .position(|header| header.trim() == name)
+            .and_then(|index| record.get(index))
+            .unwrap_or_default()
+            .trim()
+    };
+    let text = |name: &str| Some(field(name).to_string()).filter(|value| !value.is_empty());
+    let capabilities = field("capabilities")
+        .split(',')
+        .map(str::trim)
+        .filter(|cap| !cap.is_empty())
+        .map(String::from)
+        .collect();
+
+    Ok(PeerData::builder()
+        .enode_url(field("enode_url"))
+        .id(field("id"))
+        .address(field("address"))
+        .tcp_port(parse_optional("tcp_port", field("tcp_port"))?.unwrap_or_default())
+        .udp_port(parse_optional("udp_port", field("udp_port"))?)
+        .client_version(field("client_version"))
+        .eth_version(parse_optional("eth_version", field("eth_version"))?.unwrap_or_default())
+        .capabilities(capabilities)
+        .chain(field("chain"))
+        .total_difficulty(field("total_difficulty"))
+        .best_block(field("best_block"))
+        .best_block_number(parse_optional(
+            "best_block_number",
+            field("best_block_number"),
+        )?)
+        .genesis_block_hash(field("genesis_block_hash"))
+        .last_seen(field("last_seen"))
+        .country(field("country"))
+        .city(field("city"))
+        .discovery_source(field("discovery_source"))
+        .raw_handshake(text("raw_handshake"))
+        .node_role(text("node_role"))
+        .tcp_connect_ms(parse_optional("tcp_connect_ms", field("tcp_connect_ms"))?)
+        .handshake_ms(parse_optional("handshake_ms", field("handshake_ms"))?)
+        .last_disconnect_reason(text("last_disconnect_reason"))
+        .asn(parse_optional("asn", field("asn"))?)
+        .org(text("org"))
+        .session_id(field("session_id"))
+        .build()?)
+}
+
+/// Parse the CSV field `name`, `None` if it's empty.
+fn parse_optional<T>(name: &str, value: &str) -> eyre::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value
+        .parse()
+        .map(Some)
+        .map_err(|err| eyre!("invalid {} {:?}, {}", name, value, err))
+}
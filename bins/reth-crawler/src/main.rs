@@ -1,7 +1,39 @@
+mod config;
 mod crawler;
+mod geo;
+mod import;
+mod metrics;
 mod p2p;
-use clap::{Args, Parser, Subcommand};
-use crawler::CrawlerFactory;
+use chrono::{DateTime, Utc};
+use clap::{ArgAction, Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use crawler::{
+    load_or_create_node_key, load_static_peers, Backend, Chain, ClientFilter, CrawlerConfig,
+    CrawlerFactory, DbConfig, DialPolicy, EnodeList, KafkaSink, LocationFilter, PeerSampler,
+    RetryQueue, RoleDetector, SubnetLimiter,
+};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::StreamExt;
+use geo::{GeoInfo, GeoLocator, GeoLocatorKind};
+use import::{import_peers, ImportFormat};
+use metrics::METRICS;
+use reth_crawler_db::db::DEFAULT_TABLE_NAME;
+use reth_crawler_db::{
+    handshake_latency_percentiles, verify_peers, InMemoryPeerDB, LatencyPercentiles, PeerDB,
+};
+use reth_network::config::rng_secret_key;
+use reth_primitives::NodeRecord;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::info;
+use tracing_subscriber::EnvFilter;
+use uuid::Uuid;
 
 #[derive(Parser)]
 #[command(author, version)]
@@ -12,37 +44,981 @@ use crawler::CrawlerFactory;
 One can use this crawler to quantify how many Ethereum nodes exists and what is the distribution of clients."
 )]
 struct Cli {
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Pretty)]
+    /// Format of the log output. Both formats honor `RUST_LOG`.
+    log_format: LogFormat,
+    #[arg(long, global = true, value_name = "PATH")]
+    /// TOML file setting options of the command, keyed by option name, e.g.
+    /// `dynamo_table = "eth-peers"` or `bootnodes = ["enode://..."]`. Options set on the command
+    /// line take precedence.
+    config: Option<PathBuf>,
     #[command(subcommand)]
     command: Commands,
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+enum LogFormat {
+    /// Human readable logs
+    Pretty,
+    /// One JSON object per line, for log aggregation pipelines
+    Json,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Start crawling the network
     Crawl(CrawlOpts),
+    /// Look up a single node in the db
+    Lookup(LookupOpts),
+    /// Print statistics about the crawled nodes
+    Stats(StatsOpts),
+    /// Fill in the country and city of the recorded peers, e.g. after adding a GeoIP db
+    Geolocate(GeolocateOpts),
+    /// Delete every recorded peer
+    Clear(ClearOpts),
+    /// Write every recorded peer to a file, one JSON object per line
+    Export(ExportOpts),
+    /// Write the peers of a JSON lines or CSV file to the db, e.g. an export of another db
+    Import(ImportOpts),
+    /// Check the recorded peers for corrupt fields, e.g. left by older versions
+    Verify(VerifyOpts),
 }
 
 #[derive(Args)]
 struct CrawlOpts {
     #[arg(long)]
-    /// Use a sqlite db for local testing.
+    /// Deprecated alias of `--backend sqlite`.
     local_db: bool,
+    #[arg(
+        long,
+        alias = "backend",
+        value_enum,
+        value_delimiter = ',',
+        conflicts_with = "local_db"
+    )]
+    /// Comma separated dbs to write the crawled peers to, e.g. `sqlite,dynamodb`, also accepted as
+    /// `--backend`. `aws` is an alias of `dynamodb`, and `memory` keeps the peers in memory only,
+    /// for dry runs. Reads go to the first one. Defaults to DynamoDB.
+    backends: Vec<Backend>,
+    #[arg(long, default_value = "peers_data.db")]
+    /// Path of the sqlite db of the `sqlite` backend. `:memory:` keeps it in memory only.
+    db_path: PathBuf,
+    #[arg(long, default_value_t = NonZeroUsize::new(4).unwrap())]
+    /// Number of connections to the sqlite db of the `sqlite` backend.
+    sqlite_pool_size: NonZeroUsize,
+    #[arg(long)]
+    /// Load AWS credentials from this named profile instead of the default provider chain.
+    aws_profile: Option<String>,
+    #[arg(long, default_value = DEFAULT_TABLE_NAME)]
+    /// DynamoDB table used for every read and write, e.g. one table per chain.
+    dynamo_table: String,
+    #[arg(long, value_delimiter = ',')]
+    /// Comma separated AWS regions to write every crawled peer to, e.g.
+    /// `us-west-2,eu-central-1`, for redundancy. Each region must hold the table. Reads go to the
+    /// first one. Defaults to the region of the AWS config.
+    dynamo_regions: Vec<String>,
+    #[arg(long, value_name = "URL")]
+    /// Send the DynamoDB requests to this endpoint instead of AWS, e.g. `http://localhost:4566`
+    /// for LocalStack or a DynamoDB compatible service.
+    dynamo_endpoint: Option<String>,
+    #[arg(long)]
+    /// Look up peers by id in DynamoDB with strongly consistent reads, which see the peers written
+    /// just before but cost twice as much read capacity.
+    consistent_reads: bool,
+    #[command(flatten)]
+    geo: GeoOpts,
+    #[arg(long, conflicts_with_all = ["geo_locator", "geoip_db", "asn_db"])]
+    /// Don't locate crawled peers at all, leaving their country and city empty, to crawl faster
+    /// and without depending on a locator.
+    no_geo: bool,
+    #[arg(long, default_value_t = NonZeroUsize::new(100).unwrap())]
+    /// Number of crawled peers buffered before they are written to the db.
+    flush_size: NonZeroUsize,
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    /// Longest time a crawled peer stays buffered before being written to the db, in seconds.
+    flush_interval_secs: u64,
+    #[arg(long, default_value_t = NonZeroUsize::new(10_000).unwrap())]
+    /// Number of crawled peers queued for the db writer. Once it's full, crawling waits for the
+    /// db to catch up.
+    write_buffer: NonZeroUsize,
+    #[arg(long, default_value_t = NonZeroUsize::new(1).unwrap())]
+    /// Number of tasks writing batches of crawled peers to the db concurrently. More than one
+    /// helps when the db, like DynamoDB, handles concurrent writes faster than sequential ones.
+    db_writer_tasks: NonZeroUsize,
+    #[arg(long, default_value_t = 7, value_parser = clap::value_parser!(u64).range(1..))]
+    /// Days after their last write that DynamoDB expires the peers, through their `ttl` attribute.
+    ttl_days: u64,
+    #[arg(long, default_value_t = 300)]
+    /// Shortest time between two dials of the same peer, in seconds.
+    recrawl_cooldown_secs: u64,
+    #[arg(long, value_enum, default_value_t = Chain::Mainnet)]
+    /// Network to crawl, whose genesis and fork id the handshakes advertise. Peers of other
+    /// networks fail the ETH handshake.
+    chain: Chain,
+    #[arg(long, value_delimiter = ',', value_name = "ENODE")]
+    /// Comma separated enode URLs discv4 starts from, instead of the canonical bootnodes of
+    /// `--chain`.
+    bootnodes: Vec<NodeRecord>,
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u64).range(1..))]
+    /// Seconds between the discv4 lookups refilling the routing table with new nodes. Lower
+    /// values find peers faster at the cost of more discovery traffic.
+    discovery_refresh_secs: u64,
+    #[arg(long)]
+    /// Exit after a single discovery pass, once no new peer shows up, instead of crawling forever.
+    once: bool,
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    /// Stop crawling once this many distinct peers have been handshaked.
+    max_peers: Option<u64>,
+    #[arg(long, default_value_t = 24, value_parser = clap::value_parser!(u8).range(0..=32))]
+    /// Prefix length of the IPv4 subnets whose dials are rate limited together.
+    subnet_rate_prefix: u8,
+    #[arg(long, default_value_t = 48, value_parser = clap::value_parser!(u8).range(0..=128))]
+    /// Prefix length of the IPv6 subnets whose dials are rate limited together.
+    subnet_rate_prefix_v6: u8,
+    #[arg(long, default_value_t = 60, value_parser = clap::value_parser!(u64).range(1..))]
+    /// Dials per minute allowed to each subnet, after a burst of a few dials.
+    subnet_dials_per_min: u64,
+    #[arg(long, default_value_t = 2)]
+    /// How many times a peer is dialed again after a transient handshake failure, like a reset
+    /// connection. Peers rejecting the handshake are never retried.
+    handshake_retries: u32,
+    #[arg(long, default_value_t = 30)]
+    /// Delay before dialing again a peer whose handshake failed transiently, in seconds.
+    handshake_retry_delay_secs: u64,
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    /// Drop peers whose address is private, loopback, link-local or bogon.
+    skip_private_ips: bool,
+    #[arg(long, value_name = "PATH")]
+    /// File listing addresses never to dial, one IP address or CIDR range per line, e.g. to
+    /// honor opt-outs. Blank lines and lines starting with `#` are ignored.
+    deny_ips_file: Option<PathBuf>,
+    #[arg(long, value_name = "PATH")]
+    /// File listing the only addresses to dial, in the format of `--deny-ips-file`, which still
+    /// takes precedence. Inbound sessions are recorded whatever their address.
+    allow_ips_file: Option<PathBuf>,
+    #[arg(long, default_value_t = 1.0, value_parser = parse_sample_rate)]
+    /// Fraction of the discovered peers, between 0 and 1, that are handshaked and recorded, each
+    /// peer being kept at random. Static and re-crawled peers are always dialed.
+    sample_rate: f64,
+    #[arg(long)]
+    /// Seed of the `--sample-rate` draws. Crawls with the same seed keep the same peers only as
+    /// long as they discover them in the same order. Random by default.
+    sample_seed: Option<u64>,
+    #[arg(long, value_name = "HOST:PORT")]
+    /// Dial peers through a SOCKS5 proxy. Only the TCP handshakes go through it: discv4 and DNS
+    /// discovery still use direct UDP and DNS traffic, and inbound sessions are unaffected.
+    socks5: Option<String>,
+    #[arg(long, default_value = DEFAULT_CRAWLER_ID, value_parser = parse_crawler_id)]
+    /// Client version advertised to the peers in the crawler's `Hello`, so node operators can
+    /// tell who is connecting, e.g. `reth-crawler/0.1 research@example.com`. At most 256 bytes.
+    crawler_id: String,
+    #[arg(long, value_parser = parse_session_id)]
+    /// Id tagging every peer recorded by this crawl, to tell the results of repeated crawls
+    /// apart. A new UUID is generated on every run by default. Peers are stored once, so a peer
+    /// recorded again belongs to the latest crawl, except the chain status updates of
+    /// `--recrawl-chain-status-only`, which keep its id.
+    session_id: Option<String>,
+    #[arg(long, value_name = "PATH")]
+    /// File holding the crawler's node key as hex, generated and saved there if it doesn't exist,
+    /// so the crawler keeps the same node id across restarts, e.g. for node operators to
+    /// allowlist it. A new key is generated on every run by default.
+    node_key_file: Option<PathBuf>,
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    /// Prune old peers from the db every this many hours. Disabled by default.
+    prune_interval_hours: Option<u64>,
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(i64).range(0..))]
+    /// Age, in days since they were last seen, of the peers deleted when pruning.
+    prune_older_than_days: i64,
+    #[arg(long, value_parser = humantime::parse_duration)]
+    /// Record the number of stored peers in the peer count history this often, e.g. `5m`, and
+    /// once more on shutdown. Disabled by default.
+    count_history_interval: Option<Duration>,
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    /// Log a one line summary of the crawl every this many seconds, and once more on shutdown:
+    /// peers discovered, written, failures and active dials. Disabled by default.
+    metrics_log_interval_secs: Option<u64>,
+    #[arg(long, env = "KAFKA_BROKERS", requires = "kafka_topic")]
+    /// Comma separated Kafka brokers to publish every crawled peer to, as JSON.
+    kafka_brokers: Option<String>,
+    #[arg(long, env = "KAFKA_TOPIC", requires = "kafka_brokers")]
+    /// Kafka topic the crawled peers are published to, keyed by peer id.
+    kafka_topic: Option<String>,
+    #[arg(long, value_name = "PATH")]
+    /// Keep a file listing the enode URLs of the live peers, one per line, e.g. for a node to use
+    /// as static peers.
+    enode_list_file: Option<PathBuf>,
+    #[arg(long, default_value = "1m", value_parser = humantime::parse_duration)]
+    /// How often the `--enode-list-file` is rewritten.
+    enode_list_interval: Duration,
+    #[arg(long, default_value = "1h", value_parser = humantime::parse_duration)]
+    /// How long after its last crawl a peer stays in the `--enode-list-file`.
+    enode_list_max_age: Duration,
+    #[arg(long)]
+    /// Write every successfully handshaked peer to stdout as a JSON line as soon as it's crawled,
+    /// in addition to the db. Logs always go to stderr.
+    stdout_json: bool,
+    #[arg(long)]
+    /// Log every discovered peer before its handshake, and the reason it was dropped if it was.
+    trace_discovery: bool,
+    #[arg(long, value_parser = parse_hash)]
+    /// Only record the peers whose genesis block has this hash, e.g. to drop peers of other
+    /// networks. Every peer is recorded by default.
+    expected_genesis: Option<String>,
+    #[arg(long, value_delimiter = ',', value_name = "SUBSTRING")]
+    /// Drop the peers whose client name, e.g. `geth` or `nethermind`, contains any of these
+    /// comma separated substrings. Matching ignores case.
+    exclude_clients: Vec<String>,
+    #[arg(long, value_delimiter = ',', value_name = "SUBSTRING")]
+    /// Only record the peers whose client name contains one of these comma separated substrings.
+    /// Matching ignores case.
+    only_clients: Vec<String>,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_name = "COUNTRY",
+        conflicts_with = "no_geo"
+    )]
+    /// Only record the peers located in one of these comma separated countries, by name or ISO
+    /// code, e.g. `US,DE` or `Germany`. Matching ignores case. Only the `maxmind` locator knows
+    /// ISO codes, and peers of unknown country are dropped.
+    only_countries: Vec<String>,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_name = "ASN",
+        value_parser = parse_asn,
+        requires = "asn_db"
+    )]
+    /// Only record the peers whose address is announced by one of these comma separated
+    /// autonomous systems, e.g. `AS16509,24940`, as found in `--asn-db`.
+    only_asns: Vec<u32>,
+    #[arg(long)]
+    /// Record the RLP encoded `Hello` and `Status` messages of the dialed peers, as hex, to
+    /// re-parse them offline. Off by default since it makes every record much larger. Inbound
+    /// sessions don't expose their `Hello`, so they are never recorded.
+    store_raw_handshake: bool,
+    #[arg(long)]
+    /// Record the peers that accept our connection but disconnect during the handshake, with the
+    /// reason they gave, e.g. `Too many peers`. Only their id, address and, past the `Hello`, client
+    /// version and capabilities are known, so these records are partial. Peers already fully
+    /// handshaked since the crawler started keep their record.
+    record_disconnects: bool,
+    #[arg(long, value_parser = humantime::parse_duration)]
+    /// Dial again the known peers seen the longest time ago this often, e.g. `10m`, to keep their
+    /// `last_seen` fresh. Disabled by default.
+    recrawl_interval: Option<Duration>,
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(i32).range(1..))]
+    /// Number of known peers dialed again every `--recrawl-interval`.
+    recrawl_batch_size: i32,
+    #[arg(long, requires = "recrawl_interval")]
+    /// Only refresh the best block and its number, total difficulty and `last_seen` of the
+    /// re-crawled peers, leaving the rest of their record, e.g. their client version, as it was.
+    recrawl_chain_status_only: bool,
+    #[arg(long, default_value_t = 3)]
+    /// Flag peers as `sentry` nodes once this many distinct peer ids have been seen at their
+    /// address with their client version. 0 disables this heuristic.
+    sentry_min_ids_per_ip: usize,
+    #[arg(long, value_delimiter = ',', value_name = "CAPABILITY")]
+    /// Flag peers advertising exactly these comma separated capabilities, e.g. `eth/66,eth/67`,
+    /// as `proxy` nodes. Disabled by default.
+    proxy_capabilities: Vec<String>,
+    #[arg(long, value_name = "PATH")]
+    /// File listing peers to dial in a loop, one enode URL or ENR per line. Blank lines and lines
+    /// starting with `#` are ignored; invalid entries are logged and skipped.
+    static_peers_file: Option<PathBuf>,
+    #[arg(long, default_value = "10m", value_parser = humantime::parse_duration)]
+    /// How often the peers of `--static-peers-file` are dialed. Dials closer than
+    /// `--recrawl-cooldown-secs` to the previous one are skipped.
+    static_peers_interval: Duration,
+    #[arg(long, requires = "static_peers_file")]
+    /// Dial only the peers of `--static-peers-file`, ignoring discv4, DNS discovery and inbound
+    /// sessions, e.g. to monitor the uptime of a known fleet.
+    no_discovery: bool,
+}
+
+#[derive(Args)]
+struct LookupOpts {
+    #[command(flatten)]
+    db: DbOpts,
+    #[arg(long, conflicts_with = "ip", required_unless_present = "ip")]
+    /// Id of the peer to look up.
+    id: Option<String>,
+    #[arg(long)]
+    /// Address of the peers to look up.
+    ip: Option<String>,
+}
+
+#[derive(Args)]
+struct StatsOpts {
+    #[command(flatten)]
+    db: DbOpts,
+    #[arg(long, value_parser = humantime::parse_duration)]
+    /// Also print the peer count history recorded within this long, e.g. `7d`.
+    count_history: Option<Duration>,
+    #[arg(long, value_parser = humantime::parse_duration)]
+    /// Only count the peers seen within this long, e.g. `24h`. The count history is unaffected.
+    since: Option<Duration>,
+}
+
+#[derive(Args)]
+struct GeolocateOpts {
+    #[command(flatten)]
+    db: DbOpts,
+    #[command(flatten)]
+    geo: GeoOpts,
+    #[arg(long)]
+    /// Locate every peer again, not only the peers without a country.
+    all: bool,
+}
+
+#[derive(Args)]
+struct ClearOpts {
+    #[command(flatten)]
+    db: DbOpts,
+    #[arg(long)]
+    /// Confirm that every peer must be deleted. Nothing is deleted without it.
+    confirm: bool,
+}
+
+#[derive(Args)]
+struct ExportOpts {
+    #[command(flatten)]
+    db: DbOpts,
+    #[arg(long, default_value = "peers.json")]
+    /// File the peers are written to, replaced if it exists.
+    output: PathBuf,
+    #[arg(long)]
+    /// Compress the export with gzip, appending `.gz` to `--output`.
+    gzip: bool,
+    #[arg(long)]
+    /// Only export the geolocated peers, leaving out those with an unknown country.
+    require_geo: bool,
+    #[arg(long, value_parser = humantime::parse_duration)]
+    /// Only export the peers seen within this long, e.g. `7d`.
+    since: Option<Duration>,
+}
+
+#[derive(Args)]
+struct ImportOpts {
+    #[command(flatten)]
+    db: DbOpts,
+    #[arg(long, default_value = "peers.json")]
+    /// File the peers are read from, decompressed if it ends with `.gz`.
+    input: PathBuf,
+    #[arg(long, value_enum)]
+    /// Format of `--input`. Detected from its extension by default: `.csv` for CSV, `.jsonl`,
+    /// `.json` or `.ndjson` for JSON lines.
+    format: Option<ImportFormat>,
+}
+
+#[derive(Args)]
+struct VerifyOpts {
+    #[command(flatten)]
+    db: DbOpts,
+    #[arg(long)]
+    /// Delete every record of the peers breaking any invariant.
+    fix: bool,
+}
+
+/// How peers are located.
+#[derive(Args)]
+struct GeoOpts {
+    #[arg(long, value_enum, default_value_t = GeoLocatorKind::IpApi)]
+    /// Where to look up the country and city of peers.
+    geo_locator: GeoLocatorKind,
+    #[arg(long, required_if_eq("geo_locator", "maxmind"))]
+    /// Path to a MaxMind GeoLite2 City database, used by the `maxmind` locator.
+    geoip_db: Option<PathBuf>,
+    #[arg(long, value_name = "PATH")]
+    /// Path to a MaxMind GeoLite2 ASN database to also record the autonomous system and
+    /// organization hosting each peer, whatever the `--geo-locator`.
+    asn_db: Option<PathBuf>,
+    #[arg(long, default_value_t = NonZeroUsize::new(100_000).unwrap())]
+    /// Maximum number of peer locations kept in memory.
+    geo_cache_size: NonZeroUsize,
+    #[arg(long, default_value_t = 86_400)]
+    /// How long a cached peer location stays valid, in seconds.
+    geo_cache_ttl_secs: u64,
+}
+
+impl GeoOpts {
+    fn locator(&self) -> eyre::Result<Arc<dyn GeoLocator>> {
+        geo::new_locator(
+            self.geo_locator,
+            self.geoip_db.as_deref(),
+            self.asn_db.as_deref(),
+            self.geo_cache_size,
+            Duration::from_secs(self.geo_cache_ttl_secs),
+        )
+    }
+}
+
+/// Which db a command reads from.
+#[derive(Args)]
+struct DbOpts {
+    #[arg(long)]
+    /// Deprecated alias of `--backend sqlite`.
+    local_db: bool,
+    #[arg(long, value_enum, conflicts_with = "local_db")]
+    /// Db to read, e.g. the `sqlite` db written by `crawl --backend sqlite`. `aws` is an alias of
+    /// `dynamodb`. Defaults to DynamoDB.
+    backend: Option<Backend>,
+    #[arg(long, default_value = "peers_data.db")]
+    /// Path of the sqlite db of the `sqlite` backend.
+    db_path: PathBuf,
+    #[arg(long)]
+    /// Load AWS credentials from this named profile instead of the default provider chain.
+    aws_profile: Option<String>,
+    #[arg(long, default_value = DEFAULT_TABLE_NAME)]
+    /// DynamoDB table used for every read and write, e.g. one table per chain.
+    dynamo_table: String,
+    #[arg(long, value_name = "URL")]
+    /// Send the DynamoDB requests to this endpoint instead of AWS, e.g. `http://localhost:4566`
+    /// for LocalStack.
+    dynamo_endpoint: Option<String>,
+    #[arg(long)]
+    /// Look up peers by id in DynamoDB with strongly consistent reads, which see the peers written
+    /// just before but cost twice as much read capacity.
+    consistent_reads: bool,
+}
+
+impl DbOpts {
+    async fn open(&self) -> eyre::Result<Arc<dyn PeerDB>> {
+        let backend = match (self.backend, self.local_db) {
+            (Some(backend), _) => backend,
+            (None, true) => Backend::Sqlite,
+            (None, false) => Backend::Dynamodb,
+        };
+        let config = DbConfig {
+            db_path: self.db_path.clone(),
+            sqlite_pool_size: 1,
+            aws_profile: self.aws_profile.clone(),
+            dynamo_table: self.dynamo_table.clone(),
+            dynamo_regions: vec![],
+            dynamo_endpoint: self.dynamo_endpoint.clone(),
+            consistent_reads: self.consistent_reads,
+        };
+        CrawlerFactory::open_backend(backend, &config, false).await
+    }
+}
+
+/// Client version the crawler advertises unless `--crawler-id` is set.
+const DEFAULT_CRAWLER_ID: &str = concat!("reth-crawler/v", env!("CARGO_PKG_VERSION"));
+/// Longest `--crawler-id`, in bytes. The devp2p spec sets no limit, but client versions are
+/// short strings and peers may drop a larger `Hello`.
+const MAX_CRAWLER_ID_LEN: usize = 256;
+
+/// Check that `rate` is a fraction between 0 and 1.
+fn parse_sample_rate(rate: &str) -> Result<f64, String> {
+    let rate: f64 = rate
+        .parse()
+        .map_err(|e: std::num::ParseFloatError| e.to_string())?;
+    if (0.0..=1.0).contains(&rate) {
+        Ok(rate)
+    } else {
+        Err(format!("{rate} isn't between 0 and 1"))
+    }
+}
+
+/// Check that `id` is a non empty session id without control characters.
+fn parse_session_id(id: &str) -> Result<String, String> {
+    if id.is_empty() {
+        Err("the session id can't be empty".to_string())
+    } else if id.chars().any(char::is_control) {
+        Err("the session id can't contain control characters".to_string())
+    } else {
+        Ok(id.to_string())
+    }
+}
+
+/// Check that `id` is a non empty client version of at most [`MAX_CRAWLER_ID_LEN`] bytes without
+/// control characters.
+fn parse_crawler_id(id: &str) -> Result<String, String> {
+    if id.is_empty() {
+        Err("the crawler id can't be empty".to_string())
+    } else if id.len() > MAX_CRAWLER_ID_LEN {
+        Err(format!(
+            "the crawler id is {} bytes long, the limit is {MAX_CRAWLER_ID_LEN}",
+            id.len()
+        ))
+    } else if id.chars().any(char::is_control) {
+        Err("the crawler id can't contain control characters".to_string())
+    } else {
+        Ok(id.to_string())
+    }
+}
+
+/// Parse an autonomous system number, with or without `AS` prefix.
+fn parse_asn(asn: &str) -> Result<u32, String> {
+    let digits = asn
+        .strip_prefix("AS")
+        .or_else(|| asn.strip_prefix("as"))
+        .unwrap_or(asn);
+    digits
+        .parse()
+        .map_err(|_| format!("{asn} is not an autonomous system number"))
+}
+
+/// Check that `hash` is a 32 bytes hex hash, with or without `0x` prefix.
+fn parse_hash(hash: &str) -> Result<String, String> {
+    let digits = hash.strip_prefix("0x").unwrap_or(hash);
+    if digits.len() == 64 && digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(hash.to_string())
+    } else {
+        Err(format!("{hash} is not a 32 bytes hex hash"))
+    }
+}
+
+fn init_tracing(log_format: LogFormat) {
+    // stdout is left to the commands' output, e.g. the JSON lines of `--stdout-json`
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(std::io::stderr);
+    match log_format {
+        LogFormat::Pretty => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
+
+/// Write every peer of `db`, or every geolocated one if `require_geo`, to `output`, one JSON
+/// object per line, returning how many. Peers are streamed page by page, so memory stays bounded
+/// whatever the size of the db. With `since`, the peers last seen before it are left out.
+async fn export_peers(
+    db: &dyn PeerDB,
+    require_geo: bool,
+    since: Option<DateTime<Utc>>,
+    output: &mut impl Write,
+) -> eyre::Result<usize> {
+    // compared as strings, like `PeerDB::peers_seen_between` does
+    let since = since.map(|since| since.to_string());
+    let mut exported = 0;
+    let mut peers = db.peers_stream(require_geo);
+    while let Some(peer) = peers.next().await {
+        let peer = peer?;
+        if since.as_ref().is_some_and(|since| peer.last_seen < *since) {
+            continue;
+        }
+        serde_json::to_writer(&mut *output, &peer)?;
+        output.write_all(b"\n")?;
+        exported += 1;
+    }
+    Ok(exported)
+}
+
+/// Unwrap the result of a startup step, e.g. reading a file named by an option, printing its
+/// error and exiting instead of panicking, as such errors are the operator's to fix.
+fn or_exit<T>(result: eyre::Result<T>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("{:#}", err);
+        std::process::exit(1);
+    })
 }
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt::init();
-
-    let cli = Cli::parse();
+    let args = config::args_with_config(Cli::command(), std::env::args_os().collect())
+        .unwrap_or_else(|err| {
+            eprintln!("{:#}", err);
+            std::process::exit(2);
+        });
+    let cli = Cli::parse_from(args);
+    init_tracing(cli.log_format);
+    if let Some(path) = &cli.config {
+        info!("Loaded the options of {}", path.display());
+    }
 
     match &cli.command {
         Commands::Crawl(opts) => {
-            let (_, _, _) = CrawlerFactory::new()
+            let geo = (!opts.no_geo).then(|| or_exit(opts.geo.locator()));
+            let session_id = opts
+                .session_id
+                .clone()
+                .unwrap_or_else(|| Uuid::new_v4().to_string());
+            info!("Starting crawl session {}", session_id);
+            let config = CrawlerConfig {
+                backends: match (opts.backends.is_empty(), opts.local_db) {
+                    (false, _) => opts.backends.clone(),
+                    (true, true) => vec![Backend::Sqlite],
+                    (true, false) => vec![Backend::Dynamodb],
+                },
+                db: DbConfig {
+                    db_path: opts.db_path.clone(),
+                    sqlite_pool_size: opts.sqlite_pool_size.get(),
+                    aws_profile: opts.aws_profile.clone(),
+                    dynamo_table: opts.dynamo_table.clone(),
+                    dynamo_regions: opts.dynamo_regions.clone(),
+                    dynamo_endpoint: opts.dynamo_endpoint.clone(),
+                    consistent_reads: opts.consistent_reads,
+                },
+                geo,
+                flush_size: opts.flush_size.get(),
+                flush_interval: Duration::from_secs(opts.flush_interval_secs),
+                write_buffer: opts.write_buffer.get(),
+                db_writer_tasks: opts.db_writer_tasks,
+                ttl_days: opts.ttl_days,
+                recrawl_cooldown: Duration::from_secs(opts.recrawl_cooldown_secs),
+                chain: opts.chain,
+                once: opts.once,
+                max_peers: opts.max_peers.map(|max_peers| max_peers as usize),
+                subnet_limiter: SubnetLimiter::new(
+                    opts.subnet_rate_prefix,
+                    opts.subnet_rate_prefix_v6,
+                    opts.subnet_dials_per_min,
+                ),
+                retry_queue: RetryQueue::new(
+                    opts.handshake_retries,
+                    Duration::from_secs(opts.handshake_retry_delay_secs),
+                ),
+                skip_private_ips: opts.skip_private_ips,
+                dial_policy: or_exit(DialPolicy::load(
+                    opts.deny_ips_file.as_deref(),
+                    opts.allow_ips_file.as_deref(),
+                )),
+                sampler: PeerSampler::new(opts.sample_rate, opts.sample_seed),
+                socks5: opts.socks5.clone(),
+                crawler_id: opts.crawler_id.clone(),
+                session_id,
+                prune_interval: opts
+                    .prune_interval_hours
+                    .map(|hours| Duration::from_secs(hours * 3600)),
+                prune_older_than_days: opts.prune_older_than_days,
+                count_history_interval: opts.count_history_interval,
+                metrics_log_interval: opts.metrics_log_interval_secs.map(Duration::from_secs),
+                kafka: or_exit(
+                    opts.kafka_brokers
+                        .as_deref()
+                        .zip(opts.kafka_topic.clone())
+                        .map(|(brokers, topic)| KafkaSink::new(brokers, topic))
+                        .transpose(),
+                ),
+                enode_list: opts.enode_list_file.clone().map(|path| {
+                    EnodeList::new(path, opts.enode_list_interval, opts.enode_list_max_age)
+                }),
+                stdout_json: opts.stdout_json,
+                trace_discovery: opts.trace_discovery,
+                expected_genesis: opts.expected_genesis.clone(),
+                client_filter: ClientFilter::new(
+                    opts.exclude_clients.clone(),
+                    opts.only_clients.clone(),
+                ),
+                location_filter: LocationFilter::new(
+                    opts.only_countries.clone(),
+                    opts.only_asns.clone(),
+                ),
+                store_raw_handshake: opts.store_raw_handshake,
+                record_disconnects: opts.record_disconnects,
+                recrawl_interval: opts.recrawl_interval,
+                recrawl_batch_size: opts.recrawl_batch_size,
+                recrawl_chain_status_only: opts.recrawl_chain_status_only,
+                role_detector: RoleDetector::new(
+                    opts.sentry_min_ids_per_ip,
+                    opts.proxy_capabilities.clone(),
+                ),
+                static_peers: or_exit(
+                    opts.static_peers_file
+                        .as_deref()
+                        .map(load_static_peers)
+                        .transpose(),
+                )
+                .unwrap_or_default(),
+                static_peers_interval: opts.static_peers_interval,
+                discovery: !opts.no_discovery,
+            };
+            let key = match &opts.node_key_file {
+                Some(path) => or_exit(load_or_create_node_key(path)),
+                None => rng_secret_key(),
+            };
+            let bootnodes = if opts.bootnodes.is_empty() {
+                opts.chain.bootnodes()
+            } else {
+                opts.bootnodes.clone()
+            };
+            let lookup_interval = Duration::from_secs(opts.discovery_refresh_secs);
+            CrawlerFactory::new(key, &opts.crawler_id, bootnodes, lookup_interval)
                 .await
-                .make(opts.local_db)
+                .make(config)
                 .await
+                .unwrap()
                 .run()
-                .await;
+                .await
+                .unwrap();
+            if opts.once {
+                // keeps stdout to the JSON lines of the peers if they're written there
+                let mut summary: Box<dyn Write> = if opts.stdout_json {
+                    Box::new(std::io::stderr())
+                } else {
+                    Box::new(std::io::stdout())
+                };
+                writeln!(
+                    summary,
+                    "Crawled {} peers ({} duplicate dials suppressed, {} non public peers skipped, {} dials denied, {} peers of other networks skipped)",
+                    METRICS.crawled_peers.load(Ordering::Relaxed),
+                    METRICS.suppressed_dials.load(Ordering::Relaxed),
+                    METRICS.skipped_private_peers.load(Ordering::Relaxed),
+                    METRICS.denied_dials.load(Ordering::Relaxed),
+                    METRICS.genesis_mismatches.load(Ordering::Relaxed)
+                )
+                .unwrap();
+                writeln!(
+                    summary,
+                    "Failed handshakes: {} timed out, {} refused, {} protocol mismatches, {} decode errors, {} disconnected, {} other",
+                    METRICS.handshake_timeouts.load(Ordering::Relaxed),
+                    METRICS.handshake_refusals.load(Ordering::Relaxed),
+                    METRICS.handshake_protocol_mismatches.load(Ordering::Relaxed),
+                    METRICS.handshake_decode_errors.load(Ordering::Relaxed),
+                    METRICS.handshake_disconnects.load(Ordering::Relaxed),
+                    METRICS.handshake_other_failures.load(Ordering::Relaxed)
+                )
+                .unwrap();
+            }
+        }
+        Commands::Lookup(opts) => {
+            let db = opts.db.open().await.unwrap();
+            let peers = match (&opts.id, &opts.ip) {
+                (Some(id), _) => db.node_by_id(id.clone()).await,
+                (None, Some(ip)) => db.node_by_ip(ip.clone()).await,
+                (None, None) => unreachable!("clap requires either --id or --ip"),
+            }
+            .unwrap();
+            match peers {
+                Some(peers) if !peers.is_empty() => {
+                    for peer in peers {
+                        println!("{}", serde_json::to_string_pretty(&peer).unwrap());
+                    }
+                }
+                _ => {
+                    eprintln!("No peer found");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Stats(opts) => {
+            let stored = opts.db.open().await.unwrap();
+            // the peers of the window are counted by an in memory db, with the same queries
+            let db: Arc<dyn PeerDB> = match opts.since {
+                Some(since) => {
+                    let end = Utc::now();
+                    let start = end - chrono::Duration::from_std(since).unwrap();
+                    let window = InMemoryPeerDB::new();
+                    window
+                        .add_peers(
+                            stored.peers_seen_between(start, end, None).await.unwrap(),
+                            None,
+                        )
+                        .await
+                        .unwrap();
+                    Arc::new(window)
+                }
+                None => stored.clone(),
+            };
+            println!("Peers: {}", db.count_peers().await.unwrap());
+            println!(
+                "Distinct peer ids: {}",
+                db.distinct_peer_id_count().await.unwrap()
+            );
+            println!("Distinct IPs: {}", db.distinct_ip_count().await.unwrap());
+            println!("Snap servers: {}", db.snap_server_count().await.unwrap());
+            println!();
+
+            let mut clients: Vec<_> = db
+                .client_distribution()
+                .await
+                .unwrap()
+                .into_iter()
+                .collect();
+            clients.sort_by(|(a_name, a_count), (b_name, b_count)| {
+                b_count.cmp(a_count).then(a_name.cmp(b_name))
+            });
+            println!("{:<12} {:>8}", "client", "peers");
+            for (client, count) in clients {
+                println!("{:<12} {:>8}", client, count);
+            }
+            println!();
+
+            let mut countries: Vec<_> = db
+                .country_distribution()
+                .await
+                .unwrap()
+                .into_iter()
+                .collect();
+            countries.sort_by(|(a_name, a_count), (b_name, b_count)| {
+                b_count.cmp(a_count).then(a_name.cmp(b_name))
+            });
+            println!("{:<24} {:>8}", "country", "peers");
+            for (country, count) in countries {
+                println!("{:<24} {:>8}", country, count);
+            }
+            println!();
+
+            let mut capabilities: Vec<_> = db
+                .capability_distribution()
+                .await
+                .unwrap()
+                .into_iter()
+                .collect();
+            capabilities.sort_by(|(a_name, a_count), (b_name, b_count)| {
+                b_count.cmp(a_count).then(a_name.cmp(b_name))
+            });
+            println!("{:<12} {:>8}", "capability", "peers");
+            for (capability, count) in capabilities {
+                println!("{:<12} {:>8}", capability, count);
+            }
+            println!();
+
+            let mut reasons: Vec<_> = db
+                .disconnect_reason_distribution()
+                .await
+                .unwrap()
+                .into_iter()
+                .collect();
+            if !reasons.is_empty() {
+                reasons.sort_by(|(a_reason, a_count), (b_reason, b_count)| {
+                    b_count.cmp(a_count).then(a_reason.cmp(b_reason))
+                });
+                println!("{:<40} {:>8}", "disconnect reason", "peers");
+                for (reason, count) in reasons {
+                    println!("{:<40} {:>8}", reason, count);
+                }
+                println!();
+            }
+
+            let mut asns: Vec<_> = db.asn_distribution().await.unwrap().into_iter().collect();
+            if !asns.is_empty() {
+                asns.sort_by(|(a_asn, a_count), (b_asn, b_count)| {
+                    b_count.cmp(a_count).then(a_asn.cmp(b_asn))
+                });
+                println!("{:<40} {:>8}", "autonomous system", "peers");
+                for (asn, count) in asns {
+                    println!("{:<40} {:>8}", asn, count);
+                }
+                println!();
+            }
+
+            match handshake_latency_percentiles(db.as_ref()).await.unwrap() {
+                Some(LatencyPercentiles { p50, p90, p99 }) => {
+                    println!("Handshake latency: p50 {p50} ms, p90 {p90} ms, p99 {p99} ms")
+                }
+                None => println!("Handshake latency: no measured peers"),
+            }
+
+            if let Some(count_history) = opts.count_history {
+                let since = Utc::now() - chrono::Duration::from_std(count_history).unwrap();
+                println!();
+                println!("{:<40} {:>8}", "timestamp", "peers");
+                for snapshot in stored.count_history(since).await.unwrap() {
+                    println!("{:<40} {:>8}", snapshot.timestamp, snapshot.count);
+                }
+            }
+        }
+        Commands::Geolocate(opts) => {
+            let db = opts.db.open().await.unwrap();
+            let geo = or_exit(opts.geo.locator());
+            let mut enriched = 0;
+            let mut peers = db.peers_stream(false);
+            while let Some(peer) = peers.next().await {
+                let peer = peer.unwrap();
+                if !opts.all && !peer.country.is_empty() {
+                    continue;
+                }
+                let Ok(ip) = peer.address.parse::<IpAddr>() else {
+                    continue;
+                };
+                let Some(GeoInfo { country, city, .. }) = geo.locate(ip).await else {
+                    continue;
+                };
+                if country == peer.country && city == peer.city {
+                    continue;
+                }
+                match db.update_location(peer.id.clone(), country, city).await {
+                    Ok(true) => enriched += 1,
+                    Ok(false) => {}
+                    Err(e) => eprintln!("Failed to update the location of peer {}: {}", peer.id, e),
+                }
+            }
+            println!("Enriched {} peers", enriched);
+        }
+        Commands::Clear(opts) => {
+            if !opts.confirm {
+                eprintln!("Refusing to delete every peer without --confirm");
+                std::process::exit(1);
+            }
+            let db = opts.db.open().await.unwrap();
+            db.clear().await.unwrap();
+            println!("Deleted every peer");
+        }
+        Commands::Export(opts) => {
+            let db = opts.db.open().await.unwrap();
+            let since = opts
+                .since
+                .map(|since| Utc::now() - chrono::Duration::from_std(since).unwrap());
+            let mut path = opts.output.clone().into_os_string();
+            if opts.gzip {
+                path.push(".gz");
+            }
+            let mut file = BufWriter::new(File::create(&path).unwrap());
+            let exported = if opts.gzip {
+                let mut encoder = GzEncoder::new(file, Compression::default());
+                let exported = export_peers(db.as_ref(), opts.require_geo, since, &mut encoder)
+                    .await
+                    .unwrap();
+                // dropping the encoder would write its trailer but swallow the errors
+                encoder.finish().unwrap().flush().unwrap();
+                exported
+            } else {
+                let exported = export_peers(db.as_ref(), opts.require_geo, since, &mut file)
+                    .await
+                    .unwrap();
+                file.flush().unwrap();
+                exported
+            };
+            println!(
+                "Exported {} peers to {}",
+                exported,
+                PathBuf::from(path).display()
+            );
+        }
+        Commands::Import(opts) => {
+            let Some(format) = opts.format.or_else(|| ImportFormat::detect(&opts.input)) else {
+                eprintln!(
+                    "Can't tell the format of {} from its extension, set --format",
+                    opts.input.display()
+                );
+                std::process::exit(1);
+            };
+            let db = opts.db.open().await.unwrap();
+            let input = import::open(&opts.input).unwrap();
+            let report = import_peers(db.as_ref(), input, format).await.unwrap();
+            println!(
+                "Imported {} peers from {}, skipped {} invalid records",
+                report.imported,
+                opts.input.display(),
+                report.skipped
+            );
+        }
+        Commands::Verify(opts) => {
+            let db = opts.db.open().await.unwrap();
+            let report = verify_peers(db.as_ref()).await.unwrap();
+            println!(
+                "Checked {} peers, {} invalid",
+                report.checked,
+                report.invalid_ids.len()
+            );
+            if !report.violations.is_empty() {
+                println!();
+                println!("{:<26} {:>8}", "violation", "peers");
+                for (violation, count) in &report.violations {
+                    println!("{:<26} {:>8}", violation.to_string(), count);
+                }
+            }
+            if opts.fix {
+                let mut deleted = 0;
+                for id in report.invalid_ids {
+                    if db.delete_peer(id).await.unwrap() {
+                        deleted += 1;
+                    }
+                }
+                println!("Deleted {} invalid peers", deleted);
+            }
         }
     }
 }
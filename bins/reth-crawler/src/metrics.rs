@@ -0,0 +1,92 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters describing the crawler activity, shared by the whole process.
+pub static METRICS: CrawlerMetrics = CrawlerMetrics::new();
+
+pub struct CrawlerMetrics {
+    /// Peers found by discovery, the static peers and the re-crawl, before they are filtered.
+    pub discovered_peers: AtomicU64,
+    /// Peers successfully handshaked and queued for writing.
+    pub crawled_peers: AtomicU64,
+    /// Geolocation lookups answered by the cache.
+    pub geo_cache_hits: AtomicU64,
+    /// Geolocation lookups that had to query the locator.
+    pub geo_cache_misses: AtomicU64,
+    /// Dials skipped because the peer was already being dialed or was dialed recently.
+    pub suppressed_dials: AtomicU64,
+    /// Handshakes queued for a retry after a transient failure.
+    pub handshake_retries: AtomicU64,
+    /// Peers dropped because their address isn't publicly routable.
+    pub skipped_private_peers: AtomicU64,
+    /// Dials skipped because `--deny-ips-file` or `--allow-ips-file` denies the address.
+    pub denied_dials: AtomicU64,
+    /// Discovered peers dropped because they weren't drawn by `--sample-rate`.
+    pub sampled_out_peers: AtomicU64,
+    /// Peers dropped because their genesis hash isn't `--expected-genesis`.
+    pub genesis_mismatches: AtomicU64,
+    /// Crawled peers waiting in the queue to the db writer.
+    pub write_queue_depth: AtomicU64,
+    /// Peers written to the db.
+    pub written_peers: AtomicU64,
+    /// Peers the db failed to write.
+    pub write_failures: AtomicU64,
+    /// Dials whose handshake is in progress.
+    pub active_dials: AtomicU64,
+    /// Known peers dialed again by the periodic re-crawl whose handshake failed.
+    pub recrawl_failures: AtomicU64,
+    /// Handshakes failing because the peer didn't answer in time.
+    pub handshake_timeouts: AtomicU64,
+    /// Handshakes failing because the peer refused the TCP connection.
+    pub handshake_refusals: AtomicU64,
+    /// Handshakes failing because the peer speaks another protocol version or network.
+    pub handshake_protocol_mismatches: AtomicU64,
+    /// Handshakes failing on a message that couldn't be decoded.
+    pub handshake_decode_errors: AtomicU64,
+    /// Handshakes failing because the peer disconnected us.
+    pub handshake_disconnects: AtomicU64,
+    /// Handshakes failing for any other reason.
+    pub handshake_other_failures: AtomicU64,
+}
+
+impl CrawlerMetrics {
+    const fn new() -> Self {
+        Self {
+            discovered_peers: AtomicU64::new(0),
+            crawled_peers: AtomicU64::new(0),
+            geo_cache_hits: AtomicU64::new(0),
+            geo_cache_misses: AtomicU64::new(0),
+            suppressed_dials: AtomicU64::new(0),
+            handshake_retries: AtomicU64::new(0),
+            skipped_private_peers: AtomicU64::new(0),
+            denied_dials: AtomicU64::new(0),
+            sampled_out_peers: AtomicU64::new(0),
+            genesis_mismatches: AtomicU64::new(0),
+            write_queue_depth: AtomicU64::new(0),
+            written_peers: AtomicU64::new(0),
+            write_failures: AtomicU64::new(0),
+            active_dials: AtomicU64::new(0),
+            recrawl_failures: AtomicU64::new(0),
+            handshake_timeouts: AtomicU64::new(0),
+            handshake_refusals: AtomicU64::new(0),
+            handshake_protocol_mismatches: AtomicU64::new(0),
+            handshake_decode_errors: AtomicU64::new(0),
+            handshake_disconnects: AtomicU64::new(0),
+            handshake_other_failures: AtomicU64::new(0),
+        }
+    }
+
+    /// Handshakes that failed, for any reason.
+    pub fn handshake_failures(&self) -> u64 {
+        [
+            &self.handshake_timeouts,
+            &self.handshake_refusals,
+            &self.handshake_protocol_mismatches,
+            &self.handshake_decode_errors,
+            &self.handshake_disconnects,
+            &self.handshake_other_failures,
+        ]
+        .iter()
+        .map(|counter| counter.load(Ordering::Relaxed))
+        .sum()
+    }
+}
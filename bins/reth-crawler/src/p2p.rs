@@ -1,42 +1,185 @@
-use futures::StreamExt;
+use alloy_rlp::{Encodable, Header};
+use futures::{SinkExt, StreamExt};
 use reth_ecies::{stream::ECIESStream, util::pk2id};
+use reth_eth_wire::errors::{EthStreamError, P2PHandshakeError, P2PStreamError};
 use reth_eth_wire::{
-    EthMessage, EthStream, HelloMessage, P2PStream, Status, UnauthedEthStream, UnauthedP2PStream,
+    DisconnectReason, EthMessage, EthStream, GetBlockHeaders, HelloMessage, P2PStream, RequestPair,
+    Status, UnauthedEthStream, UnauthedP2PStream,
 };
-use reth_primitives::{Chain, Hardfork, Head, NodeRecord, MAINNET, MAINNET_GENESIS};
+use reth_primitives::{BlockHashOrNumber, ChainSpec, Hardfork, Head, HeadersDirection, NodeRecord};
 use secp256k1::{SecretKey, SECP256K1};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicU64;
+use std::time::{Duration, Instant};
+use thiserror::Error;
 use tokio::net::TcpStream;
+use tokio_socks::tcp::Socks5Stream;
+
+use crate::metrics::METRICS;
 
 type AuthedP2PStream = P2PStream<ECIESStream<TcpStream>>;
 type AuthedEthStream = EthStream<P2PStream<ECIESStream<TcpStream>>>;
 
-// Perform a P2P handshake with a peer
+/// How long to wait for a peer to answer the header request for its best block.
+const BEST_BLOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Perform a P2P handshake with a peer, connecting through the SOCKS5 proxy at `socks5` if set
+// and advertising `crawler_id` as client version. Also returns how long opening the TCP
+// connection took.
 pub async fn handshake_p2p(
     peer: NodeRecord,
     key: SecretKey,
-) -> eyre::Result<(AuthedP2PStream, HelloMessage)> {
-    let outgoing = TcpStream::connect((peer.address, peer.tcp_port)).await?;
-    let ecies_stream = ECIESStream::connect(outgoing, key, peer.id).await?;
+    socks5: Option<&str>,
+    crawler_id: &str,
+) -> Result<(AuthedP2PStream, HelloMessage, Duration), HandshakeError> {
+    let target = SocketAddr::new(peer.address, peer.tcp_port);
+    let started = Instant::now();
+    let outgoing = match socks5 {
+        // once the proxy connected us the stream carries the peer's bytes as is
+        Some(proxy) => Socks5Stream::connect(proxy, target)
+            .await
+            .map_err(|e| HandshakeError::classify(&e))?
+            .into_inner(),
+        None => TcpStream::connect(target)
+            .await
+            .map_err(|e| HandshakeError::classify(&e))?,
+    };
+    let tcp_connect = started.elapsed();
+    let ecies_stream = ECIESStream::connect(outgoing, key, peer.id)
+        .await
+        .map_err(|e| HandshakeError::classify(&e))?;
 
     let our_peer_id = pk2id(&key.public_key(SECP256K1));
-    let our_hello = HelloMessage::builder(our_peer_id).build();
+    let our_hello = HelloMessage::builder(our_peer_id)
+        .client_version(crawler_id)
+        .build();
 
-    Ok(UnauthedP2PStream::new(ecies_stream)
+    let (p2p_stream, their_hello) = UnauthedP2PStream::new(ecies_stream)
         .handshake(our_hello)
-        .await?)
+        .await
+        .map_err(|e| HandshakeError::classify(&e))?;
+    Ok((p2p_stream, their_hello, tcp_connect))
 }
 
-// Perform a ETH Wire handshake with a peer
-pub async fn handshake_eth(p2p_stream: AuthedP2PStream) -> eyre::Result<(AuthedEthStream, Status)> {
-    let fork_filter = MAINNET.fork_filter(Head {
-        timestamp: MAINNET.fork(Hardfork::Shanghai).as_timestamp().unwrap(),
+/// Why a P2P or ETH handshake with a peer failed.
+#[derive(Debug, Error)]
+pub enum HandshakeError {
+    #[error("timed out")]
+    Timeout,
+    #[error("connection refused")]
+    ConnectionRefused,
+    #[error("protocol mismatch: {0}")]
+    ProtocolMismatch(String),
+    #[error("undecodable message: {0}")]
+    DecodeError(String),
+    #[error("disconnected: {0}")]
+    Disconnected(DisconnectReason),
+    #[error("connection failed: {0}")]
+    Io(io::ErrorKind),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl HandshakeError {
+    /// Classify an error returned while connecting or handshaking, looking through its sources.
+    pub fn classify(err: &(dyn std::error::Error + 'static)) -> Self {
+        let mut source = Some(err);
+        while let Some(err) = source {
+            if let Some(handshake_err) = Self::category(err) {
+                return handshake_err;
+            }
+            source = err.source();
+        }
+        Self::Other(err.to_string())
+    }
+
+    fn category(err: &(dyn std::error::Error + 'static)) -> Option<Self> {
+        if let Some(err) = err.downcast_ref::<io::Error>() {
+            return Some(match err.kind() {
+                io::ErrorKind::TimedOut => Self::Timeout,
+                io::ErrorKind::ConnectionRefused => Self::ConnectionRefused,
+                kind => Self::Io(kind),
+            });
+        }
+        if let Some(tokio_socks::Error::Io(err)) = err.downcast_ref::<tokio_socks::Error>() {
+            return Self::category(err);
+        }
+        if let Some(err) = err.downcast_ref::<EthStreamError>() {
+            return match err {
+                EthStreamError::P2PStreamError(err) => Self::category(err),
+                EthStreamError::EthHandshakeError(err) => {
+                    Some(Self::ProtocolMismatch(err.to_string()))
+                }
+                err => Some(Self::DecodeError(err.to_string())),
+            };
+        }
+        if let Some(err) = err.downcast_ref::<P2PStreamError>() {
+            return match err {
+                P2PStreamError::Io(err) => Self::category(err),
+                P2PStreamError::HandshakeError(err) => Self::category(err),
+                P2PStreamError::Disconnected(reason) => Some(Self::Disconnected(*reason)),
+                P2PStreamError::PingTimeout => Some(Self::Timeout),
+                P2PStreamError::MismatchedProtocolVersion { .. } => {
+                    Some(Self::ProtocolMismatch(err.to_string()))
+                }
+                err => Some(Self::DecodeError(err.to_string())),
+            };
+        }
+        if let Some(err) = err.downcast_ref::<P2PHandshakeError>() {
+            return Some(match err {
+                P2PHandshakeError::Timeout | P2PHandshakeError::NoResponse => Self::Timeout,
+                P2PHandshakeError::Disconnected(reason) => Self::Disconnected(*reason),
+                P2PHandshakeError::NoSharedCapabilities => Self::ProtocolMismatch(err.to_string()),
+                err => Self::DecodeError(err.to_string()),
+            });
+        }
+        None
+    }
+
+    /// Whether the handshake failed for a reason that may go away by itself, like a timeout, a
+    /// reset connection or a peer without free slots, rather than because the peer rejected us.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Timeout => true,
+            Self::Disconnected(reason) => *reason == DisconnectReason::TooManyPeers,
+            Self::Io(kind) => matches!(
+                kind,
+                io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+            ),
+            _ => false,
+        }
+    }
+
+    /// The counter of the handshakes failing for this reason.
+    pub fn counter(&self) -> &'static AtomicU64 {
+        match self {
+            Self::Timeout => &METRICS.handshake_timeouts,
+            Self::ConnectionRefused => &METRICS.handshake_refusals,
+            Self::ProtocolMismatch(_) => &METRICS.handshake_protocol_mismatches,
+            Self::DecodeError(_) => &METRICS.handshake_decode_errors,
+            Self::Disconnected(_) => &METRICS.handshake_disconnects,
+            Self::Io(_) | Self::Other(_) => &METRICS.handshake_other_failures,
+        }
+    }
+}
+
+// Perform a ETH Wire handshake with a peer, as a node of the chain `spec` at its Shanghai fork
+pub async fn handshake_eth(
+    p2p_stream: AuthedP2PStream,
+    spec: &ChainSpec,
+) -> Result<(AuthedEthStream, Status), HandshakeError> {
+    let fork_filter = spec.fork_filter(Head {
+        timestamp: spec.fork(Hardfork::Shanghai).as_timestamp().unwrap(),
         ..Default::default()
     });
 
     let status = Status::builder()
-        .chain(Chain::mainnet())
-        .genesis(MAINNET_GENESIS)
-        .forkid(Hardfork::Shanghai.fork_id(&MAINNET).unwrap())
+        .chain(spec.chain)
+        .genesis(spec.genesis_hash())
+        .forkid(Hardfork::Shanghai.fork_id(spec).unwrap())
         .build();
 
     let status = Status {
@@ -44,7 +187,49 @@ pub async fn handshake_eth(p2p_stream: AuthedP2PStream) -> eyre::Result<(AuthedE
         ..status
     };
     let eth_unauthed = UnauthedEthStream::new(p2p_stream);
-    Ok(eth_unauthed.handshake(status, fork_filter).await?)
+    eth_unauthed
+        .handshake(status, fork_filter)
+        .await
+        .map_err(|e| HandshakeError::classify(&e))
+}
+
+/// Hex of the RLP list of a peer's `hello` and `status` messages, each RLP encoded as in the
+/// handshake, without its message id.
+pub fn encode_handshake(hello: &HelloMessage, status: &Status) -> String {
+    let mut out = Vec::new();
+    Header {
+        list: true,
+        payload_length: hello.length() + status.length(),
+    }
+    .encode(&mut out);
+    hello.encode(&mut out);
+    status.encode(&mut out);
+    out.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Ask the peer for the header of its best block, since the status only carries its hash
+pub async fn best_block_number(eth_stream: &mut AuthedEthStream, status: &Status) -> Option<u64> {
+    let request = EthMessage::GetBlockHeaders(RequestPair {
+        request_id: 0,
+        message: GetBlockHeaders {
+            start_block: BlockHashOrNumber::Hash(status.blockhash),
+            limit: 1,
+            skip: 0,
+            direction: HeadersDirection::Rising,
+        },
+    });
+    eth_stream.send(request).await.ok()?;
+
+    let response = tokio::time::timeout(BEST_BLOCK_TIMEOUT, async {
+        while let Some(Ok(message)) = eth_stream.next().await {
+            if let EthMessage::BlockHeaders(RequestPair { message, .. }) = message {
+                return message.0.first().map(|header| header.number);
+            }
+        }
+        None
+    })
+    .await;
+    response.ok().flatten()
 }
 
 // Snoop by greedily capturing all broadcasts that the peer emits
@@ -1,76 +1,712 @@
-use crate::types::{AddItemError, DeleteItemError, PeerData, QueryItemError, ScanTableError};
+use crate::types::{
+    advertises_snap, as_opt_u64, as_string, client_family, integrity_violations, normalize_ip,
+    normalize_peer_id, parse_is_ipv6, parse_total_difficulty, AddItemError, Cidr, DeleteItemError,
+    IntegrityReport, LatencyPercentiles, PeerCountSnapshot, PeerData, QueryItemError,
+    ScanTableError, SchemaInfo, TableError, UpdateItemError, U256,
+};
 use async_trait::async_trait;
 use aws_config::meta::region::RegionProviderChain;
-use aws_sdk_dynamodb::types::AttributeValue;
-use aws_sdk_dynamodb::{config::Region, Client};
+use aws_config::profile::ProfileFileCredentialsProvider;
+use aws_sdk_dynamodb::operation::describe_table::DescribeTableError;
+use aws_sdk_dynamodb::operation::scan::builders::ScanFluentBuilder;
+use aws_sdk_dynamodb::types::{
+    AttributeValue, DeleteRequest, KeySchemaElement, KeyType, KeysAndAttributes, Select,
+    WriteRequest,
+};
+use aws_sdk_dynamodb::{
+    config::Region,
+    error::{ProvideErrorMetadata, SdkError},
+    Client,
+};
 use chrono::{DateTime, Days, Duration, Utc};
-use std::collections::HashMap;
+use futures::stream::{self, BoxStream};
+use futures::TryStreamExt;
+use rand::Rng;
+use rusqlite::{params, params_from_iter, Params, Row, Statement, TransactionBehavior};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock};
 use tokio_rusqlite::Connection;
 use tokio_stream::StreamExt;
-use tracing::info;
+use tracing::{debug, info, warn};
 
 #[async_trait]
 pub trait PeerDB: Send + Sync {
+    /// Add a peer, or update it if its `last_seen` is later than the stored one's. An older
+    /// observation arriving late, e.g. from a slower worker, leaves the stored peer as it is.
+    ///
+    /// `ttl` is the Unix timestamp after which DynamoDB may expire the peer, which never expires
+    /// without it. Other backends ignore it.
     async fn add_peer(&self, peer_data: PeerData, ttl: Option<i64>) -> Result<(), AddItemError>;
-    async fn all_peers(&self, page_size: Option<i32>) -> Result<Vec<PeerData>, ScanTableError>;
+    /// Add many peers at once, which backends can write more efficiently than one by one. Like
+    /// [`PeerDB::add_peer`], stored peers are only updated by later observations.
+    async fn add_peers(&self, peers: Vec<PeerData>, ttl: Option<i64>) -> Result<(), AddItemError>;
+    /// Peers of the database, only the geolocated ones, i.e. with a non empty `country`, if
+    /// `require_geo`.
+    async fn all_peers(
+        &self,
+        page_size: Option<i32>,
+        require_geo: bool,
+    ) -> Result<Vec<PeerData>, ScanTableError>;
+    /// Every peer, or every geolocated peer if `require_geo`, read a page at a time so that
+    /// memory stays bounded however large the database is. Unlike [`PeerDB::all_peers`] no
+    /// `last_seen` window applies.
+    fn peers_stream(&self, require_geo: bool) -> BoxStream<'_, Result<PeerData, ScanTableError>>;
+    /// Peers with this id, in any of the forms accepted by [`normalize_peer_id`].
     async fn node_by_id(&self, id: String) -> Result<Option<Vec<PeerData>>, QueryItemError>;
+    /// Peers at this address, in any of the forms accepted by [`normalize_ip`].
     async fn node_by_ip(&self, ip: String) -> Result<Option<Vec<PeerData>>, QueryItemError>;
+    /// Whether a peer with this id is recorded, without fetching the record.
+    async fn peer_exists(&self, id: String) -> Result<bool, QueryItemError>;
+    /// The ids among `ids` of the recorded peers, normalized with [`normalize_peer_id`], to skip
+    /// the known peers of a batch at once rather than with one [`PeerDB::peer_exists`] each.
+    async fn peers_exist(&self, ids: Vec<String>) -> Result<HashSet<String>, QueryItemError>;
+    /// Peers whose address is an IPv6 (`v6 = true`) or IPv4 (`v6 = false`) address.
+    /// Peers with a malformed address are in neither bucket.
+    async fn nodes_by_ip_version(&self, v6: bool) -> Result<Vec<PeerData>, ScanTableError>;
+    /// Number of peers stored in the database.
+    async fn count_peers(&self) -> Result<u64, ScanTableError>;
+    /// Number of distinct addresses of the stored peers. A host running several nodes counts once.
+    async fn distinct_ip_count(&self) -> Result<u64, ScanTableError>;
+    /// Number of distinct ids of the stored peers. A node seen at several addresses counts once.
+    async fn distinct_peer_id_count(&self) -> Result<u64, ScanTableError>;
+    /// Peers whose `last_seen` falls within `[start, end]`, all of them: `page_size` only sets
+    /// how many items a DynamoDB scan reads per request.
+    ///
+    /// The bounds are compared as strings against the stored `last_seen`. This relies on `last_seen`
+    /// being written in the `DateTime<Utc>` display format (`2023-10-04 05:59:56.470468 UTC`), which
+    /// like RFC3339 sorts lexicographically in chronological order.
+    async fn peers_seen_between(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        page_size: Option<i32>,
+    ) -> Result<Vec<PeerData>, ScanTableError>;
+    /// The `limit` most recently seen peers, sorted by `last_seen` descending.
+    async fn recent_peers(&self, limit: i32) -> Result<Vec<PeerData>, ScanTableError>;
+    /// The `limit` peers seen the longest time ago, sorted by `last_seen` ascending.
+    async fn stale_peers(&self, limit: i32) -> Result<Vec<PeerData>, ScanTableError>;
+    /// Peers whose total difficulty is below `threshold`, i.e. lagging behind the chain tip,
+    /// compared as [`U256`] so that totals beyond `u128::MAX` are ordered right. Peers with an
+    /// unparseable total difficulty are excluded.
+    async fn peers_below_difficulty(
+        &self,
+        threshold: U256,
+    ) -> Result<Vec<PeerData>, ScanTableError>;
+    /// Peers whose best block is more than `lag` blocks behind `current_head`.
+    /// Peers with an unknown best block number are excluded.
+    async fn peers_behind_head(
+        &self,
+        current_head: u64,
+        lag: u64,
+    ) -> Result<Vec<PeerData>, ScanTableError>;
+    /// Number of peers per client family, see [`client_family`].
+    async fn client_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError>;
+    /// Number of peers per country. Peers with an empty or unknown country are counted as
+    /// `unknown`.
+    async fn country_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError>;
+    /// Number of peers advertising each capability, e.g. `eth/68` or `snap/1`. A peer is counted
+    /// once per capability it advertises.
+    async fn capability_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError>;
+    /// Number of peers per [`PeerData::last_disconnect_reason`]. Peers without one aren't
+    /// counted.
+    async fn disconnect_reason_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError>;
+    /// Number of peers per autonomous system, keyed by [`asn_label`], e.g.
+    /// `AS16509 Amazon.com, Inc.`, to tell how concentrated the hosting is. Peers without a
+    /// [`PeerData::asn`] aren't counted.
+    async fn asn_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError>;
+    /// Peers found through the given discovery source, see [`PeerData::discovery_source`].
+    async fn nodes_by_source(&self, source: String) -> Result<Vec<PeerData>, ScanTableError>;
+    /// Peers last observed by the crawl `session_id`, see [`PeerData::session_id`]. Peers are
+    /// stored once per id, so a peer observed again by a later crawl belongs to that crawl.
+    async fn peers_by_session(&self, session_id: String) -> Result<Vec<PeerData>, ScanTableError>;
+    /// Peers flagged with the given role, see [`PeerData::node_role`].
+    async fn nodes_by_role(&self, role: String) -> Result<Vec<PeerData>, ScanTableError>;
+    /// Number of peers serving the snap protocol, see [`PeerData::serves_snap`].
+    async fn snap_server_count(&self) -> Result<u64, ScanTableError>;
+    /// Peers advertising `capability`, e.g. `snap/1`. Only whole capabilities match, so `snap`
+    /// doesn't match a peer advertising `snap/1`.
+    async fn nodes_by_capability(
+        &self,
+        capability: String,
+        page_size: Option<i32>,
+    ) -> Result<Vec<PeerData>, QueryItemError>;
+    /// Every peer, with one record per id: the one with the latest `last_seen` when several
+    /// records share an id, like the records of a peer written from several regions.
+    async fn latest_per_peer(
+        &self,
+        page_size: Option<i32>,
+    ) -> Result<Vec<PeerData>, ScanTableError>;
+    /// Peers whose address is within the `cidr` range, e.g. `203.0.113.0/24`. Fails with
+    /// [`QueryItemError::InvalidCidrError`] if `cidr` isn't a valid range.
+    async fn nodes_by_cidr(
+        &self,
+        cidr: String,
+        page_size: Option<i32>,
+    ) -> Result<Vec<PeerData>, QueryItemError>;
+    /// Delete the peers not seen for `time_validity` days, returning how many were deleted.
+    async fn prune_peers(&self, time_validity: i64) -> Result<u64, DeleteItemError>;
+    /// Delete every peer, e.g. to reset the db between tests. The schema metadata and the peer
+    /// count history are kept.
+    async fn clear(&self) -> Result<(), DeleteItemError>;
+    /// Delete every record of the peer with exactly this id, returning whether there was one.
+    /// Unlike the lookups the id isn't normalized, so that records stored under another form of
+    /// the id, e.g. by an older version, can be deleted too.
+    async fn delete_peer(&self, id: String) -> Result<bool, DeleteItemError>;
+    /// Append `snapshot` to the peer count history.
+    async fn record_peer_count(&self, snapshot: PeerCountSnapshot) -> Result<(), AddItemError>;
+    /// The snapshots of the peer count history taken at or after `since`, oldest first.
+    async fn count_history(
+        &self,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<PeerCountSnapshot>, ScanTableError>;
+    /// Set the `country` and `city` of the peer with this id, leaving its other fields as they
+    /// are. Returns whether such a peer is recorded.
+    async fn update_location(
+        &self,
+        id: String,
+        country: String,
+        city: String,
+    ) -> Result<bool, UpdateItemError>;
+    /// Set the `best_block`, `best_block_number`, `total_difficulty` and `last_seen` of the peer
+    /// with this id, e.g. after handshaking again with a known peer, leaving its other fields as
+    /// they are. Like [`PeerDB::add_peer`], a peer stored with a later or equal `last_seen` is
+    /// left as it is. Returns whether such a peer is recorded.
+    async fn update_chain_status(
+        &self,
+        id: String,
+        best_block: String,
+        best_block_number: Option<u64>,
+        total_difficulty: String,
+        last_seen: String,
+    ) -> Result<bool, UpdateItemError>;
+    /// Version of the crate and schema that wrote the database.
+    async fn schema_info(&self) -> Result<SchemaInfo, QueryItemError>;
 }
 
+/// Collapse `peers` to one peer per id, the one with the latest `last_seen`.
+pub fn latest_per_id(peers: impl IntoIterator<Item = PeerData>) -> Vec<PeerData> {
+    let mut latest = HashMap::new();
+    for peer_data in peers {
+        insert_if_newer(&mut latest, peer_data);
+    }
+    latest.into_values().collect()
+}
+
+/// Percentiles of the [`PeerData::handshake_ms`] of every peer of `db` that has one, read
+/// through [`PeerDB::peers_stream`]. `None` if no peer has a handshake duration.
+pub async fn handshake_latency_percentiles(
+    db: &dyn PeerDB,
+) -> Result<Option<LatencyPercentiles>, ScanTableError> {
+    let samples: Vec<u64> = db
+        .peers_stream(false)
+        .try_filter_map(|peer_data| async move { Ok(peer_data.handshake_ms) })
+        .try_collect()
+        .await?;
+    Ok(LatencyPercentiles::from_samples(samples))
+}
+
+/// Check every peer of `db`, read through [`PeerDB::peers_stream`], against the invariants of
+/// [`integrity_violations`]. DynamoDB items that can't be read as peers at all are skipped and
+/// logged by the scan.
+pub async fn verify_peers(db: &dyn PeerDB) -> Result<IntegrityReport, ScanTableError> {
+    let mut report = IntegrityReport::default();
+    let mut peers = db.peers_stream(false);
+    while let Some(peer_data) = peers.try_next().await? {
+        report.checked += 1;
+        let violations = integrity_violations(&peer_data);
+        if violations.is_empty() {
+            continue;
+        }
+        for violation in violations {
+            *report.violations.entry(violation).or_default() += 1;
+        }
+        if !report.invalid_ids.contains(&peer_data.id) {
+            report.invalid_ids.push(peer_data.id);
+        }
+    }
+    Ok(report)
+}
+
+/// Default `last_seen` window, in hours, used when scanning the AWS database.
+const DEFAULT_PEERS_WINDOW_HOURS: i64 = 24;
+
+/// Default number of attempts for a throttled write to the AWS database.
+pub const DEFAULT_MAX_WRITE_ATTEMPTS: u32 = 5;
+/// Base delay of the exponential backoff between throttled writes, in milliseconds.
+const WRITE_BACKOFF_BASE_MS: u64 = 50;
+/// Upper bound of the backoff between throttled writes, in milliseconds.
+const WRITE_BACKOFF_MAX_MS: u64 = 5_000;
+/// Table used by [`AwsPeerDB`] unless overridden with [`AwsPeerDB::with_table`].
+pub const DEFAULT_TABLE_NAME: &str = "eth-peer-data";
+/// `peer-id` of the reserved DynamoDB item holding the [`SchemaInfo`].
+const META_ITEM_ID: &str = "__meta__";
+/// Prefix of the `peer-id` of the reserved DynamoDB items holding the peer count history, followed
+/// by the timestamp of the snapshot.
+const COUNT_HISTORY_ID_PREFIX: &str = "__count__#";
+/// Prefix of the `peer-id` of every reserved DynamoDB item, which scans of the peers skip.
+const RESERVED_ID_PREFIX: &str = "__";
+/// Largest item DynamoDB accepts, in bytes.
+const MAX_ITEM_SIZE: usize = 400 * 1024;
+/// Maximum number of items in a single `BatchWriteItem` request.
+const BATCH_WRITE_SIZE: usize = 25;
+/// Number of conditional puts [`AwsPeerDB`]'s [`PeerDB::add_peers`] sends at once to a region.
+const CONCURRENT_PUTS: usize = 25;
+/// Maximum number of keys in a single `BatchGetItem` request.
+const BATCH_GET_SIZE: usize = 100;
+/// How long a sqlite connection waits for a lock held by another connection before failing.
+const SQLITE_BUSY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+/// Number of rows read at once by [`SqlPeerDB`]'s [`PeerDB::peers_stream`].
+const STREAM_PAGE_SIZE: i64 = 1_000;
+/// Number of ids bound at once by [`SqlPeerDB`]'s [`PeerDB::peers_exist`], below the 999
+/// parameters older sqlite versions accept.
+const SQLITE_IDS_PER_QUERY: usize = 500;
+/// Path that makes [`SqlPeerDB::open`] use an in memory db.
+const IN_MEMORY_PATH: &str = ":memory:";
+/// Condition of the DynamoDB scans keeping only the geolocated peers, with `:empty` bound to `""`.
+const GEO_FILTER: &str = "attribute_exists(country) AND country <> :empty";
+
+/// DynamoDB backend.
+///
+/// Cloning is cheap: clones share the same SDK [`Client`], and so its resolved credentials and
+/// connection pool. Build it once and clone it (or share it behind an `Arc`) rather than calling
+/// [`AwsPeerDB::new`] for every worker, which resolves the config and credentials again.
 #[derive(Clone)]
 pub struct AwsPeerDB {
     client: Client,
+    // clients of the additional regions peers are also written to
+    replicas: Vec<Client>,
+    table: String,
+    max_write_attempts: u32,
+    consistent_read: bool,
 }
 
 impl AwsPeerDB {
-    pub async fn new() -> Self {
+    /// Connect to DynamoDB, or to the DynamoDB compatible service at `endpoint_url` if set, e.g.
+    /// a LocalStack instance at `http://localhost:4566`.
+    pub async fn new(endpoint_url: Option<String>) -> Self {
+        Self::new_with_regions(None, &[], endpoint_url.as_deref()).await
+    }
+
+    /// Like [`AwsPeerDB::new`] without endpoint override, but loads credentials from the named
+    /// profile of `~/.aws/credentials` when `profile` is set instead of using the default
+    /// provider chain.
+    pub async fn new_with_profile(profile: Option<&str>) -> Self {
+        Self::new_with_regions(profile, &[], None).await
+    }
+
+    /// Like [`AwsPeerDB::new_with_profile`], but with a client per region of `regions`, which
+    /// must all hold the table. Peers added through [`PeerDB::add_peer`] and
+    /// [`PeerDB::add_peers`] are written to every region, and the first region serves the reads
+    /// and the other writes. An empty `regions` uses the default region. Every client connects
+    /// to `endpoint_url` if set.
+    pub async fn new_with_regions(
+        profile: Option<&str>,
+        regions: &[String],
+        endpoint_url: Option<&str>,
+    ) -> Self {
         let region_provider =
             RegionProviderChain::default_provider().or_else(Region::new("us-west-2"));
-        let shared_config = aws_config::from_env().region(region_provider).load().await;
-        let client = Client::new(&shared_config);
+        let mut loader = aws_config::from_env().region(region_provider);
+        if let Some(profile) = profile {
+            loader = loader.credentials_provider(
+                ProfileFileCredentialsProvider::builder()
+                    .profile_name(profile)
+                    .build(),
+            );
+        }
+        let shared_config = loader.load().await;
+        let client = |region: Option<&String>| {
+            let mut config = aws_sdk_dynamodb::config::Builder::from(&shared_config);
+            if let Some(region) = region {
+                config = config.region(Region::new(region.clone()));
+            }
+            if let Some(endpoint_url) = endpoint_url {
+                config = config.endpoint_url(endpoint_url);
+            }
+            Client::from_conf(config.build())
+        };
+        let mut clients = regions.iter().map(|region| client(Some(region)));
+        match clients.next() {
+            Some(first) => {
+                let mut db = Self::from_client(first);
+                db.replicas = clients.collect();
+                db
+            }
+            None => Self::from_client(client(None)),
+        }
+    }
 
-        AwsPeerDB { client }
+    /// Use an already configured client, e.g. one shared with other parts of the program.
+    pub fn from_client(client: Client) -> Self {
+        AwsPeerDB {
+            client,
+            replicas: Vec::new(),
+            table: DEFAULT_TABLE_NAME.to_string(),
+            max_write_attempts: DEFAULT_MAX_WRITE_ATTEMPTS,
+            consistent_read: false,
+        }
     }
 
-    pub async fn all_last_peers(
+    /// Set how many times a write is attempted when DynamoDB throttles it. Other errors are never retried.
+    pub fn with_max_write_attempts(mut self, max_write_attempts: u32) -> Self {
+        self.max_write_attempts = max_write_attempts.max(1);
+        self
+    }
+
+    /// Read and write `table` instead of [`DEFAULT_TABLE_NAME`]. Lookups by ip still go through
+    /// its `peer-ip-index` index.
+    pub fn with_table(mut self, table: impl Into<String>) -> Self {
+        self.table = table.into();
+        self
+    }
+
+    /// Use strongly consistent reads for the lookups by id, so they see the peers written just
+    /// before. They consume twice the read capacity of the default eventually consistent reads.
+    /// Lookups by ip go through a global secondary index, which only supports eventually
+    /// consistent reads.
+    pub fn with_consistent_reads(mut self, consistent_read: bool) -> Self {
+        if consistent_read {
+            info!(
+                "Using strongly consistent DynamoDB reads, which consume twice the read capacity"
+            );
+        }
+        self.consistent_read = consistent_read;
+        self
+    }
+
+    /// Check that the table exists in every region, so a misconfigured table is reported at
+    /// startup rather than on the first write.
+    pub async fn check_table(&self) -> Result<(), TableError> {
+        for client in self.clients() {
+            match client.describe_table().table_name(&self.table).send().await {
+                Ok(_) => {}
+                Err(err)
+                    if err
+                        .as_service_error()
+                        .map_or(false, |err| err.is_resource_not_found_exception()) =>
+                {
+                    return Err(TableError::AwsTableNotFoundError(format!(
+                        "{} in {}",
+                        self.table,
+                        client_region(client)
+                    )));
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// The client of the first region, then those of the other regions.
+    fn clients(&self) -> impl Iterator<Item = &Client> {
+        std::iter::once(&self.client).chain(&self.replicas)
+    }
+
+    /// Key schema of the table in `client`'s region, which may have a sort key besides `peer-id`.
+    async fn key_schema(
         &self,
-        last_seen: String,
+        client: &Client,
+    ) -> Result<Vec<KeySchemaElement>, SdkError<DescribeTableError>> {
+        Ok(client
+            .describe_table()
+            .table_name(&self.table)
+            .send()
+            .await?
+            .table
+            .and_then(|table| table.key_schema)
+            .unwrap_or_default())
+    }
+
+    /// [`PeerDB::peers_exist`] on a table with a sort key, whose items can't be read by
+    /// `peer-id` alone: one counting query per id, [`BATCH_GET_SIZE`] queries at a time.
+    async fn peers_exist_by_query(
+        &self,
+        ids: Vec<String>,
+    ) -> Result<HashSet<String>, QueryItemError> {
+        let mut found = HashSet::new();
+        for chunk in ids.chunks(BATCH_GET_SIZE) {
+            let counts = futures::future::join_all(chunk.iter().map(|id| {
+                self.client
+                    .query()
+                    .table_name(&self.table)
+                    .consistent_read(self.consistent_read)
+                    .key_condition_expression("#id = :id")
+                    .expression_attribute_names("#id", "peer-id")
+                    .expression_attribute_values(":id", AttributeValue::S(id.clone()))
+                    .select(Select::Count)
+                    .limit(1)
+                    .send()
+            }))
+            .await;
+            for (id, count) in chunk.iter().zip(counts) {
+                if count?.count > 0 {
+                    found.insert(id.clone());
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// Delete `items` from the table in `client`'s region through `BatchWriteItem`, retrying the
+    /// throttled and unprocessed deletes, and return how many were deleted.
+    async fn batch_delete(
+        &self,
+        client: &Client,
+        items: Vec<HashMap<String, AttributeValue>>,
+    ) -> Result<usize, DeleteItemError> {
+        if items.is_empty() {
+            return Ok(0);
+        }
+        let key_names: Vec<String> = self
+            .key_schema(client)
+            .await?
+            .into_iter()
+            .filter_map(|key| key.attribute_name)
+            .collect();
+        let deletes: Vec<WriteRequest> = items
+            .into_iter()
+            .map(|mut item| {
+                let key = key_names
+                    .iter()
+                    .filter_map(|name| Some((name.clone(), item.remove(name)?)))
+                    .collect();
+                WriteRequest::builder()
+                    .delete_request(DeleteRequest::builder().set_key(Some(key)).build())
+                    .build()
+            })
+            .collect();
+
+        for chunk in deletes.chunks(BATCH_WRITE_SIZE) {
+            let mut pending = chunk.to_vec();
+            let mut attempt = 1;
+            loop {
+                match client
+                    .batch_write_item()
+                    .request_items(&self.table, pending.clone())
+                    .send()
+                    .await
+                {
+                    Ok(output) => {
+                        pending = output
+                            .unprocessed_items
+                            .and_then(|mut unprocessed| unprocessed.remove(&self.table))
+                            .unwrap_or_default();
+                        if pending.is_empty() {
+                            break;
+                        }
+                        if attempt >= self.max_write_attempts {
+                            return Err(DeleteItemError::AwsUnprocessedItemsError(pending.len()));
+                        }
+                    }
+                    Err(e) if attempt < self.max_write_attempts && is_throttling_error(&e) => {}
+                    Err(e) => return Err(e.into()),
+                }
+                tokio::time::sleep(write_backoff(attempt)).await;
+                attempt += 1;
+            }
+        }
+        Ok(deletes.len())
+    }
+
+    /// Run `write` against every region at once, returning the outcome of the first region that
+    /// succeeded. Fails only if every region failed: the other failures are logged, as the peers
+    /// are still stored in the regions that succeeded.
+    async fn write_to_regions<'a, T, E, F, Fut>(&'a self, write: F) -> Result<T, E>
+    where
+        E: std::fmt::Display,
+        F: Fn(&'a Client) -> Fut,
+        Fut: std::future::Future<Output = Result<T, E>>,
+    {
+        let results = futures::future::join_all(self.clients().map(write)).await;
+        if self.replicas.is_empty() {
+            return results.into_iter().next().unwrap();
+        }
+        let mut succeeded = None;
+        let mut failures = Vec::new();
+        for (client, result) in self.clients().zip(results) {
+            match result {
+                Ok(outcome) => {
+                    succeeded.get_or_insert(outcome);
+                }
+                Err(err) => {
+                    warn!(
+                        "Failed to write peers to DynamoDB in {}: {}",
+                        client_region(client),
+                        err
+                    );
+                    failures.push(err);
+                }
+            }
+        }
+        match succeeded {
+            Some(outcome) => Ok(outcome),
+            None => Err(failures.swap_remove(0)),
+        }
+    }
+
+    /// Read every item of the peer `id` in `client`'s region, `update` it and write it back,
+    /// keeping its other attributes, `ttl` included. With `last_seen`, items stored with a later
+    /// or equal `last_seen` are left as they are, like in [`AwsPeerDB::put_peer_item`]. Returns
+    /// whether the peer is recorded in the region.
+    async fn update_peer_items(
+        &self,
+        client: &Client,
+        id: &str,
+        last_seen: Option<&str>,
+        update: impl Fn(&mut HashMap<String, AttributeValue>),
+    ) -> Result<bool, UpdateItemError> {
+        let results = client
+            .query()
+            .table_name(&self.table)
+            .consistent_read(self.consistent_read)
+            .key_condition_expression("#id = :id")
+            .expression_attribute_names("#id", "peer-id")
+            .expression_attribute_values(":id", AttributeValue::S(id.to_string()))
+            .send()
+            .await?;
+
+        let items = results.items.unwrap_or_default();
+        let found = !items.is_empty();
+        for mut item in items {
+            update(&mut item);
+            let mut request = client
+                .put_item()
+                .table_name(&self.table)
+                .set_item(Some(item));
+            if let Some(last_seen) = last_seen {
+                request = request
+                    .condition_expression(
+                        "attribute_not_exists(last_seen) OR last_seen < :new_last_seen",
+                    )
+                    .expression_attribute_values(
+                        ":new_last_seen",
+                        AttributeValue::S(last_seen.to_string()),
+                    );
+            }
+            match request.send().await {
+                Ok(_) => {}
+                Err(e)
+                    if e.as_service_error()
+                        .map_or(false, |e| e.is_conditional_check_failed_exception()) =>
+                {
+                    debug!("Peer {} is already stored with a later last_seen", id);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(found)
+    }
+
+    /// Scan all peers seen within `since` from now, only the geolocated ones if `require_geo`.
+    /// Defaults to the last 24 hours when `since` is `None`.
+    pub async fn all_peers_since(
+        &self,
+        since: Option<Duration>,
         page_size: Option<i32>,
+        require_geo: bool,
     ) -> Result<Vec<PeerData>, ScanTableError> {
-        let page_size = page_size.unwrap_or(1000);
+        let since = since.unwrap_or_else(|| Duration::hours(DEFAULT_PEERS_WINDOW_HOURS));
+        let cutoff = Utc::now()
+            .checked_sub_signed(since)
+            .unwrap_or(DateTime::<Utc>::MIN_UTC)
+            .to_string();
+        self.all_last_peers(cutoff, page_size, require_geo).await
+    }
+
+    /// Record the [`SchemaInfo`] of this version of the crate in the reserved meta item.
+    pub async fn write_schema_info(&self) -> Result<(), AddItemError> {
+        let SchemaInfo {
+            crate_version,
+            schema_version,
+        } = SchemaInfo::current();
+        self.client
+            .put_item()
+            .table_name(&self.table)
+            .item("peer-id", AttributeValue::S(META_ITEM_ID.to_string()))
+            .item("crate_version", AttributeValue::S(crate_version))
+            .item(
+                "schema_version",
+                AttributeValue::N(schema_version.to_string()),
+            )
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    /// Scan the whole table, regardless of when peers were last seen.
+    async fn scan_all(&self) -> Result<Vec<PeerData>, ScanTableError> {
         let results: Result<Vec<_>, _> = self
             .client
             .scan()
-            .table_name("eth-peer-data")
-            .filter_expression("last_seen > :last_seen_parameter")
+            .table_name(&self.table)
+            // skip the reserved items
+            .filter_expression("NOT begins_with(#id, :reserved)")
+            .expression_attribute_names("#id", "peer-id")
             .expression_attribute_values(
-                ":last_seen_parameter",
-                AttributeValue::S(last_seen.clone()),
+                ":reserved",
+                AttributeValue::S(RESERVED_ID_PREFIX.to_string()),
             )
-            .limit(page_size)
             .into_paginator()
             .items()
             .send()
             .collect()
             .await;
         match results {
-            Ok(peers) => peers.iter().map(|peer| Ok(peer.into())).collect(),
+            Ok(peers) => Ok(peers_from_items(&peers)),
             Err(err) => Err(err.into()),
         }
     }
-}
 
-#[async_trait]
-impl PeerDB for AwsPeerDB {
-    async fn add_peer(&self, peer_data: PeerData, ttl: Option<i64>) -> Result<(), AddItemError> {
+    /// Scan only `attribute` of every peer item, and count its distinct values.
+    async fn scan_distinct(&self, attribute: &str) -> Result<usize, ScanTableError> {
+        let items: Vec<_> = self
+            .client
+            .scan()
+            .table_name(&self.table)
+            .projection_expression("#attribute")
+            // skip the reserved items
+            .filter_expression("NOT begins_with(#id, :reserved)")
+            .expression_attribute_names("#attribute", attribute)
+            .expression_attribute_names("#id", "peer-id")
+            .expression_attribute_values(
+                ":reserved",
+                AttributeValue::S(RESERVED_ID_PREFIX.to_string()),
+            )
+            .into_paginator()
+            .items()
+            .send()
+            .collect::<Result<_, _>>()
+            .await?;
+        Ok(items
+            .iter()
+            .filter_map(|item| item.get(attribute)?.as_s().ok())
+            .collect::<HashSet<_>>()
+            .len())
+    }
+
+    /// Map a peer to its DynamoDB item, truncating the peer if it's larger than DynamoDB allows.
+    /// The `source_region` is added by [`AwsPeerDB::put_peer_item`], as it depends on the region
+    /// written to.
+    fn peer_item(&self, peer_data: PeerData, ttl: Option<i64>) -> HashMap<String, AttributeValue> {
+        let mut peer_data = peer_data;
+        let dropped = peer_data.truncate_to(MAX_ITEM_SIZE);
+        if !dropped.is_empty() {
+            warn!(
+                "Peer {} is too large for DynamoDB, storing it without its {}",
+                peer_data.id,
+                dropped.join(", ")
+            );
+        }
         let capabilities = peer_data
             .capabilities
             .iter()
             .map(|cap| AttributeValue::S(cap.clone()))
             .collect();
         let peer_id = AttributeValue::S(peer_data.id);
-        let peer_ip = AttributeValue::S(peer_data.address);
+        let is_ipv6 = parse_is_ipv6(&peer_data.address);
+        let peer_ip = AttributeValue::S(normalize_ip(&peer_data.address));
         let client_version = AttributeValue::S(peer_data.client_version);
         let enode_url = AttributeValue::S(peer_data.enode_url);
         let port = AttributeValue::N(peer_data.tcp_port.to_string()); // numbers are sent over the network as string
@@ -81,69 +717,400 @@ impl PeerDB for AwsPeerDB {
         let country = AttributeValue::S(peer_data.country);
         let city = AttributeValue::S(peer_data.city);
         let last_seen = AttributeValue::S(peer_data.last_seen);
-        let region_source = AttributeValue::S(self.client.config().region().unwrap().to_string());
-        let ttl = AttributeValue::N(ttl.unwrap().to_string());
         let capabilities = AttributeValue::L(capabilities);
         let eth_version = AttributeValue::N(peer_data.eth_version.to_string());
+        let discovery_source = AttributeValue::S(peer_data.discovery_source);
+        let session_id = AttributeValue::S(peer_data.session_id);
 
-        match self
-            .client
+        let mut item = HashMap::from([
+            ("peer-id".to_string(), peer_id),
+            ("peer-ip".to_string(), peer_ip),
+            ("client_version".to_string(), client_version),
+            ("enode_url".to_string(), enode_url),
+            ("port".to_string(), port),
+            ("chain".to_string(), chain),
+            ("country".to_string(), country),
+            ("city".to_string(), city),
+            ("capabilities".to_string(), capabilities),
+            ("eth_version".to_string(), eth_version),
+            ("last_seen".to_string(), last_seen),
+            ("genesis_block_hash".to_string(), genesis_hash),
+            ("best_block".to_string(), best_block),
+            ("total_difficulty".to_string(), total_difficulty),
+            ("discovery_source".to_string(), discovery_source),
+            ("session_id".to_string(), session_id),
+        ]);
+        if let Some(ttl) = ttl {
+            item.insert("ttl".to_string(), AttributeValue::N(ttl.to_string()));
+        }
+        if let Some(udp_port) = peer_data.udp_port {
+            item.insert(
+                "udp_port".to_string(),
+                AttributeValue::N(udp_port.to_string()),
+            );
+        }
+        if let Some(is_ipv6) = is_ipv6 {
+            item.insert("is_ipv6".to_string(), AttributeValue::Bool(is_ipv6));
+        }
+        item.insert(
+            "serves_snap".to_string(),
+            AttributeValue::Bool(peer_data.serves_snap),
+        );
+        if let Some(tcp_connect_ms) = peer_data.tcp_connect_ms {
+            item.insert(
+                "tcp_connect_ms".to_string(),
+                AttributeValue::N(tcp_connect_ms.to_string()),
+            );
+        }
+        if let Some(handshake_ms) = peer_data.handshake_ms {
+            item.insert(
+                "handshake_ms".to_string(),
+                AttributeValue::N(handshake_ms.to_string()),
+            );
+        }
+        if let Some(last_disconnect_reason) = peer_data.last_disconnect_reason {
+            item.insert(
+                "last_disconnect_reason".to_string(),
+                AttributeValue::S(last_disconnect_reason),
+            );
+        }
+        if let Some(asn) = peer_data.asn {
+            item.insert("asn".to_string(), AttributeValue::N(asn.to_string()));
+        }
+        if let Some(org) = peer_data.org {
+            item.insert("org".to_string(), AttributeValue::S(org));
+        }
+        if let Some(node_role) = peer_data.node_role {
+            item.insert("node_role".to_string(), AttributeValue::S(node_role));
+        }
+        if let Some(raw_handshake) = peer_data.raw_handshake {
+            item.insert(
+                "raw_handshake".to_string(),
+                AttributeValue::S(raw_handshake),
+            );
+        }
+        if let Some(best_block_number) = peer_data.best_block_number {
+            item.insert(
+                "best_block_number".to_string(),
+                AttributeValue::N(best_block_number.to_string()),
+            );
+        }
+        item
+    }
+
+    /// Put a peer item in `client`'s region, tagged with that region as its `source_region`,
+    /// unless the stored item has a later or equal `last_seen`, retrying when DynamoDB throttles
+    /// the write. Errors carry the peer's `id` and `address`.
+    async fn put_peer_item(
+        &self,
+        client: &Client,
+        id: &str,
+        address: &str,
+        mut item: HashMap<String, AttributeValue>,
+    ) -> Result<(), AddItemError> {
+        if let Some(region) = client.config().region() {
+            item.insert(
+                "source_region".to_string(),
+                AttributeValue::S(region.to_string()),
+            );
+        }
+        let last_seen = item
+            .get("last_seen")
+            .cloned()
+            .unwrap_or_else(|| AttributeValue::S(String::new()));
+        let request = client
             .put_item()
-            .table_name("eth-peer-data")
-            .item("peer-id", peer_id)
-            .item("peer-ip", peer_ip)
-            .item("client_version", client_version)
-            .item("enode_url", enode_url)
-            .item("port", port)
-            .item("chain", chain)
-            .item("country", country)
-            .item("city", city)
-            .item("capabilities", capabilities)
-            .item("eth_version", eth_version)
-            .item("last_seen", last_seen)
-            .item("source_region", region_source)
-            .item("genesis_block_hash", genesis_hash)
-            .item("best_block", best_block)
-            .item("total_difficulty", total_difficulty)
-            .item("ttl", ttl)
-            .send()
-            .await
-        {
-            Ok(_) => Ok(()),
-            Err(e) => Err(e.into()),
+            .table_name(&self.table)
+            .set_item(Some(item))
+            .condition_expression("attribute_not_exists(last_seen) OR last_seen < :new_last_seen")
+            .expression_attribute_values(":new_last_seen", last_seen);
+
+        let mut attempt = 1;
+        loop {
+            match request.clone().send().await {
+                Ok(_) => return Ok(()),
+                Err(e)
+                    if e.as_service_error()
+                        .map_or(false, |e| e.is_conditional_check_failed_exception()) =>
+                {
+                    debug!("Peer {} is already stored with a later last_seen", id);
+                    return Ok(());
+                }
+                Err(e) if attempt < self.max_write_attempts && is_throttling_error(&e) => {
+                    let delay = write_backoff(attempt);
+                    debug!(
+                        "Write throttled by DynamoDB, retrying in {:?} (attempt {}/{})",
+                        delay, attempt, self.max_write_attempts
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    if is_item_too_large_error(&e) {
+                        warn!("DynamoDB rejected peer {} as too large", id);
+                    }
+                    return Err(AddItemError::from(e).with_peer(id, address));
+                }
+            }
         }
     }
 
-    async fn all_peers(&self, page_size: Option<i32>) -> Result<Vec<PeerData>, ScanTableError> {
-        let page_size = page_size.unwrap_or(1000);
-        let cutoff = Utc::now()
-            .checked_sub_signed(Duration::hours(24))
-            .unwrap()
-            .to_string();
-        let results: Result<Vec<_>, _> = self
-            .client
-            .scan()
-            .filter_expression("last_seen > :last_seen_parameter")
-            .expression_attribute_values(":last_seen_parameter", AttributeValue::S(cutoff.clone()))
-            .table_name("eth-peer-data")
-            .limit(page_size)
-            .into_paginator()
-            .items()
-            .send()
-            .collect()
-            .await;
+    /// Put peer items in `client`'s region with [`AwsPeerDB::put_peer_item`],
+    /// [`CONCURRENT_PUTS`] at a time. Every item is attempted, and the first error is returned.
+    async fn put_peer_items(
+        &self,
+        client: &Client,
+        items: &[(String, String, HashMap<String, AttributeValue>)],
+    ) -> Result<(), AddItemError> {
+        let mut first_error = None;
+        for chunk in items.chunks(CONCURRENT_PUTS) {
+            let results =
+                futures::future::join_all(chunk.iter().map(|(id, address, item)| {
+                    self.put_peer_item(client, id, address, item.clone())
+                }))
+                .await;
+            for result in results {
+                if let Err(err) = result {
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
 
+    pub async fn all_last_peers(
+        &self,
+        last_seen: String,
+        page_size: Option<i32>,
+        require_geo: bool,
+    ) -> Result<Vec<PeerData>, ScanTableError> {
+        let page_size = page_size.unwrap_or(1000);
+        let scan = self.client.scan().table_name(&self.table);
+        let results: Result<Vec<_>, _> =
+            filter_scan(scan, "last_seen > :last_seen_parameter", require_geo)
+                .expression_attribute_values(
+                    ":last_seen_parameter",
+                    AttributeValue::S(last_seen.clone()),
+                )
+                .limit(page_size)
+                .into_paginator()
+                .items()
+                .send()
+                .collect()
+                .await;
         match results {
-            Ok(peers) => peers.iter().map(|peer| Ok(peer.into())).collect(),
+            Ok(peers) => Ok(peers_from_items(&peers)),
             Err(err) => Err(err.into()),
         }
     }
+}
+
+/// Set the filter expression of `scan` to `filter`, and to only the geolocated peers of it too if
+/// `require_geo`.
+fn filter_scan(scan: ScanFluentBuilder, filter: &str, require_geo: bool) -> ScanFluentBuilder {
+    if !require_geo {
+        return scan.filter_expression(filter);
+    }
+    scan.filter_expression(format!("({filter}) AND {GEO_FILTER}"))
+        .expression_attribute_values(":empty", AttributeValue::S(String::new()))
+}
+
+/// Count the peers giving each disconnect reason.
+fn count_disconnect_reasons<'a>(reasons: impl Iterator<Item = &'a str>) -> HashMap<String, u64> {
+    let mut distribution = HashMap::new();
+    for reason in reasons {
+        *distribution.entry(reason.to_string()).or_default() += 1;
+    }
+    distribution
+}
+
+/// Name of an autonomous system in [`PeerDB::asn_distribution`]: its number, followed by its
+/// organization if known.
+pub fn asn_label(asn: u32, org: Option<&str>) -> String {
+    match org.filter(|org| !org.is_empty()) {
+        Some(org) => format!("AS{} {}", asn, org),
+        None => format!("AS{}", asn),
+    }
+}
+
+/// Count the peers of each autonomous system, keyed by [`asn_label`].
+fn count_asns<'a>(asns: impl Iterator<Item = (u32, Option<&'a str>, u64)>) -> HashMap<String, u64> {
+    let mut distribution = HashMap::new();
+    for (asn, org, count) in asns {
+        *distribution.entry(asn_label(asn, org)).or_default() += count;
+    }
+    distribution
+}
+
+/// Whether the total difficulty of `peer` is below `threshold`, `false` if it's malformed.
+fn below_difficulty(peer: &PeerData, threshold: U256) -> bool {
+    peer.total_difficulty_u256()
+        .is_some_and(|total_difficulty| total_difficulty < threshold)
+}
+
+/// Count the peers of each client family from their client versions.
+fn count_client_families<'a>(
+    client_versions: impl Iterator<Item = &'a str>,
+) -> HashMap<String, u64> {
+    let mut distribution = HashMap::new();
+    for client_version in client_versions {
+        *distribution
+            .entry(client_family(client_version).to_string())
+            .or_default() += 1;
+    }
+    distribution
+}
+
+/// Bucket of [`PeerDB::country_distribution`] a peer's country is counted in.
+fn country_bucket(country: &str) -> String {
+    let country = country.trim();
+    if country.is_empty() || country.eq_ignore_ascii_case("unknown") {
+        "unknown".to_string()
+    } else {
+        country.to_string()
+    }
+}
+
+/// Count the peers of each country, see [`country_bucket`].
+fn count_countries<'a>(countries: impl Iterator<Item = (&'a str, u64)>) -> HashMap<String, u64> {
+    let mut distribution = HashMap::new();
+    for (country, count) in countries {
+        *distribution.entry(country_bucket(country)).or_default() += count;
+    }
+    distribution
+}
+
+/// Count the peers advertising each capability, from the capability lists of groups of `count`
+/// peers. Capabilities listed twice by a peer are counted once.
+fn count_capabilities<'a>(
+    capability_lists: impl Iterator<Item = (&'a [String], u64)>,
+) -> HashMap<String, u64> {
+    let mut distribution = HashMap::new();
+    for (capabilities, count) in capability_lists {
+        let distinct: HashSet<&String> = capabilities.iter().collect();
+        for capability in distinct {
+            *distribution.entry(capability.clone()).or_default() += count;
+        }
+    }
+    distribution
+}
+
+/// Read DynamoDB items as peers. Malformed items are skipped, but logged with their id so
+/// corrupt data doesn't go unnoticed.
+fn peers_from_items(items: &[HashMap<String, AttributeValue>]) -> Vec<PeerData> {
+    items.iter().filter_map(peer_from_item).collect()
+}
+
+/// Read a DynamoDB item as a peer, `None` and logged if it is malformed.
+fn peer_from_item(item: &HashMap<String, AttributeValue>) -> Option<PeerData> {
+    match PeerData::try_from(item) {
+        Ok(peer_data) => Some(peer_data),
+        Err(err) => {
+            warn!(
+                "Skipping malformed item of peer {}: {}",
+                as_string(item.get("peer-id"), &"".to_string()),
+                err
+            );
+            None
+        }
+    }
+}
+
+/// Region of a DynamoDB client, for logs.
+fn client_region(client: &Client) -> String {
+    client.config().region().map_or_else(
+        || "the default region".to_string(),
+        |region| region.to_string(),
+    )
+}
+
+/// Whether DynamoDB rejected a write because of throttling, in which case it's worth retrying.
+fn is_throttling_error<E: ProvideErrorMetadata>(err: &SdkError<E>) -> bool {
+    matches!(
+        err.as_service_error().and_then(|err| err.code()),
+        Some("ProvisionedThroughputExceededException")
+            | Some("RequestLimitExceeded")
+            | Some("ThrottlingException")
+    )
+}
+
+/// Whether DynamoDB rejected a write because an item exceeds its maximum item size.
+fn is_item_too_large_error<E: ProvideErrorMetadata>(err: &SdkError<E>) -> bool {
+    err.as_service_error().is_some_and(|err| {
+        err.code() == Some("ValidationException")
+            && err
+                .message()
+                .is_some_and(|message| message.contains("size"))
+    })
+}
+
+/// Exponential backoff with full jitter for the `attempt`-th retry of a throttled write.
+fn write_backoff(attempt: u32) -> std::time::Duration {
+    let max_delay = WRITE_BACKOFF_BASE_MS
+        .saturating_mul(1 << attempt.min(16))
+        .min(WRITE_BACKOFF_MAX_MS);
+    std::time::Duration::from_millis(rand::thread_rng().gen_range(0..=max_delay))
+}
+
+#[async_trait]
+impl PeerDB for AwsPeerDB {
+    async fn add_peer(&self, peer_data: PeerData, ttl: Option<i64>) -> Result<(), AddItemError> {
+        let (id, address) = (peer_data.id.clone(), peer_data.address.clone());
+        let item = self.peer_item(peer_data, ttl);
+        self.write_to_regions(|client| self.put_peer_item(client, &id, &address, item.clone()))
+            .await
+    }
+
+    /// `BatchWriteItem` doesn't support condition expressions, so the peers are written with the
+    /// conditional puts of [`PeerDB::add_peer`], [`CONCURRENT_PUTS`] at a time, and an older
+    /// observation leaves the stored peer as it is like there. Throttled puts are retried with
+    /// the same backoff.
+    async fn add_peers(&self, peers: Vec<PeerData>, ttl: Option<i64>) -> Result<(), AddItemError> {
+        let items: Vec<_> = peers
+            .into_iter()
+            .map(|peer_data| {
+                let (id, address) = (peer_data.id.clone(), peer_data.address.clone());
+                (id, address, self.peer_item(peer_data, ttl))
+            })
+            .collect();
+        self.write_to_regions(|client| self.put_peer_items(client, &items))
+            .await
+    }
+
+    async fn all_peers(
+        &self,
+        page_size: Option<i32>,
+        require_geo: bool,
+    ) -> Result<Vec<PeerData>, ScanTableError> {
+        self.all_peers_since(None, page_size, require_geo).await
+    }
+
+    /// Streams the scan paginator, which fetches the next page once the current one is consumed.
+    fn peers_stream(&self, require_geo: bool) -> BoxStream<'_, Result<PeerData, ScanTableError>> {
+        let scan = self.client.scan().table_name(&self.table);
+        // skip the reserved items
+        let items = filter_scan(scan, "NOT begins_with(#id, :reserved)", require_geo)
+            .expression_attribute_names("#id", "peer-id")
+            .expression_attribute_values(
+                ":reserved",
+                AttributeValue::S(RESERVED_ID_PREFIX.to_string()),
+            )
+            .into_paginator()
+            .items()
+            .send();
+        Box::pin(items.filter_map(|item| match item {
+            Ok(item) => peer_from_item(&item).map(Ok),
+            Err(err) => Some(Err(err.into())),
+        }))
+    }
 
     async fn node_by_id(&self, id: String) -> Result<Option<Vec<PeerData>>, QueryItemError> {
+        let id = normalize_peer_id(&id);
         let results = self
             .client
             .query()
-            .table_name("eth-peer-data")
+            .table_name(&self.table)
+            .consistent_read(self.consistent_read)
             .key_condition_expression("#id = :id")
             .expression_attribute_names("#id", "peer-id")
             .expression_attribute_values(":id", AttributeValue::S(id))
@@ -151,18 +1118,18 @@ impl PeerDB for AwsPeerDB {
             .await?;
 
         if let Some(nodes) = results.items {
-            let node = nodes.iter().map(|v| v.into()).collect();
-            Ok(Some(node))
+            Ok(Some(peers_from_items(&nodes)))
         } else {
             Ok(None)
         }
     }
 
     async fn node_by_ip(&self, ip: String) -> Result<Option<Vec<PeerData>>, QueryItemError> {
+        let ip = normalize_ip(&ip);
         let results = self
             .client
             .query()
-            .table_name("eth-peer-data")
+            .table_name(&self.table)
             .index_name("peer-ip-index")
             .key_condition_expression("#ip = :ip")
             .expression_attribute_names("#ip", "peer-ip")
@@ -171,284 +1138,1948 @@ impl PeerDB for AwsPeerDB {
             .await?;
 
         if let Some(nodes) = results.items {
-            let node = nodes.iter().map(|v| v.into()).collect();
-            Ok(Some(node))
+            Ok(Some(peers_from_items(&nodes)))
         } else {
             Ok(None)
         }
     }
-}
 
-#[derive(Clone)]
-pub struct InMemoryPeerDB {
-    db: Arc<RwLock<HashMap<String, PeerData>>>,
-}
+    async fn nodes_by_ip_version(&self, v6: bool) -> Result<Vec<PeerData>, ScanTableError> {
+        let results: Result<Vec<_>, _> = self
+            .client
+            .scan()
+            .table_name(&self.table)
+            .filter_expression("is_ipv6 = :is_ipv6")
+            .expression_attribute_values(":is_ipv6", AttributeValue::Bool(v6))
+            .into_paginator()
+            .items()
+            .send()
+            .collect()
+            .await;
 
-impl InMemoryPeerDB {
-    pub fn new() -> Self {
-        Self {
-            db: Arc::new(RwLock::new(HashMap::new())),
+        match results {
+            Ok(peers) => Ok(peers_from_items(&peers)),
+            Err(err) => Err(err.into()),
         }
     }
+
+    async fn count_peers(&self) -> Result<u64, ScanTableError> {
+        let pages: Vec<_> = self
+            .client
+            .scan()
+            .table_name(&self.table)
+            .select(Select::Count)
+            // skip the reserved items
+            .filter_expression("NOT begins_with(#id, :reserved)")
+            .expression_attribute_names("#id", "peer-id")
+            .expression_attribute_values(
+                ":reserved",
+                AttributeValue::S(RESERVED_ID_PREFIX.to_string()),
+            )
+            .into_paginator()
+            .send()
+            .collect::<Result<Vec<_>, _>>()
+            .await?;
+        Ok(pages.iter().map(|page| page.count as u64).sum())
+    }
+
+    async fn distinct_ip_count(&self) -> Result<u64, ScanTableError> {
+        Ok(self.scan_distinct("peer-ip").await? as u64)
+    }
+
+    async fn distinct_peer_id_count(&self) -> Result<u64, ScanTableError> {
+        Ok(self.scan_distinct("peer-id").await? as u64)
+    }
+
+    async fn peers_seen_between(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        page_size: Option<i32>,
+    ) -> Result<Vec<PeerData>, ScanTableError> {
+        let page_size = page_size.unwrap_or(1000);
+        let results: Result<Vec<_>, _> = self
+            .client
+            .scan()
+            .table_name(&self.table)
+            .filter_expression("last_seen BETWEEN :start AND :end")
+            .expression_attribute_values(":start", AttributeValue::S(start.to_string()))
+            .expression_attribute_values(":end", AttributeValue::S(end.to_string()))
+            .limit(page_size)
+            .into_paginator()
+            .items()
+            .send()
+            .collect()
+            .await;
+
+        match results {
+            Ok(peers) => Ok(peers_from_items(&peers)),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Note: this scans the peers of the last 24 hours and sorts them client-side, since the table
+    /// has no index on `last_seen`. A GSI on `last_seen` would be needed to avoid the scan.
+    async fn recent_peers(&self, limit: i32) -> Result<Vec<PeerData>, ScanTableError> {
+        let mut peers = self.all_peers(None, false).await?;
+        peers.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+        peers.truncate(limit.max(0) as usize);
+        Ok(peers)
+    }
+
+    /// Unlike [`PeerDB::recent_peers`] this scans the whole table, since the stale peers are
+    /// precisely the ones outside of the default `last_seen` window.
+    async fn stale_peers(&self, limit: i32) -> Result<Vec<PeerData>, ScanTableError> {
+        let mut peers = self.scan_all().await?;
+        peers.sort_by(|a, b| a.last_seen.cmp(&b.last_seen));
+        peers.truncate(limit.max(0) as usize);
+        Ok(peers)
+    }
+
+    /// `total_difficulty` is stored as a string, so this scans the table and compares client-side.
+    async fn peers_below_difficulty(
+        &self,
+        threshold: U256,
+    ) -> Result<Vec<PeerData>, ScanTableError> {
+        let mut peers = self.scan_all().await?;
+        peers.retain(|peer| below_difficulty(peer, threshold));
+        Ok(peers)
+    }
+
+    async fn peers_behind_head(
+        &self,
+        current_head: u64,
+        lag: u64,
+    ) -> Result<Vec<PeerData>, ScanTableError> {
+        let threshold = current_head.saturating_sub(lag);
+        let results: Result<Vec<_>, _> = self
+            .client
+            .scan()
+            .table_name(&self.table)
+            .filter_expression("best_block_number < :threshold")
+            .expression_attribute_values(":threshold", AttributeValue::N(threshold.to_string()))
+            .into_paginator()
+            .items()
+            .send()
+            .collect()
+            .await;
+
+        match results {
+            Ok(peers) => Ok(peers_from_items(&peers)),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn client_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError> {
+        Ok(count_client_families(
+            self.scan_all()
+                .await?
+                .iter()
+                .map(|peer| peer.client_version.as_str()),
+        ))
+    }
+
+    async fn country_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError> {
+        Ok(count_countries(
+            self.scan_all()
+                .await?
+                .iter()
+                .map(|peer| (peer.country.as_str(), 1)),
+        ))
+    }
+
+    async fn capability_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError> {
+        Ok(count_capabilities(
+            self.scan_all()
+                .await?
+                .iter()
+                .map(|peer| (peer.capabilities.as_slice(), 1)),
+        ))
+    }
+
+    async fn disconnect_reason_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError> {
+        Ok(count_disconnect_reasons(
+            self.scan_all()
+                .await?
+                .iter()
+                .filter_map(|peer| peer.last_disconnect_reason.as_deref()),
+        ))
+    }
+
+    async fn asn_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError> {
+        Ok(count_asns(self.scan_all().await?.iter().filter_map(
+            |peer| Some((peer.asn?, peer.org.as_deref(), 1)),
+        )))
+    }
+
+    /// Like [`PeerDB::node_by_id`] this queries on the partition key, as a peer id isn't assumed
+    /// to be the full primary key, but it only reads the key of at most one item.
+    async fn peer_exists(&self, id: String) -> Result<bool, QueryItemError> {
+        let id = normalize_peer_id(&id);
+        let results = self
+            .client
+            .query()
+            .table_name(&self.table)
+            .consistent_read(self.consistent_read)
+            .key_condition_expression("#id = :id")
+            .expression_attribute_names("#id", "peer-id")
+            .expression_attribute_values(":id", AttributeValue::S(id))
+            .projection_expression("#id")
+            .limit(1)
+            .send()
+            .await?;
+
+        Ok(results.count > 0)
+    }
+
+    /// Reads the keys of the peers through `BatchGetItem`, in chunks of 100, when `peer-id` is
+    /// the whole primary key of the table, and queries every id otherwise, as the items of a
+    /// table with a sort key can't be read by `peer-id` alone. Keys left unprocessed because of
+    /// throttling are retried with the same backoff as the writes.
+    async fn peers_exist(&self, ids: Vec<String>) -> Result<HashSet<String>, QueryItemError> {
+        let ids: HashSet<String> = ids.iter().map(|id| normalize_peer_id(id)).collect();
+        let has_sort_key = self
+            .key_schema(&self.client)
+            .await?
+            .iter()
+            .any(|key| key.key_type == Some(KeyType::Range));
+        if has_sort_key {
+            return self.peers_exist_by_query(ids.into_iter().collect()).await;
+        }
+        let keys: Vec<HashMap<String, AttributeValue>> = ids
+            .into_iter()
+            .map(|id| HashMap::from([("peer-id".to_string(), AttributeValue::S(id))]))
+            .collect();
+        let mut found = HashSet::new();
+        for chunk in keys.chunks(BATCH_GET_SIZE) {
+            let mut pending = chunk.to_vec();
+            let mut attempt = 1;
+            loop {
+                let request = KeysAndAttributes::builder()
+                    .set_keys(Some(pending.clone()))
+                    .projection_expression("#id")
+                    .expression_attribute_names("#id", "peer-id")
+                    .consistent_read(self.consistent_read)
+                    .build();
+                match self
+                    .client
+                    .batch_get_item()
+                    .request_items(&self.table, request)
+                    .send()
+                    .await
+                {
+                    Ok(output) => {
+                        let items = output
+                            .responses
+                            .and_then(|mut responses| responses.remove(&self.table))
+                            .unwrap_or_default();
+                        found.extend(
+                            items
+                                .iter()
+                                .filter_map(|item| item.get("peer-id")?.as_s().ok().cloned()),
+                        );
+                        pending = output
+                            .unprocessed_keys
+                            .and_then(|mut unprocessed| unprocessed.remove(&self.table))
+                            .and_then(|unprocessed| unprocessed.keys)
+                            .unwrap_or_default();
+                        if pending.is_empty() {
+                            break;
+                        }
+                        if attempt >= self.max_write_attempts {
+                            return Err(QueryItemError::AwsUnprocessedKeysError(pending.len()));
+                        }
+                    }
+                    Err(e) if attempt < self.max_write_attempts && is_throttling_error(&e) => {}
+                    Err(e) => return Err(e.into()),
+                }
+                tokio::time::sleep(write_backoff(attempt)).await;
+                attempt += 1;
+            }
+        }
+        Ok(found)
+    }
+
+    async fn nodes_by_source(&self, source: String) -> Result<Vec<PeerData>, ScanTableError> {
+        let results: Result<Vec<_>, _> = self
+            .client
+            .scan()
+            .table_name(&self.table)
+            .filter_expression("discovery_source = :source")
+            .expression_attribute_values(":source", AttributeValue::S(source))
+            .into_paginator()
+            .items()
+            .send()
+            .collect()
+            .await;
+
+        match results {
+            Ok(peers) => Ok(peers_from_items(&peers)),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn peers_by_session(&self, session_id: String) -> Result<Vec<PeerData>, ScanTableError> {
+        let results: Result<Vec<_>, _> = self
+            .client
+            .scan()
+            .table_name(&self.table)
+            .filter_expression("session_id = :session_id")
+            .expression_attribute_values(":session_id", AttributeValue::S(session_id))
+            .into_paginator()
+            .items()
+            .send()
+            .collect()
+            .await;
+
+        match results {
+            Ok(peers) => Ok(peers_from_items(&peers)),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Items written before `serves_snap` was stored are counted if they advertise `snap/1`.
+    async fn snap_server_count(&self) -> Result<u64, ScanTableError> {
+        let pages: Vec<_> = self
+            .client
+            .scan()
+            .table_name(&self.table)
+            .select(Select::Count)
+            .filter_expression(
+                "serves_snap = :true OR (attribute_not_exists(serves_snap) AND contains(capabilities, :snap))",
+            )
+            .expression_attribute_values(":true", AttributeValue::Bool(true))
+            .expression_attribute_values(":snap", AttributeValue::S("snap/1".to_string()))
+            .into_paginator()
+            .send()
+            .collect::<Result<Vec<_>, _>>()
+            .await?;
+        Ok(pages.iter().map(|page| page.count as u64).sum())
+    }
+
+    async fn nodes_by_role(&self, role: String) -> Result<Vec<PeerData>, ScanTableError> {
+        let results: Result<Vec<_>, _> = self
+            .client
+            .scan()
+            .table_name(&self.table)
+            .filter_expression("node_role = :role")
+            .expression_attribute_values(":role", AttributeValue::S(role))
+            .into_paginator()
+            .items()
+            .send()
+            .collect()
+            .await;
+
+        match results {
+            Ok(peers) => Ok(peers_from_items(&peers)),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn nodes_by_capability(
+        &self,
+        capability: String,
+        page_size: Option<i32>,
+    ) -> Result<Vec<PeerData>, QueryItemError> {
+        let page_size = page_size.unwrap_or(1000);
+        // `contains` on a list attribute matches whole elements
+        let results: Result<Vec<_>, _> = self
+            .client
+            .scan()
+            .table_name(&self.table)
+            .filter_expression("contains(capabilities, :cap)")
+            .expression_attribute_values(":cap", AttributeValue::S(capability))
+            .limit(page_size)
+            .into_paginator()
+            .items()
+            .send()
+            .collect()
+            .await;
+
+        match results {
+            Ok(peers) => Ok(peers_from_items(&peers)),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// The table isn't assumed to be keyed by peer id alone, so it may hold a record of a peer
+    /// per `source_region`: the whole table is scanned and collapsed here.
+    async fn latest_per_peer(
+        &self,
+        page_size: Option<i32>,
+    ) -> Result<Vec<PeerData>, ScanTableError> {
+        let page_size = page_size.unwrap_or(1000);
+        let items: Vec<_> = self
+            .client
+            .scan()
+            .table_name(&self.table)
+            // skip the reserved items
+            .filter_expression("NOT begins_with(#id, :reserved)")
+            .expression_attribute_names("#id", "peer-id")
+            .expression_attribute_values(
+                ":reserved",
+                AttributeValue::S(RESERVED_ID_PREFIX.to_string()),
+            )
+            .limit(page_size)
+            .into_paginator()
+            .items()
+            .send()
+            .collect::<Result<_, _>>()
+            .await?;
+        Ok(latest_per_id(peers_from_items(&items)))
+    }
+
+    /// DynamoDB can't compare addresses as ranges, so the whole table is scanned and filtered
+    /// here.
+    async fn nodes_by_cidr(
+        &self,
+        cidr: String,
+        page_size: Option<i32>,
+    ) -> Result<Vec<PeerData>, QueryItemError> {
+        let cidr = Cidr::parse(&cidr)?;
+        let page_size = page_size.unwrap_or(1000);
+        let items: Vec<_> = self
+            .client
+            .scan()
+            .table_name(&self.table)
+            // skip the reserved items
+            .filter_expression("NOT begins_with(#id, :reserved)")
+            .expression_attribute_names("#id", "peer-id")
+            .expression_attribute_values(
+                ":reserved",
+                AttributeValue::S(RESERVED_ID_PREFIX.to_string()),
+            )
+            .limit(page_size)
+            .into_paginator()
+            .items()
+            .send()
+            .collect::<Result<_, _>>()
+            .await?;
+        Ok(peers_from_items(&items)
+            .into_iter()
+            .filter(|peer_data| cidr.contains(&peer_data.address))
+            .collect())
+    }
+
+    /// Items expire by themselves through their `ttl` attribute, which the crawler sets
+    /// `--ttl-days` after they were last written, so there is nothing to delete here.
+    async fn prune_peers(&self, _: i64) -> Result<u64, DeleteItemError> {
+        Ok(0)
+    }
+
+    /// Scans the keys of every peer item and deletes them through `BatchWriteItem`,
+    /// in every region. This consumes read and write capacity for the whole table, so deleting
+    /// and recreating the table is preferable for large tables.
+    async fn clear(&self) -> Result<(), DeleteItemError> {
+        for client in self.clients() {
+            let items: Vec<_> = client
+                .scan()
+                .table_name(&self.table)
+                // keep the meta item and the peer count history
+                .filter_expression("NOT begins_with(#id, :reserved)")
+                .expression_attribute_names("#id", "peer-id")
+                .expression_attribute_values(
+                    ":reserved",
+                    AttributeValue::S(RESERVED_ID_PREFIX.to_string()),
+                )
+                .into_paginator()
+                .items()
+                .send()
+                .collect::<Result<_, _>>()
+                .await?;
+            let deleted = self.batch_delete(client, items).await?;
+            info!(
+                "Deleted {} items from {} in {}",
+                deleted,
+                self.table,
+                client_region(client)
+            );
+        }
+        Ok(())
+    }
+
+    /// Queries the items of the peer's partition key and deletes them, in every region.
+    async fn delete_peer(&self, id: String) -> Result<bool, DeleteItemError> {
+        let mut found = false;
+        for client in self.clients() {
+            let items: Vec<_> = client
+                .query()
+                .table_name(&self.table)
+                .key_condition_expression("#id = :id")
+                .expression_attribute_names("#id", "peer-id")
+                .expression_attribute_values(":id", AttributeValue::S(id.clone()))
+                .into_paginator()
+                .items()
+                .send()
+                .collect::<Result<_, _>>()
+                .await?;
+            found |= self.batch_delete(client, items).await? > 0;
+        }
+        Ok(found)
+    }
+
+    /// Snapshots are reserved items of the table, whose `peer-id` is the timestamp prefixed with
+    /// `__count__#`, written to every region.
+    async fn record_peer_count(&self, snapshot: PeerCountSnapshot) -> Result<(), AddItemError> {
+        let id = format!("{COUNT_HISTORY_ID_PREFIX}{}", snapshot.timestamp);
+        self.write_to_regions(|client| {
+            let (id, snapshot) = (id.clone(), snapshot.clone());
+            async move {
+                client
+                    .put_item()
+                    .table_name(&self.table)
+                    .item("peer-id", AttributeValue::S(id))
+                    .item("timestamp", AttributeValue::S(snapshot.timestamp))
+                    .item("count", AttributeValue::N(snapshot.count.to_string()))
+                    .send()
+                    .await?;
+                Ok(())
+            }
+        })
+        .await
+    }
+
+    /// Scans the whole table for the snapshot items, as they share it with the peers.
+    async fn count_history(
+        &self,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<PeerCountSnapshot>, ScanTableError> {
+        let items: Vec<_> = self
+            .client
+            .scan()
+            .table_name(&self.table)
+            .filter_expression("begins_with(#id, :prefix) AND #timestamp >= :since")
+            .expression_attribute_names("#id", "peer-id")
+            .expression_attribute_names("#timestamp", "timestamp")
+            .expression_attribute_values(
+                ":prefix",
+                AttributeValue::S(COUNT_HISTORY_ID_PREFIX.to_string()),
+            )
+            .expression_attribute_values(":since", AttributeValue::S(since.to_string()))
+            .into_paginator()
+            .items()
+            .send()
+            .collect::<Result<_, _>>()
+            .await?;
+        let mut history: Vec<PeerCountSnapshot> = items
+            .iter()
+            .map(|item| PeerCountSnapshot {
+                timestamp: as_string(item.get("timestamp"), &"".to_string()),
+                count: as_opt_u64(item.get("count")).unwrap_or_default(),
+            })
+            .collect();
+        history.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+        Ok(history)
+    }
+
+    /// A peer id isn't assumed to be the full primary key, so every item of the peer is read and
+    /// written back with its new location, keeping its other attributes, `ttl` included, in every
+    /// region.
+    async fn update_location(
+        &self,
+        id: String,
+        country: String,
+        city: String,
+    ) -> Result<bool, UpdateItemError> {
+        let id = normalize_peer_id(&id);
+        let update = |item: &mut HashMap<String, AttributeValue>| {
+            item.insert("country".to_string(), AttributeValue::S(country.clone()));
+            item.insert("city".to_string(), AttributeValue::S(city.clone()));
+        };
+        self.write_to_regions(|client| self.update_peer_items(client, &id, None, &update))
+            .await
+    }
+
+    /// Like [`AwsPeerDB::update_location`], every item of the peer is read and written back, in
+    /// every region. Like [`PeerDB::add_peer`], items stored with a later `last_seen` are left as
+    /// they are.
+    async fn update_chain_status(
+        &self,
+        id: String,
+        best_block: String,
+        best_block_number: Option<u64>,
+        total_difficulty: String,
+        last_seen: String,
+    ) -> Result<bool, UpdateItemError> {
+        let id = normalize_peer_id(&id);
+        let update = |item: &mut HashMap<String, AttributeValue>| {
+            item.insert(
+                "best_block".to_string(),
+                AttributeValue::S(best_block.clone()),
+            );
+            match best_block_number {
+                Some(best_block_number) => item.insert(
+                    "best_block_number".to_string(),
+                    AttributeValue::N(best_block_number.to_string()),
+                ),
+                None => item.remove("best_block_number"),
+            };
+            item.insert(
+                "total_difficulty".to_string(),
+                AttributeValue::S(total_difficulty.clone()),
+            );
+            item.insert(
+                "last_seen".to_string(),
+                AttributeValue::S(last_seen.clone()),
+            );
+        };
+        self.write_to_regions(|client| {
+            self.update_peer_items(client, &id, Some(&last_seen), &update)
+        })
+        .await
+    }
+
+    /// Read the reserved item written by [`AwsPeerDB::write_schema_info`].
+    async fn schema_info(&self) -> Result<SchemaInfo, QueryItemError> {
+        let results = self
+            .client
+            .query()
+            .table_name(&self.table)
+            .consistent_read(self.consistent_read)
+            .key_condition_expression("#id = :id")
+            .expression_attribute_names("#id", "peer-id")
+            .expression_attribute_values(":id", AttributeValue::S(META_ITEM_ID.to_string()))
+            .limit(1)
+            .send()
+            .await?;
+
+        let item = results
+            .items
+            .and_then(|items| items.into_iter().next())
+            .ok_or(QueryItemError::MissingSchemaInfoError())?;
+        Ok(SchemaInfo {
+            crate_version: as_string(item.get("crate_version"), &"".to_string()),
+            schema_version: as_opt_u64(item.get("schema_version"))
+                .ok_or(QueryItemError::MissingSchemaInfoError())?
+                as u32,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct InMemoryPeerDB {
+    db: Arc<RwLock<HashMap<String, PeerData>>>,
+    count_history: Arc<RwLock<Vec<PeerCountSnapshot>>>,
+}
+
+impl InMemoryPeerDB {
+    pub fn new() -> Self {
+        Self {
+            db: Arc::new(RwLock::new(HashMap::new())),
+            count_history: Arc::new(RwLock::new(Vec::new())),
+        }
+    }
+}
+
+#[async_trait]
+impl PeerDB for InMemoryPeerDB {
+    async fn add_peer(&self, peer_data: PeerData, _: Option<i64>) -> Result<(), AddItemError> {
+        let mut db = self.db.write().map_err(|_| {
+            AddItemError::InMemoryDbAddItemError().with_peer(&peer_data.id, &peer_data.address)
+        })?;
+        insert_if_newer(&mut db, with_normalized_ip(peer_data));
+        Ok(())
+    }
+
+    async fn add_peers(&self, peers: Vec<PeerData>, _: Option<i64>) -> Result<(), AddItemError> {
+        let Some(first) = peers.first() else {
+            return Ok(());
+        };
+        // nothing is inserted if the lock is poisoned, so the first insert is the failing one
+        let mut db = self.db.write().map_err(|_| {
+            AddItemError::InMemoryDbAddItemError().with_peer(&first.id, &first.address)
+        })?;
+        for peer_data in peers {
+            insert_if_newer(&mut db, with_normalized_ip(peer_data));
+        }
+        Ok(())
+    }
+
+    async fn all_peers(
+        &self,
+        page_size: Option<i32>,
+        require_geo: bool,
+    ) -> Result<Vec<PeerData>, ScanTableError> {
+        let page_size = page_size.unwrap_or(50);
+        let db = self
+            .db
+            .read()
+            .map_err(|_| ScanTableError::InMemoryDbScanError())?;
+        Ok(db
+            .iter()
+            .map(|(_, peer_data)| peer_data)
+            .filter(|peer_data| !require_geo || !peer_data.country.is_empty())
+            .cloned()
+            .take(page_size as usize)
+            .collect())
+    }
+
+    /// Streams a snapshot of the peers, which are in memory anyway.
+    fn peers_stream(&self, require_geo: bool) -> BoxStream<'_, Result<PeerData, ScanTableError>> {
+        let peers: Vec<_> = match self.db.read() {
+            Ok(db) => db
+                .values()
+                .filter(|peer_data| !require_geo || !peer_data.country.is_empty())
+                .cloned()
+                .map(Ok)
+                .collect(),
+            Err(_) => vec![Err(ScanTableError::InMemoryDbScanError())],
+        };
+        Box::pin(stream::iter(peers))
+    }
+
+    async fn node_by_id(&self, id: String) -> Result<Option<Vec<PeerData>>, QueryItemError> {
+        let id = normalize_peer_id(&id);
+        let db = self
+            .db
+            .read()
+            .map_err(|_| QueryItemError::InMemoryDbQueryItemError())?;
+        Ok(Some(
+            db.iter()
+                .filter(|(peer_id, _)| **peer_id == id)
+                .map(|(_, peer_data)| peer_data.clone())
+                .collect(),
+        ))
+    }
+
+    async fn node_by_ip(&self, ip: String) -> Result<Option<Vec<PeerData>>, QueryItemError> {
+        let ip = normalize_ip(&ip);
+        let db = self
+            .db
+            .read()
+            .map_err(|_| QueryItemError::InMemoryDbQueryItemError())?;
+        Ok(Some(
+            db.iter()
+                .filter(|(_, peer_data)| peer_data.address == ip)
+                .map(|(_, peer_data)| peer_data.clone())
+                .collect(),
+        ))
+    }
+
+    async fn nodes_by_ip_version(&self, v6: bool) -> Result<Vec<PeerData>, ScanTableError> {
+        let db = self
+            .db
+            .read()
+            .map_err(|_| ScanTableError::InMemoryDbScanError())?;
+        Ok(db
+            .iter()
+            .filter(|(_, peer_data)| parse_is_ipv6(&peer_data.address) == Some(v6))
+            .map(|(_, peer_data)| peer_data.clone())
+            .collect())
+    }
+
+    async fn count_peers(&self) -> Result<u64, ScanTableError> {
+        let db = self
+            .db
+            .read()
+            .map_err(|_| ScanTableError::InMemoryDbScanError())?;
+        Ok(db.len() as u64)
+    }
+
+    async fn distinct_ip_count(&self) -> Result<u64, ScanTableError> {
+        let db = self
+            .db
+            .read()
+            .map_err(|_| ScanTableError::InMemoryDbScanError())?;
+        Ok(db
+            .values()
+            .map(|peer_data| &peer_data.address)
+            .collect::<HashSet<_>>()
+            .len() as u64)
+    }
+
+    async fn distinct_peer_id_count(&self) -> Result<u64, ScanTableError> {
+        let db = self
+            .db
+            .read()
+            .map_err(|_| ScanTableError::InMemoryDbScanError())?;
+        Ok(db
+            .values()
+            .map(|peer_data| &peer_data.id)
+            .collect::<HashSet<_>>()
+            .len() as u64)
+    }
+
+    async fn peers_seen_between(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        _: Option<i32>,
+    ) -> Result<Vec<PeerData>, ScanTableError> {
+        let (start, end) = (start.to_string(), end.to_string());
+        let db = self
+            .db
+            .read()
+            .map_err(|_| ScanTableError::InMemoryDbScanError())?;
+        Ok(db
+            .iter()
+            .filter(|(_, peer_data)| peer_data.last_seen >= start && peer_data.last_seen <= end)
+            .map(|(_, peer_data)| peer_data.clone())
+            .collect())
+    }
+
+    async fn recent_peers(&self, limit: i32) -> Result<Vec<PeerData>, ScanTableError> {
+        let db = self
+            .db
+            .read()
+            .map_err(|_| ScanTableError::InMemoryDbScanError())?;
+        let mut peers: Vec<PeerData> = db.values().cloned().collect();
+        peers.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+        peers.truncate(limit.max(0) as usize);
+        Ok(peers)
+    }
+
+    async fn stale_peers(&self, limit: i32) -> Result<Vec<PeerData>, ScanTableError> {
+        let db = self
+            .db
+            .read()
+            .map_err(|_| ScanTableError::InMemoryDbScanError())?;
+        let mut peers: Vec<PeerData> = db.values().cloned().collect();
+        peers.sort_by(|a, b| a.last_seen.cmp(&b.last_seen));
+        peers.truncate(limit.max(0) as usize);
+        Ok(peers)
+    }
+
+    async fn peers_below_difficulty(
+        &self,
+        threshold: U256,
+    ) -> Result<Vec<PeerData>, ScanTableError> {
+        let db = self
+            .db
+            .read()
+            .map_err(|_| ScanTableError::InMemoryDbScanError())?;
+        Ok(db
+            .values()
+            .filter(|peer| below_difficulty(peer, threshold))
+            .cloned()
+            .collect())
+    }
+
+    async fn peers_behind_head(
+        &self,
+        current_head: u64,
+        lag: u64,
+    ) -> Result<Vec<PeerData>, ScanTableError> {
+        let threshold = current_head.saturating_sub(lag);
+        let db = self
+            .db
+            .read()
+            .map_err(|_| ScanTableError::InMemoryDbScanError())?;
+        Ok(db
+            .values()
+            .filter(|peer| matches!(peer.best_block_number, Some(number) if number < threshold))
+            .cloned()
+            .collect())
+    }
+
+    async fn client_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError> {
+        let db = self
+            .db
+            .read()
+            .map_err(|_| ScanTableError::InMemoryDbScanError())?;
+        Ok(count_client_families(
+            db.values()
+                .map(|peer_data| peer_data.client_version.as_str()),
+        ))
+    }
+
+    async fn country_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError> {
+        let db = self
+            .db
+            .read()
+            .map_err(|_| ScanTableError::InMemoryDbScanError())?;
+        Ok(count_countries(
+            db.values().map(|peer_data| (peer_data.country.as_str(), 1)),
+        ))
+    }
+
+    async fn capability_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError> {
+        let db = self
+            .db
+            .read()
+            .map_err(|_| ScanTableError::InMemoryDbScanError())?;
+        Ok(count_capabilities(
+            db.values()
+                .map(|peer_data| (peer_data.capabilities.as_slice(), 1)),
+        ))
+    }
+
+    async fn disconnect_reason_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError> {
+        let db = self
+            .db
+            .read()
+            .map_err(|_| ScanTableError::InMemoryDbScanError())?;
+        Ok(count_disconnect_reasons(db.values().filter_map(
+            |peer_data| peer_data.last_disconnect_reason.as_deref(),
+        )))
+    }
+
+    async fn asn_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError> {
+        let db = self
+            .db
+            .read()
+            .map_err(|_| ScanTableError::InMemoryDbScanError())?;
+        Ok(count_asns(db.values().filter_map(|peer_data| {
+            Some((peer_data.asn?, peer_data.org.as_deref(), 1))
+        })))
+    }
+
+    async fn peer_exists(&self, id: String) -> Result<bool, QueryItemError> {
+        let id = normalize_peer_id(&id);
+        let db = self
+            .db
+            .read()
+            .map_err(|_| QueryItemError::InMemoryDbQueryItemError())?;
+        Ok(db.contains_key(&id))
+    }
+
+    async fn peers_exist(&self, ids: Vec<String>) -> Result<HashSet<String>, QueryItemError> {
+        let db = self
+            .db
+            .read()
+            .map_err(|_| QueryItemError::InMemoryDbQueryItemError())?;
+        Ok(ids
+            .iter()
+            .map(|id| normalize_peer_id(id))
+            .filter(|id| db.contains_key(id))
+            .collect())
+    }
+
+    async fn nodes_by_source(&self, source: String) -> Result<Vec<PeerData>, ScanTableError> {
+        let db = self
+            .db
+            .read()
+            .map_err(|_| ScanTableError::InMemoryDbScanError())?;
+        Ok(db
+            .values()
+            .filter(|peer_data| peer_data.discovery_source == source)
+            .cloned()
+            .collect())
+    }
+
+    async fn peers_by_session(&self, session_id: String) -> Result<Vec<PeerData>, ScanTableError> {
+        let db = self
+            .db
+            .read()
+            .map_err(|_| ScanTableError::InMemoryDbScanError())?;
+        Ok(db
+            .values()
+            .filter(|peer_data| peer_data.session_id == session_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn snap_server_count(&self) -> Result<u64, ScanTableError> {
+        let db = self
+            .db
+            .read()
+            .map_err(|_| ScanTableError::InMemoryDbScanError())?;
+        Ok(db
+            .values()
+            .filter(|peer_data| peer_data.serves_snap)
+            .count() as u64)
+    }
+
+    async fn nodes_by_role(&self, role: String) -> Result<Vec<PeerData>, ScanTableError> {
+        let db = self
+            .db
+            .read()
+            .map_err(|_| ScanTableError::InMemoryDbScanError())?;
+        Ok(db
+            .values()
+            .filter(|peer_data| peer_data.node_role.as_ref() == Some(&role))
+            .cloned()
+            .collect())
+    }
+
+    async fn nodes_by_capability(
+        &self,
+        capability: String,
+        _: Option<i32>,
+    ) -> Result<Vec<PeerData>, QueryItemError> {
+        let db = self
+            .db
+            .read()
+            .map_err(|_| QueryItemError::InMemoryDbQueryItemError())?;
+        Ok(db
+            .values()
+            .filter(|peer_data| peer_data.capabilities.contains(&capability))
+            .cloned()
+            .collect())
+    }
+
+    /// Peers are keyed by id, so there is a single record per peer already.
+    async fn latest_per_peer(&self, _: Option<i32>) -> Result<Vec<PeerData>, ScanTableError> {
+        let db = self
+            .db
+            .read()
+            .map_err(|_| ScanTableError::InMemoryDbScanError())?;
+        Ok(db.values().cloned().collect())
+    }
+
+    async fn nodes_by_cidr(
+        &self,
+        cidr: String,
+        _: Option<i32>,
+    ) -> Result<Vec<PeerData>, QueryItemError> {
+        let cidr = Cidr::parse(&cidr)?;
+        let db = self
+            .db
+            .read()
+            .map_err(|_| QueryItemError::InMemoryDbQueryItemError())?;
+        Ok(db
+            .values()
+            .filter(|peer_data| cidr.contains(&peer_data.address))
+            .cloned()
+            .collect())
+    }
+
+    async fn prune_peers(&self, time_validity: i64) -> Result<u64, DeleteItemError> {
+        let cutoff = Utc::now()
+            .checked_sub_signed(Duration::days(time_validity))
+            .unwrap()
+            .to_string();
+        let mut db = self
+            .db
+            .write()
+            .map_err(|_| DeleteItemError::InMemoryDbDeleteItemError())?;
+        let len = db.len();
+        db.retain(|_, peer_data| peer_data.last_seen >= cutoff);
+        Ok((len - db.len()) as u64)
+    }
+
+    async fn clear(&self) -> Result<(), DeleteItemError> {
+        self.db
+            .write()
+            .map_err(|_| DeleteItemError::InMemoryDbDeleteItemError())?
+            .clear();
+        Ok(())
+    }
+
+    async fn delete_peer(&self, id: String) -> Result<bool, DeleteItemError> {
+        Ok(self
+            .db
+            .write()
+            .map_err(|_| DeleteItemError::InMemoryDbDeleteItemError())?
+            .remove(&id)
+            .is_some())
+    }
+
+    async fn record_peer_count(&self, snapshot: PeerCountSnapshot) -> Result<(), AddItemError> {
+        self.count_history
+            .write()
+            .map_err(|_| AddItemError::InMemoryDbAddItemError())?
+            .push(snapshot);
+        Ok(())
+    }
+
+    async fn count_history(
+        &self,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<PeerCountSnapshot>, ScanTableError> {
+        let since = since.to_string();
+        let history = self
+            .count_history
+            .read()
+            .map_err(|_| ScanTableError::InMemoryDbScanError())?;
+        let mut history: Vec<PeerCountSnapshot> = history
+            .iter()
+            .filter(|snapshot| snapshot.timestamp >= since)
+            .cloned()
+            .collect();
+        history.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+        Ok(history)
+    }
+
+    async fn update_location(
+        &self,
+        id: String,
+        country: String,
+        city: String,
+    ) -> Result<bool, UpdateItemError> {
+        let id = normalize_peer_id(&id);
+        let mut db = self
+            .db
+            .write()
+            .map_err(|_| UpdateItemError::InMemoryDbUpdateItemError())?;
+        match db.get_mut(&id) {
+            Some(peer_data) => {
+                peer_data.country = country;
+                peer_data.city = city;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn update_chain_status(
+        &self,
+        id: String,
+        best_block: String,
+        best_block_number: Option<u64>,
+        total_difficulty: String,
+        last_seen: String,
+    ) -> Result<bool, UpdateItemError> {
+        let id = normalize_peer_id(&id);
+        let mut db = self
+            .db
+            .write()
+            .map_err(|_| UpdateItemError::InMemoryDbUpdateItemError())?;
+        match db.get_mut(&id) {
+            Some(peer_data) if peer_data.last_seen >= last_seen => Ok(true),
+            Some(peer_data) => {
+                peer_data.best_block = best_block;
+                peer_data.best_block_number = best_block_number;
+                peer_data.total_difficulty_u128 = parse_total_difficulty(&total_difficulty);
+                peer_data.total_difficulty = total_difficulty;
+                peer_data.last_seen = last_seen;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Nothing outlives an in memory db, so it's always written by this version of the crate.
+    async fn schema_info(&self) -> Result<SchemaInfo, QueryItemError> {
+        Ok(SchemaInfo::current())
+    }
+}
+
+/// SQLite backend.
+///
+/// Calls are spread round-robin over a pool of connections to the same file, which runs in WAL
+/// mode so that readers don't wait for the writer.
+pub struct SqlPeerDB {
+    pool: Vec<Connection>,
+    next: AtomicUsize,
+}
+
+/// Columns added to `eth_peer_data` after its first release, with their SQL type.
+///
+/// Databases created by older versions are migrated on startup by adding any missing column.
+/// New columns are always appended so that the positional indices used by [`peer_data_from_row`]
+/// stay valid for both fresh and migrated databases.
+const COLUMN_MIGRATIONS: &[(&str, &str)] = &[
+    ("udp_port", "INTEGER"),
+    ("is_ipv6", "INTEGER"),
+    ("best_block_number", "INTEGER"),
+    ("discovery_source", "TEXT"),
+    ("raw_handshake", "TEXT"),
+    ("node_role", "TEXT"),
+    ("tcp_connect_ms", "INTEGER"),
+    ("handshake_ms", "INTEGER"),
+    ("serves_snap", "INTEGER"),
+    ("last_disconnect_reason", "TEXT"),
+    ("asn", "INTEGER"),
+    ("org", "TEXT"),
+    ("session_id", "TEXT"),
+];
+
+impl SqlPeerDB {
+    pub async fn new() -> Self {
+        Self::open("peers_data.db").await
+    }
+
+    /// Open the sqlite db at `path`, creating it if it doesn't exist. The special path `:memory:`
+    /// opens an in memory db, see [`SqlPeerDB::new_in_memory`].
+    pub async fn open(path: impl AsRef<Path>) -> Self {
+        Self::open_with_pool(path, 1).await
+    }
+
+    /// Like [`SqlPeerDB::open`], with a pool of `pool_size` connections. An in memory db always
+    /// has a single connection, as every connection to `:memory:` is a distinct db.
+    pub async fn open_with_pool(path: impl AsRef<Path>, pool_size: usize) -> Self {
+        let path = path.as_ref();
+        if path == Path::new(IN_MEMORY_PATH) {
+            return Self::new_in_memory().await;
+        }
+        let db = Self::init(Connection::open(path).await.unwrap()).await;
+        let mut pool = db.pool;
+        for _ in 1..pool_size.max(1) {
+            pool.push(Self::configure(Connection::open(path).await.unwrap()).await);
+        }
+        Self {
+            pool,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Open a sqlite db that only lives in memory, so nothing touches the filesystem. Its peers
+    /// are lost once it's dropped.
+    pub async fn new_in_memory() -> Self {
+        Self::init(Connection::open_in_memory().await.unwrap()).await
+    }
+
+    /// The connection the next call goes through.
+    fn db(&self) -> &Connection {
+        &self.pool[self.next.fetch_add(1, Ordering::Relaxed) % self.pool.len()]
+    }
+
+    /// Switch the connection to WAL mode and make it wait for locks held by the other connections
+    /// of the pool instead of failing right away with "database is locked".
+    ///
+    /// WAL keeps `-wal` and `-shm` files next to the db file while it's open. `synchronous=NORMAL`
+    /// is safe in WAL mode: a power loss may lose the last commits, but never corrupts the db.
+    async fn configure(db: Connection) -> Connection {
+        db.call(|conn| {
+            conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| {
+                row.get::<_, String>(0)
+            })?;
+            conn.pragma_update(None, "synchronous", "NORMAL")?;
+            conn.busy_timeout(SQLITE_BUSY_TIMEOUT)
+        })
+        .await
+        .unwrap();
+        db
+    }
+
+    async fn init(db: Connection) -> Self {
+        let db = Self::configure(db).await;
+        // create `eth_peer_data` table if not exists
+        let _ = db
+            .call(|conn| {
+                conn.execute(
+                    "CREATE TABLE IF NOT EXISTS eth_peer_data (
+                id TEXT PRIMARY KEY,
+                ip TEXT NOT NULL,
+                client_version TEXT NOT NULL,
+                enode_url TEXT NOT NULL,
+                port INTEGER NOT NULL,
+                chain TEXT NOT NULL,
+                genesis_hash TEXT NOT NULL,
+                best_block TEXT NOT NULL,
+                total_difficulty TEXT NOT NULL,
+                country TEXT,
+                city TEXT,
+                last_seen TEXT NOT NULL,
+                capabilities TEXT,
+                eth_version INTEGER,
+                udp_port INTEGER,
+                is_ipv6 INTEGER,
+                best_block_number INTEGER,
+                discovery_source TEXT,
+                raw_handshake TEXT,
+                node_role TEXT,
+                tcp_connect_ms INTEGER,
+                handshake_ms INTEGER,
+                serves_snap INTEGER,
+                last_disconnect_reason TEXT,
+                asn INTEGER,
+                org TEXT,
+                session_id TEXT
+            );",
+                    [],
+                )
+            })
+            .await
+            .unwrap();
+        // add columns missing from databases created by older versions
+        db.call(|conn| {
+            let existing_columns = conn
+                .prepare("PRAGMA table_info(eth_peer_data)")?
+                .query_map([], |row| row.get::<_, String>(1))?
+                .collect::<Result<Vec<_>, _>>()?;
+            for (column, column_type) in COLUMN_MIGRATIONS {
+                if !existing_columns.iter().any(|c| c == column) {
+                    conn.execute(
+                        &format!("ALTER TABLE eth_peer_data ADD COLUMN {column} {column_type}"),
+                        [],
+                    )?;
+                }
+            }
+            Ok(())
+        })
+        .await
+        .unwrap();
+        // create the `peer_count_history` table if not exists
+        db.call(|conn| {
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS peer_count_history (
+                timestamp TEXT PRIMARY KEY,
+                count INTEGER NOT NULL
+            )",
+                [],
+            )
+        })
+        .await
+        .unwrap();
+        // record which version wrote the db
+        db.call(|conn| {
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS meta (key TEXT PRIMARY KEY, value TEXT NOT NULL)",
+                [],
+            )?;
+            let SchemaInfo {
+                crate_version,
+                schema_version,
+            } = SchemaInfo::current();
+            let mut stmt =
+                conn.prepare("INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)")?;
+            stmt.execute(["crate_version", crate_version.as_str()])?;
+            stmt.execute(["schema_version", schema_version.to_string().as_str()])?;
+            Ok(())
+        })
+        .await
+        .unwrap();
+        Self {
+            pool: vec![db],
+            next: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// `peer_data` with its address in the canonical form of [`normalize_ip`].
+fn with_normalized_ip(mut peer_data: PeerData) -> PeerData {
+    peer_data.address = normalize_ip(&peer_data.address);
+    peer_data
+}
+
+/// Store `peer_data` in `db`, unless a peer with the same id and a later or equal `last_seen` is
+/// already stored.
+fn insert_if_newer(db: &mut HashMap<String, PeerData>, peer_data: PeerData) {
+    match db.get(&peer_data.id) {
+        Some(stored) if stored.last_seen >= peer_data.last_seen => {}
+        _ => {
+            db.insert(peer_data.id.clone(), peer_data);
+        }
+    }
+}
+
+/// Map a `SELECT * from eth_peer_data` row into a [`PeerData`].
+fn peer_data_from_row(row: &Row<'_>) -> rusqlite::Result<PeerData> {
+    let total_difficulty: String = row.get(8)?;
+    // an empty list is stored as "", which must not read back as `[""]`
+    let capabilities: Vec<String> = row
+        .get::<_, String>(12)?
+        .split(',')
+        .filter(|cap| !cap.is_empty())
+        .map(|cap| cap.to_string())
+        .collect();
+    Ok(PeerData {
+        id: row.get(0)?,
+        address: row.get(1)?,
+        client_version: row.get(2)?,
+        enode_url: row.get(3)?,
+        tcp_port: row.get(4)?,
+        chain: row.get(5)?,
+        genesis_block_hash: row.get(6)?,
+        best_block: row.get(7)?,
+        total_difficulty_u128: parse_total_difficulty(&total_difficulty),
+        total_difficulty,
+        country: row.get(9)?,
+        city: row.get(10)?,
+        last_seen: row.get(11)?,
+        // rows written before the flag was stored derive it from their capabilities
+        serves_snap: row
+            .get::<_, Option<bool>>(22)?
+            .unwrap_or_else(|| advertises_snap(&capabilities)),
+        capabilities,
+        eth_version: row.get(13)?,
+        udp_port: row.get(14)?,
+        is_ipv6: row.get::<_, Option<bool>>(15)?.unwrap_or(false),
+        best_block_number: row.get(16)?,
+        discovery_source: row.get::<_, Option<String>>(17)?.unwrap_or_default(),
+        raw_handshake: row.get(18)?,
+        node_role: row.get(19)?,
+        tcp_connect_ms: row.get(20)?,
+        handshake_ms: row.get(21)?,
+        last_disconnect_reason: row.get(23)?,
+        asn: row.get(24)?,
+        org: row.get(25)?,
+        session_id: row.get::<_, Option<String>>(26)?.unwrap_or_default(),
+    })
+}
+
+/// Run `stmt` and read its rows as peers. Malformed rows are skipped, but logged with their id so
+/// corrupt data doesn't go unnoticed.
+fn query_peers<P: Params>(stmt: &mut Statement<'_>, params: P) -> rusqlite::Result<Vec<PeerData>> {
+    let rows = stmt.query_map(params, |row| {
+        Ok(peer_data_from_row(row).map_err(|err| (row.get::<_, String>(0), err)))
+    })?;
+    let mut peers = vec![];
+    for row in rows {
+        match row? {
+            Ok(peer_data) => peers.push(peer_data),
+            Err((Ok(id), err)) => warn!("Skipping malformed row of peer {}: {}", id, err),
+            Err((Err(_), err)) => warn!("Skipping malformed row without id: {}", err),
+        }
+    }
+    Ok(peers)
+}
+
+/// Insert a peer in `eth_peer_data`, or replace the stored one if its `last_seen` is earlier.
+fn insert_peer(conn: &rusqlite::Connection, peer_data: &PeerData) -> rusqlite::Result<usize> {
+    // stored as NULL for malformed addresses so they match neither ip version
+    let is_ipv6 = parse_is_ipv6(&peer_data.address);
+    conn.execute(
+        "INSERT INTO eth_peer_data (id, ip, client_version, enode_url, port, chain, genesis_hash, best_block, total_difficulty, country, city, last_seen, capabilities, eth_version, udp_port, is_ipv6, best_block_number, discovery_source, raw_handshake, node_role, tcp_connect_ms, handshake_ms, serves_snap, last_disconnect_reason, asn, org, session_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27)
+        ON CONFLICT(id) DO UPDATE SET ip = excluded.ip, client_version = excluded.client_version, enode_url = excluded.enode_url, port = excluded.port, chain = excluded.chain, genesis_hash = excluded.genesis_hash, best_block = excluded.best_block, total_difficulty = excluded.total_difficulty, country = excluded.country, city = excluded.city, last_seen = excluded.last_seen, capabilities = excluded.capabilities, eth_version = excluded.eth_version, udp_port = excluded.udp_port, is_ipv6 = excluded.is_ipv6, best_block_number = excluded.best_block_number, discovery_source = excluded.discovery_source, raw_handshake = excluded.raw_handshake, node_role = excluded.node_role, tcp_connect_ms = excluded.tcp_connect_ms, handshake_ms = excluded.handshake_ms, serves_snap = excluded.serves_snap, last_disconnect_reason = excluded.last_disconnect_reason, asn = excluded.asn, org = excluded.org, session_id = excluded.session_id
+        WHERE excluded.last_seen > eth_peer_data.last_seen",
+        params![
+            &peer_data.id,
+            normalize_ip(&peer_data.address),
+            &peer_data.client_version,
+            &peer_data.enode_url,
+            &peer_data.tcp_port,
+            &peer_data.chain,
+            &peer_data.genesis_block_hash,
+            &peer_data.best_block,
+            &peer_data.total_difficulty,
+            &peer_data.country,
+            &peer_data.city,
+            &peer_data.last_seen,
+            &peer_data.capabilities.join(","),
+            &peer_data.eth_version,
+            &peer_data.udp_port,
+            &is_ipv6,
+            &peer_data.best_block_number,
+            &peer_data.discovery_source,
+            &peer_data.raw_handshake,
+            &peer_data.node_role,
+            &peer_data.tcp_connect_ms,
+            &peer_data.handshake_ms,
+            &peer_data.serves_snap,
+            &peer_data.last_disconnect_reason,
+            &peer_data.asn,
+            &peer_data.org,
+            &peer_data.session_id,
+        ],
+    )
 }
 
 #[async_trait]
-impl PeerDB for InMemoryPeerDB {
+impl PeerDB for SqlPeerDB {
     async fn add_peer(&self, peer_data: PeerData, _: Option<i64>) -> Result<(), AddItemError> {
-        let mut db = self
-            .db
-            .write()
-            .map_err(|_| AddItemError::InMemoryDbAddItemError())?;
-        db.insert(peer_data.id.clone(), peer_data);
+        let (id, address) = (peer_data.id.clone(), peer_data.address.clone());
+        self.db()
+            .call(move |conn| insert_peer(conn, &peer_data))
+            .await
+            .map_err(|err| AddItemError::SqlAddItemError(err).with_peer(&id, &address))?;
         Ok(())
     }
 
-    async fn all_peers(&self, page_size: Option<i32>) -> Result<Vec<PeerData>, ScanTableError> {
-        let page_size = page_size.unwrap_or(50);
-        let db = self
-            .db
-            .read()
-            .map_err(|_| ScanTableError::InMemoryDbScanError())?;
-        Ok(db
-            .iter()
-            .map(|(_, peer_data)| peer_data.clone())
-            .take(page_size as usize)
-            .collect())
+    async fn add_peers(&self, peers: Vec<PeerData>, _: Option<i64>) -> Result<(), AddItemError> {
+        self.db()
+            .call(move |conn| {
+                // take the write lock upfront, waiting for concurrent writers through the busy
+                // timeout instead of failing to upgrade a read transaction
+                let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+                for peer_data in &peers {
+                    // the transaction rolls back as it drops
+                    if let Err(err) = insert_peer(&tx, peer_data) {
+                        return Ok(Err((peer_data.id.clone(), peer_data.address.clone(), err)));
+                    }
+                }
+                tx.commit().map(Ok)
+            })
+            .await
+            .map_err(|err| AddItemError::SqlAddItemError(err))?
+            .map_err(|(id, address, err)| {
+                AddItemError::SqlAddItemError(tokio_rusqlite::Error::Rusqlite(err))
+                    .with_peer(&id, &address)
+            })
+    }
+
+    async fn all_peers(
+        &self,
+        page_size: Option<i32>,
+        require_geo: bool,
+    ) -> Result<Vec<PeerData>, ScanTableError> {
+        let peers = self
+            .db()
+            .call(move |conn| {
+                let mut stmt = if require_geo {
+                    conn.prepare(
+                        "SELECT * from eth_peer_data WHERE country IS NOT NULL AND country <> ''",
+                    )?
+                } else {
+                    conn.prepare("SELECT * from eth_peer_data")?
+                };
+                query_peers(&mut stmt, [])
+            })
+            .await
+            .map_err(|err| ScanTableError::SqlScanError(err))?;
+
+        Ok(peers)
+    }
+
+    /// Reads [`STREAM_PAGE_SIZE`] rows at a time with `LIMIT`/`OFFSET`, in `id` order, until a
+    /// page comes back empty. Peers added or deleted while streaming may be skipped or read twice.
+    fn peers_stream(&self, require_geo: bool) -> BoxStream<'_, Result<PeerData, ScanTableError>> {
+        let query = if require_geo {
+            "SELECT * from eth_peer_data WHERE country IS NOT NULL AND country <> '' ORDER BY id LIMIT ?1 OFFSET ?2"
+        } else {
+            "SELECT * from eth_peer_data ORDER BY id LIMIT ?1 OFFSET ?2"
+        };
+        let pages = stream::try_unfold(Some(0), move |offset| async move {
+            let Some(offset) = offset else {
+                return Ok(None);
+            };
+            let peers = self
+                .db()
+                .call(move |conn| {
+                    let mut stmt = conn.prepare(query)?;
+                    query_peers(&mut stmt, [STREAM_PAGE_SIZE, offset])
+                })
+                .await
+                .map_err(|err| ScanTableError::SqlScanError(err))?;
+            let next = (!peers.is_empty()).then_some(offset + STREAM_PAGE_SIZE);
+            Ok(Some((peers, next)))
+        });
+        Box::pin(
+            pages
+                .map_ok(|peers| stream::iter(peers.into_iter().map(Ok)))
+                .try_flatten(),
+        )
     }
 
     async fn node_by_id(&self, id: String) -> Result<Option<Vec<PeerData>>, QueryItemError> {
-        let db = self
-            .db
-            .read()
-            .map_err(|_| QueryItemError::InMemoryDbQueryItemError())?;
-        Ok(Some(
-            db.iter()
-                .filter(|(peer_id, _)| **peer_id == id)
-                .map(|(_, peer_data)| peer_data.clone())
-                .collect(),
-        ))
+        let id = normalize_peer_id(&id);
+        let peers = self
+            .db()
+            .call(move |conn| {
+                let mut stmt = conn.prepare("SELECT * from eth_peer_data WHERE id = ?1")?;
+                query_peers(&mut stmt, [id])
+            })
+            .await
+            .map_err(|err| QueryItemError::SqlQueryItemError(err))?;
+
+        Ok(Some(peers))
     }
 
     async fn node_by_ip(&self, ip: String) -> Result<Option<Vec<PeerData>>, QueryItemError> {
-        let db = self
-            .db
-            .read()
-            .map_err(|_| QueryItemError::InMemoryDbQueryItemError())?;
-        Ok(Some(
-            db.iter()
-                .filter(|(_, peer_data)| peer_data.address == ip)
-                .map(|(_, peer_data)| peer_data.clone())
-                .collect(),
+        let ip = normalize_ip(&ip);
+        let peers = self
+            .db()
+            .call(move |conn| {
+                let mut stmt = conn.prepare("SELECT * from eth_peer_data WHERE ip = ?1")?;
+                query_peers(&mut stmt, [ip])
+            })
+            .await
+            .map_err(|err| QueryItemError::SqlQueryItemError(err))?;
+
+        Ok(Some(peers))
+    }
+
+    async fn nodes_by_ip_version(&self, v6: bool) -> Result<Vec<PeerData>, ScanTableError> {
+        let peers = self
+            .db()
+            .call(move |conn| {
+                let mut stmt = conn.prepare("SELECT * from eth_peer_data WHERE is_ipv6 = ?1")?;
+                query_peers(&mut stmt, [v6])
+            })
+            .await
+            .map_err(|err| ScanTableError::SqlScanError(err))?;
+
+        Ok(peers)
+    }
+
+    async fn count_peers(&self) -> Result<u64, ScanTableError> {
+        let count = self
+            .db()
+            .call(|conn| {
+                conn.query_row("SELECT COUNT(*) from eth_peer_data", [], |row| {
+                    row.get::<_, i64>(0)
+                })
+            })
+            .await
+            .map_err(|err| ScanTableError::SqlScanError(err))?;
+
+        Ok(count as u64)
+    }
+
+    async fn distinct_ip_count(&self) -> Result<u64, ScanTableError> {
+        let count = self
+            .db()
+            .call(|conn| {
+                conn.query_row("SELECT COUNT(DISTINCT ip) from eth_peer_data", [], |row| {
+                    row.get::<_, i64>(0)
+                })
+            })
+            .await
+            .map_err(|err| ScanTableError::SqlScanError(err))?;
+
+        Ok(count as u64)
+    }
+
+    async fn distinct_peer_id_count(&self) -> Result<u64, ScanTableError> {
+        let count = self
+            .db()
+            .call(|conn| {
+                conn.query_row("SELECT COUNT(DISTINCT id) from eth_peer_data", [], |row| {
+                    row.get::<_, i64>(0)
+                })
+            })
+            .await
+            .map_err(|err| ScanTableError::SqlScanError(err))?;
+
+        Ok(count as u64)
+    }
+
+    async fn peers_seen_between(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        _: Option<i32>,
+    ) -> Result<Vec<PeerData>, ScanTableError> {
+        let (start, end) = (start.to_string(), end.to_string());
+        let peers = self
+            .db()
+            .call(move |conn| {
+                let mut stmt =
+                    conn.prepare("SELECT * from eth_peer_data WHERE last_seen BETWEEN ?1 AND ?2")?;
+                query_peers(&mut stmt, [start, end])
+            })
+            .await
+            .map_err(|err| ScanTableError::SqlScanError(err))?;
+
+        Ok(peers)
+    }
+
+    async fn recent_peers(&self, limit: i32) -> Result<Vec<PeerData>, ScanTableError> {
+        let peers = self
+            .db()
+            .call(move |conn| {
+                let mut stmt =
+                    conn.prepare("SELECT * from eth_peer_data ORDER BY last_seen DESC LIMIT ?1")?;
+                query_peers(&mut stmt, [limit])
+            })
+            .await
+            .map_err(|err| ScanTableError::SqlScanError(err))?;
+
+        Ok(peers)
+    }
+
+    async fn stale_peers(&self, limit: i32) -> Result<Vec<PeerData>, ScanTableError> {
+        let peers = self
+            .db()
+            .call(move |conn| {
+                let mut stmt =
+                    conn.prepare("SELECT * from eth_peer_data ORDER BY last_seen ASC LIMIT ?1")?;
+                query_peers(&mut stmt, [limit])
+            })
+            .await
+            .map_err(|err| ScanTableError::SqlScanError(err))?;
+
+        Ok(peers)
+    }
+
+    /// `total_difficulty` is stored as TEXT and can exceed SQLite integers, so compare client-side.
+    async fn peers_below_difficulty(
+        &self,
+        threshold: U256,
+    ) -> Result<Vec<PeerData>, ScanTableError> {
+        let mut peers = self.all_peers(None, false).await?;
+        peers.retain(|peer| below_difficulty(peer, threshold));
+        Ok(peers)
+    }
+
+    async fn peers_behind_head(
+        &self,
+        current_head: u64,
+        lag: u64,
+    ) -> Result<Vec<PeerData>, ScanTableError> {
+        // SQLite integers are signed, block numbers won't come close to i64::MAX
+        let threshold = current_head.saturating_sub(lag) as i64;
+        let peers = self
+            .db()
+            .call(move |conn| {
+                let mut stmt =
+                    conn.prepare("SELECT * from eth_peer_data WHERE best_block_number < ?1")?;
+                query_peers(&mut stmt, [threshold])
+            })
+            .await
+            .map_err(|err| ScanTableError::SqlScanError(err))?;
+
+        Ok(peers)
+    }
+
+    async fn client_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError> {
+        let client_versions = self
+            .db()
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT client_version, COUNT(*) FROM eth_peer_data GROUP BY client_version",
+                )?;
+                let rows = stmt.query_map([], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?))
+                })?;
+                rows.collect::<Result<Vec<_>, _>>()
+            })
+            .await
+            .map_err(|err| ScanTableError::SqlScanError(err))?;
+
+        let mut distribution = HashMap::new();
+        for (client_version, count) in client_versions {
+            *distribution
+                .entry(client_family(&client_version).to_string())
+                .or_default() += count;
+        }
+        Ok(distribution)
+    }
+
+    async fn country_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError> {
+        let countries = self
+            .db()
+            .call(move |conn| {
+                let mut stmt =
+                    conn.prepare("SELECT country, COUNT(*) FROM eth_peer_data GROUP BY country")?;
+                let rows = stmt.query_map([], |row| {
+                    Ok((
+                        row.get::<_, Option<String>>(0)?.unwrap_or_default(),
+                        row.get::<_, u64>(1)?,
+                    ))
+                })?;
+                rows.collect::<Result<Vec<_>, _>>()
+            })
+            .await
+            .map_err(|err| ScanTableError::SqlScanError(err))?;
+
+        Ok(count_countries(
+            countries
+                .iter()
+                .map(|(country, count)| (country.as_str(), *count)),
         ))
     }
-}
 
-pub struct SqlPeerDB {
-    db: Connection,
-}
+    /// Capabilities are stored joined by commas, which SQLite can't split, so the peers are
+    /// grouped by capability list and the lists split here.
+    async fn capability_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError> {
+        let capability_lists = self
+            .db()
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT capabilities, COUNT(*) FROM eth_peer_data GROUP BY capabilities",
+                )?;
+                let rows = stmt.query_map([], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?))
+                })?;
+                rows.collect::<Result<Vec<_>, _>>()
+            })
+            .await
+            .map_err(|err| ScanTableError::SqlScanError(err))?;
+
+        let capability_lists: Vec<(Vec<String>, u64)> = capability_lists
+            .into_iter()
+            .map(|(capabilities, count)| {
+                let capabilities = capabilities
+                    .split(',')
+                    .filter(|cap| !cap.is_empty())
+                    .map(|cap| cap.to_string())
+                    .collect();
+                (capabilities, count)
+            })
+            .collect();
+        Ok(count_capabilities(capability_lists.iter().map(
+            |(capabilities, count)| (capabilities.as_slice(), *count),
+        )))
+    }
+
+    async fn disconnect_reason_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError> {
+        let reasons = self
+            .db()
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT last_disconnect_reason, COUNT(*) FROM eth_peer_data WHERE last_disconnect_reason IS NOT NULL GROUP BY last_disconnect_reason",
+                )?;
+                let rows = stmt.query_map([], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?))
+                })?;
+                rows.collect::<Result<Vec<_>, _>>()
+            })
+            .await
+            .map_err(|err| ScanTableError::SqlScanError(err))?;
+
+        Ok(reasons.into_iter().collect())
+    }
+
+    async fn asn_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError> {
+        let asns = self
+            .db()
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT asn, org, COUNT(*) FROM eth_peer_data WHERE asn IS NOT NULL GROUP BY asn, org",
+                )?;
+                let rows = stmt.query_map([], |row| {
+                    Ok((
+                        row.get::<_, u32>(0)?,
+                        row.get::<_, Option<String>>(1)?,
+                        row.get::<_, u64>(2)?,
+                    ))
+                })?;
+                rows.collect::<Result<Vec<_>, _>>()
+            })
+            .await
+            .map_err(|err| ScanTableError::SqlScanError(err))?;
+
+        Ok(count_asns(
+            asns.iter()
+                .map(|(asn, org, count)| (*asn, org.as_deref(), *count)),
+        ))
+    }
+
+    async fn peer_exists(&self, id: String) -> Result<bool, QueryItemError> {
+        let id = normalize_peer_id(&id);
+        let exists = self
+            .db()
+            .call(move |conn| {
+                let mut stmt = conn.prepare("SELECT 1 from eth_peer_data WHERE id = ?1 LIMIT 1")?;
+                stmt.exists([id])
+            })
+            .await
+            .map_err(|err| QueryItemError::SqlQueryItemError(err))?;
+
+        Ok(exists)
+    }
+
+    async fn peers_exist(&self, ids: Vec<String>) -> Result<HashSet<String>, QueryItemError> {
+        let ids: HashSet<String> = ids.iter().map(|id| normalize_peer_id(id)).collect();
+        let ids: Vec<String> = ids.into_iter().collect();
+        let found = self
+            .db()
+            .call(move |conn| {
+                let mut found = HashSet::new();
+                for chunk in ids.chunks(SQLITE_IDS_PER_QUERY) {
+                    let placeholders = vec!["?"; chunk.len()].join(", ");
+                    let mut stmt = conn.prepare(&format!(
+                        "SELECT id from eth_peer_data WHERE id IN ({placeholders})"
+                    ))?;
+                    let rows =
+                        stmt.query_map(params_from_iter(chunk), |row| row.get::<_, String>(0))?;
+                    for id in rows {
+                        found.insert(id?);
+                    }
+                }
+                Ok(found)
+            })
+            .await
+            .map_err(|err| QueryItemError::SqlQueryItemError(err))?;
+
+        Ok(found)
+    }
+
+    async fn nodes_by_source(&self, source: String) -> Result<Vec<PeerData>, ScanTableError> {
+        let peers = self
+            .db()
+            .call(move |conn| {
+                let mut stmt =
+                    conn.prepare("SELECT * from eth_peer_data WHERE discovery_source = ?1")?;
+                query_peers(&mut stmt, [source])
+            })
+            .await
+            .map_err(|err| ScanTableError::SqlScanError(err))?;
+
+        Ok(peers)
+    }
+
+    async fn peers_by_session(&self, session_id: String) -> Result<Vec<PeerData>, ScanTableError> {
+        let peers = self
+            .db()
+            .call(move |conn| {
+                let mut stmt = conn.prepare("SELECT * from eth_peer_data WHERE session_id = ?1")?;
+                query_peers(&mut stmt, [session_id])
+            })
+            .await
+            .map_err(|err| ScanTableError::SqlScanError(err))?;
 
-impl SqlPeerDB {
-    pub async fn new() -> Self {
-        let db = Connection::open("peers_data.db").await.unwrap();
-        // create `eth_peer_data` table if not exists
-        let _ = db
+        Ok(peers)
+    }
+
+    /// Rows written before `serves_snap` was stored are counted if their capabilities include a
+    /// snap version.
+    async fn snap_server_count(&self) -> Result<u64, ScanTableError> {
+        let count = self
+            .db()
             .call(|conn| {
-                conn.execute(
-                    "CREATE TABLE IF NOT EXISTS eth_peer_data (
-                id TEXT PRIMARY KEY,
-                ip TEXT NOT NULL,
-                client_version TEXT NOT NULL,
-                enode_url TEXT NOT NULL,
-                port INTEGER NOT NULL,
-                chain TEXT NOT NULL,
-                genesis_hash TEXT NOT NULL,
-                best_block TEXT NOT NULL,
-                total_difficulty TEXT NOT NULL,
-                country TEXT,
-                city TEXT,
-                last_seen TEXT NOT NULL,
-                capabilities TEXT,
-                eth_version INTEGER
-            );",
+                conn.query_row(
+                    "SELECT COUNT(*) from eth_peer_data WHERE COALESCE(serves_snap, ',' || capabilities LIKE '%,snap/%')",
                     [],
+                    |row| row.get::<_, i64>(0),
                 )
             })
             .await
-            .unwrap();
-        Self { db }
+            .map_err(|err| ScanTableError::SqlScanError(err))?;
+
+        Ok(count as u64)
     }
-}
 
-#[async_trait]
-impl PeerDB for SqlPeerDB {
-    async fn add_peer(&self, peer_data: PeerData, _: Option<i64>) -> Result<(), AddItemError> {
-        let cap = &peer_data.capabilities.join(",");
-        self.db
+    async fn nodes_by_role(&self, role: String) -> Result<Vec<PeerData>, ScanTableError> {
+        let peers = self
+            .db()
             .call(move |conn| {
-                conn.execute(
-                    "INSERT OR REPLACE INTO eth_peer_data (id, ip, client_version, enode_url, port, chain, genesis_hash, best_block, total_difficulty, country, city, last_seen, capabilities, eth_version) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
-                    (
-                        &peer_data.id,
-                        &peer_data.address,
-                        &peer_data.client_version,
-                        &peer_data.enode_url,
-                        &peer_data.tcp_port,
-                        &peer_data.chain,
-                        &peer_data.genesis_block_hash,
-                        &peer_data.best_block,
-                        &peer_data.total_difficulty,
-                        &peer_data.country,
-                        &peer_data.city,
-                        &peer_data.last_seen,
-                        &peer_data.capabilities.join(","),
-                        &peer_data.eth_version,
-                    ),
-                )
+                let mut stmt = conn.prepare("SELECT * from eth_peer_data WHERE node_role = ?1")?;
+                query_peers(&mut stmt, [role])
             })
             .await
-            .map_err(|err| AddItemError::SqlAddItemError(err))?;
-        Ok(())
+            .map_err(|err| ScanTableError::SqlScanError(err))?;
+
+        Ok(peers)
     }
 
-    async fn all_peers(&self, page_size: Option<i32>) -> Result<Vec<PeerData>, ScanTableError> {
+    async fn nodes_by_capability(
+        &self,
+        capability: String,
+        _: Option<i32>,
+    ) -> Result<Vec<PeerData>, QueryItemError> {
         let peers = self
-            .db
+            .db()
             .call(move |conn| {
-                let mut stmt = conn.prepare("SELECT * from eth_peer_data")?;
-                let rows = stmt.query_map([], |row| {
-                    Ok(PeerData {
-                        id: row.get(0)?,
-                        address: row.get(1)?,
-                        client_version: row.get(2)?,
-                        enode_url: row.get(3)?,
-                        tcp_port: row.get(4)?,
-                        chain: row.get(5)?,
-                        genesis_block_hash: row.get(6)?,
-                        best_block: row.get(7)?,
-                        total_difficulty: row.get(8)?,
-                        country: row.get(9)?,
-                        city: row.get(10)?,
-                        last_seen: row.get(11)?,
-                        capabilities: row
-                            .get::<_, String>(12)?
-                            .as_str()
-                            .split(",")
-                            .into_iter()
-                            .map(|s| s.to_string())
-                            .collect(),
-                        eth_version: row.get(13)?,
-                    })
-                })?;
-                let mut peers = vec![];
-                for row in rows {
-                    if let Ok(peer_data) = row {
-                        peers.push(peer_data);
-                    }
-                }
-                Ok(peers)
+                // capabilities are stored comma separated, wrap them in commas so that only whole
+                // capabilities match
+                let mut stmt = conn.prepare(
+                    "SELECT * from eth_peer_data WHERE ',' || capabilities || ',' LIKE '%,' || ?1 || ',%'",
+                )?;
+                query_peers(&mut stmt, [capability])
             })
             .await
-            .map_err(|err| ScanTableError::SqlScanError(err))?;
+            .map_err(|err| QueryItemError::SqlQueryItemError(err))?;
 
         Ok(peers)
     }
 
-    async fn node_by_id(&self, id: String) -> Result<Option<Vec<PeerData>>, QueryItemError> {
+    /// `id` is the primary key, so there is a single row per peer already.
+    async fn latest_per_peer(&self, _: Option<i32>) -> Result<Vec<PeerData>, ScanTableError> {
         let peers = self
-            .db
-            .call(move |conn| {
-                let mut stmt = conn.prepare("SELECT * from eth_peer_data WHERE id = ?1")?;
-                let rows = stmt.query_map([id], |row| {
-                    Ok(PeerData {
-                        id: row.get(0)?,
-                        address: row.get(1)?,
-                        client_version: row.get(2)?,
-                        enode_url: row.get(3)?,
-                        tcp_port: row.get(4)?,
-                        chain: row.get(5)?,
-                        genesis_block_hash: row.get(6)?,
-                        best_block: row.get(7)?,
-                        total_difficulty: row.get(8)?,
-                        country: row.get(9)?,
-                        city: row.get(10)?,
-                        last_seen: row.get(11)?,
-                        capabilities: row
-                            .get::<_, String>(12)?
-                            .as_str()
-                            .split(",")
-                            .into_iter()
-                            .map(|s| s.to_string())
-                            .collect(),
-                        eth_version: row.get(13)?,
-                    })
-                })?;
-                let mut peers = vec![];
-                for row in rows {
-                    if let Ok(peer_data) = row {
-                        peers.push(peer_data);
-                    }
-                }
-                Ok(peers)
+            .db()
+            .call(|conn| {
+                let mut stmt = conn.prepare("SELECT * from eth_peer_data")?;
+                query_peers(&mut stmt, [])
             })
             .await
-            .map_err(|err| QueryItemError::SqlQueryItemError(err))?;
+            .map_err(|err| ScanTableError::SqlScanError(err))?;
 
-        Ok(Some(peers))
+        Ok(peers)
     }
 
-    async fn node_by_ip(&self, ip: String) -> Result<Option<Vec<PeerData>>, QueryItemError> {
+    /// Addresses are stored as text, so every peer is read and filtered here.
+    async fn nodes_by_cidr(
+        &self,
+        cidr: String,
+        _: Option<i32>,
+    ) -> Result<Vec<PeerData>, QueryItemError> {
+        let cidr = Cidr::parse(&cidr)?;
         let peers = self
-            .db
-            .call(move |conn| {
-                let mut stmt = conn.prepare("SELECT * from eth_peer_data WHERE ip = ?1")?;
-                let rows = stmt.query_map([ip], |row| {
-                    Ok(PeerData {
-                        id: row.get(0)?,
-                        address: row.get(1)?,
-                        client_version: row.get(2)?,
-                        enode_url: row.get(3)?,
-                        tcp_port: row.get(4)?,
-                        chain: row.get(5)?,
-                        genesis_block_hash: row.get(6)?,
-                        best_block: row.get(7)?,
-                        total_difficulty: row.get(8)?,
-                        country: row.get(9)?,
-                        city: row.get(10)?,
-                        last_seen: row.get(11)?,
-                        capabilities: row
-                            .get::<_, String>(12)?
-                            .as_str()
-                            .split(",")
-                            .into_iter()
-                            .map(|s| s.to_string())
-                            .collect(),
-                        eth_version: row.get(13)?,
-                    })
-                })?;
-                let mut peers = vec![];
-                for row in rows {
-                    if let Ok(peer_data) = row {
-                        peers.push(peer_data);
-                    }
-                }
-                Ok(peers)
+            .db()
+            .call(|conn| {
+                let mut stmt = conn.prepare("SELECT * from eth_peer_data")?;
+                query_peers(&mut stmt, [])
             })
             .await
             .map_err(|err| QueryItemError::SqlQueryItemError(err))?;
 
-        Ok(Some(peers))
+        Ok(peers
+            .into_iter()
+            .filter(|peer_data| cidr.contains(&peer_data.address))
+            .collect())
     }
-}
 
-impl SqlPeerDB {
-    /// Prune peers that are older than `time_validity`. Note that `time_validity` **MUST** be in days.
-    pub async fn prune_peers(&self, time_validity: i64) -> Result<(), DeleteItemError> {
+    async fn prune_peers(&self, time_validity: i64) -> Result<u64, DeleteItemError> {
         let cutoff = Utc::now()
             .checked_sub_signed(Duration::days(time_validity))
             .unwrap()
             .to_string();
         let deleted_peers_number = self
-            .db
+            .db()
             .call(move |conn| {
                 conn.execute(
                     "DELETE FROM eth_peer_data WHERE last_seen < ?1 ",
@@ -458,7 +3089,446 @@ impl SqlPeerDB {
             .await
             .map_err(|err| DeleteItemError::SqlDeleteItemError(err))?;
 
-        info!("Number of peers pruned: {}", deleted_peers_number);
+        Ok(deleted_peers_number as u64)
+    }
+
+    async fn clear(&self) -> Result<(), DeleteItemError> {
+        self.db()
+            .call(|conn| conn.execute("DELETE FROM eth_peer_data", []))
+            .await
+            .map_err(|err| DeleteItemError::SqlDeleteItemError(err))?;
+        Ok(())
+    }
+
+    async fn delete_peer(&self, id: String) -> Result<bool, DeleteItemError> {
+        let deleted = self
+            .db()
+            .call(move |conn| conn.execute("DELETE FROM eth_peer_data WHERE id = ?1", [id]))
+            .await
+            .map_err(|err| DeleteItemError::SqlDeleteItemError(err))?;
+        Ok(deleted > 0)
+    }
+
+    async fn record_peer_count(&self, snapshot: PeerCountSnapshot) -> Result<(), AddItemError> {
+        self.db()
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT OR REPLACE INTO peer_count_history (timestamp, count) VALUES (?1, ?2)",
+                    params![snapshot.timestamp, snapshot.count as i64],
+                )
+            })
+            .await
+            .map_err(|err| AddItemError::SqlAddItemError(err))?;
         Ok(())
     }
+
+    async fn count_history(
+        &self,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<PeerCountSnapshot>, ScanTableError> {
+        let since = since.to_string();
+        let history = self
+            .db()
+            .call(move |conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT timestamp, count FROM peer_count_history WHERE timestamp >= ?1
+                    ORDER BY timestamp",
+                )?;
+                let rows = stmt
+                    .query_map([since], |row| {
+                        Ok(PeerCountSnapshot {
+                            timestamp: row.get(0)?,
+                            count: row.get::<_, i64>(1)? as u64,
+                        })
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(rows)
+            })
+            .await
+            .map_err(|err| ScanTableError::SqlScanError(err))?;
+        Ok(history)
+    }
+
+    async fn update_location(
+        &self,
+        id: String,
+        country: String,
+        city: String,
+    ) -> Result<bool, UpdateItemError> {
+        let id = normalize_peer_id(&id);
+        let updated = self
+            .db()
+            .call(move |conn| {
+                conn.execute(
+                    "UPDATE eth_peer_data SET country = ?2, city = ?3 WHERE id = ?1",
+                    params![id, country, city],
+                )
+            })
+            .await
+            .map_err(|err| UpdateItemError::SqlUpdateItemError(err))?;
+
+        Ok(updated > 0)
+    }
+
+    async fn update_chain_status(
+        &self,
+        id: String,
+        best_block: String,
+        best_block_number: Option<u64>,
+        total_difficulty: String,
+        last_seen: String,
+    ) -> Result<bool, UpdateItemError> {
+        let id = normalize_peer_id(&id);
+        let found = self
+            .db()
+            .call(move |conn| {
+                let updated = conn.execute(
+                    "UPDATE eth_peer_data SET best_block = ?2, best_block_number = ?3, total_difficulty = ?4, last_seen = ?5 WHERE id = ?1 AND (last_seen IS NULL OR last_seen < ?5)",
+                    params![id, best_block, best_block_number, total_difficulty, last_seen],
+                )?;
+                if updated > 0 {
+                    return Ok(true);
+                }
+                // a peer with a later `last_seen` is left as it is, but still recorded
+                conn.query_row(
+                    "SELECT EXISTS(SELECT 1 FROM eth_peer_data WHERE id = ?1)",
+                    params![id],
+                    |row| row.get(0),
+                )
+            })
+            .await
+            .map_err(|err| UpdateItemError::SqlUpdateItemError(err))?;
+
+        Ok(found)
+    }
+
+    async fn schema_info(&self) -> Result<SchemaInfo, QueryItemError> {
+        let info = self
+            .db()
+            .call(|conn| {
+                let mut stmt = conn.prepare("SELECT key, value from meta")?;
+                let rows = stmt
+                    .query_map([], |row| {
+                        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+                    })?
+                    .collect::<Result<HashMap<_, _>, _>>()?;
+                Ok(rows)
+            })
+            .await
+            .map_err(|err| QueryItemError::SqlQueryItemError(err))?;
+
+        match (info.get("crate_version"), info.get("schema_version")) {
+            (Some(crate_version), Some(schema_version)) => Ok(SchemaInfo {
+                crate_version: crate_version.clone(),
+                schema_version: schema_version
+                    .parse()
+                    .map_err(|_| QueryItemError::MissingSchemaInfoError())?,
+            }),
+            _ => Err(QueryItemError::MissingSchemaInfoError()),
+        }
+    }
+}
+
+/// Writes to several backends at once, e.g. a local sqlite db for fast queries plus DynamoDB for
+/// durability, and reads from the first one.
+///
+/// A write succeeds if it succeeded on any backend; the failures of the others are logged.
+pub struct CompositePeerDB {
+    backends: Vec<Arc<dyn PeerDB>>,
+}
+
+impl CompositePeerDB {
+    /// Panics if `backends` is empty.
+    pub fn new(backends: Vec<Arc<dyn PeerDB>>) -> Self {
+        assert!(!backends.is_empty(), "CompositePeerDB needs a backend");
+        Self { backends }
+    }
+
+    /// The backend reads go to.
+    fn primary(&self) -> &dyn PeerDB {
+        self.backends[0].as_ref()
+    }
+}
+
+/// The first successful result of a write fanned out to every backend, or the last error if
+/// they all failed. `write` names the operation in the logs.
+fn any_ok<T, E: std::fmt::Display>(results: Vec<Result<T, E>>, write: &str) -> Result<T, E> {
+    let backends = results.len();
+    let mut ok = None;
+    let mut last_err = None;
+    for (backend, result) in results.into_iter().enumerate() {
+        match result {
+            Ok(value) => {
+                ok.get_or_insert(value);
+            }
+            Err(err) => {
+                warn!(
+                    "{} failed on backend {}/{}: {}",
+                    write,
+                    backend + 1,
+                    backends,
+                    err
+                );
+                last_err = Some(err);
+            }
+        }
+    }
+    match (ok, last_err) {
+        (Some(value), _) => Ok(value),
+        (None, Some(err)) => Err(err),
+        (None, None) => unreachable!("CompositePeerDB has at least one backend"),
+    }
+}
+
+#[async_trait]
+impl PeerDB for CompositePeerDB {
+    async fn add_peer(&self, peer_data: PeerData, ttl: Option<i64>) -> Result<(), AddItemError> {
+        let mut results = Vec::with_capacity(self.backends.len());
+        for backend in &self.backends {
+            results.push(backend.add_peer(peer_data.clone(), ttl).await);
+        }
+        any_ok(results, "add_peer")
+    }
+
+    async fn add_peers(&self, peers: Vec<PeerData>, ttl: Option<i64>) -> Result<(), AddItemError> {
+        let mut results = Vec::with_capacity(self.backends.len());
+        for backend in &self.backends {
+            results.push(backend.add_peers(peers.clone(), ttl).await);
+        }
+        any_ok(results, "add_peers")
+    }
+
+    async fn all_peers(
+        &self,
+        page_size: Option<i32>,
+        require_geo: bool,
+    ) -> Result<Vec<PeerData>, ScanTableError> {
+        self.primary().all_peers(page_size, require_geo).await
+    }
+
+    fn peers_stream(&self, require_geo: bool) -> BoxStream<'_, Result<PeerData, ScanTableError>> {
+        self.primary().peers_stream(require_geo)
+    }
+
+    async fn node_by_id(&self, id: String) -> Result<Option<Vec<PeerData>>, QueryItemError> {
+        self.primary().node_by_id(id).await
+    }
+
+    async fn node_by_ip(&self, ip: String) -> Result<Option<Vec<PeerData>>, QueryItemError> {
+        self.primary().node_by_ip(ip).await
+    }
+
+    async fn peer_exists(&self, id: String) -> Result<bool, QueryItemError> {
+        self.primary().peer_exists(id).await
+    }
+
+    async fn peers_exist(&self, ids: Vec<String>) -> Result<HashSet<String>, QueryItemError> {
+        self.primary().peers_exist(ids).await
+    }
+
+    async fn nodes_by_ip_version(&self, v6: bool) -> Result<Vec<PeerData>, ScanTableError> {
+        self.primary().nodes_by_ip_version(v6).await
+    }
+
+    async fn count_peers(&self) -> Result<u64, ScanTableError> {
+        self.primary().count_peers().await
+    }
+
+    async fn distinct_ip_count(&self) -> Result<u64, ScanTableError> {
+        self.primary().distinct_ip_count().await
+    }
+
+    async fn distinct_peer_id_count(&self) -> Result<u64, ScanTableError> {
+        self.primary().distinct_peer_id_count().await
+    }
+
+    async fn peers_seen_between(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        page_size: Option<i32>,
+    ) -> Result<Vec<PeerData>, ScanTableError> {
+        self.primary()
+            .peers_seen_between(start, end, page_size)
+            .await
+    }
+
+    async fn recent_peers(&self, limit: i32) -> Result<Vec<PeerData>, ScanTableError> {
+        self.primary().recent_peers(limit).await
+    }
+
+    async fn stale_peers(&self, limit: i32) -> Result<Vec<PeerData>, ScanTableError> {
+        self.primary().stale_peers(limit).await
+    }
+
+    async fn peers_below_difficulty(
+        &self,
+        threshold: U256,
+    ) -> Result<Vec<PeerData>, ScanTableError> {
+        self.primary().peers_below_difficulty(threshold).await
+    }
+
+    async fn peers_behind_head(
+        &self,
+        current_head: u64,
+        lag: u64,
+    ) -> Result<Vec<PeerData>, ScanTableError> {
+        self.primary().peers_behind_head(current_head, lag).await
+    }
+
+    async fn client_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError> {
+        self.primary().client_distribution().await
+    }
+
+    async fn country_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError> {
+        self.primary().country_distribution().await
+    }
+
+    async fn capability_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError> {
+        self.primary().capability_distribution().await
+    }
+
+    async fn disconnect_reason_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError> {
+        self.primary().disconnect_reason_distribution().await
+    }
+
+    async fn asn_distribution(&self) -> Result<HashMap<String, u64>, ScanTableError> {
+        self.primary().asn_distribution().await
+    }
+
+    async fn nodes_by_source(&self, source: String) -> Result<Vec<PeerData>, ScanTableError> {
+        self.primary().nodes_by_source(source).await
+    }
+
+    async fn peers_by_session(&self, session_id: String) -> Result<Vec<PeerData>, ScanTableError> {
+        self.primary().peers_by_session(session_id).await
+    }
+
+    async fn snap_server_count(&self) -> Result<u64, ScanTableError> {
+        self.primary().snap_server_count().await
+    }
+
+    async fn nodes_by_role(&self, role: String) -> Result<Vec<PeerData>, ScanTableError> {
+        self.primary().nodes_by_role(role).await
+    }
+
+    async fn nodes_by_capability(
+        &self,
+        capability: String,
+        page_size: Option<i32>,
+    ) -> Result<Vec<PeerData>, QueryItemError> {
+        self.primary()
+            .nodes_by_capability(capability, page_size)
+            .await
+    }
+
+    async fn latest_per_peer(
+        &self,
+        page_size: Option<i32>,
+    ) -> Result<Vec<PeerData>, ScanTableError> {
+        self.primary().latest_per_peer(page_size).await
+    }
+
+    async fn nodes_by_cidr(
+        &self,
+        cidr: String,
+        page_size: Option<i32>,
+    ) -> Result<Vec<PeerData>, QueryItemError> {
+        self.primary().nodes_by_cidr(cidr, page_size).await
+    }
+
+    /// Prune every backend, returning how many peers were deleted from the first one that
+    /// succeeded.
+    async fn prune_peers(&self, time_validity: i64) -> Result<u64, DeleteItemError> {
+        let mut results = Vec::with_capacity(self.backends.len());
+        for backend in &self.backends {
+            results.push(backend.prune_peers(time_validity).await);
+        }
+        any_ok(results, "prune_peers")
+    }
+
+    /// Clear every backend, failing only if every backend failed.
+    async fn clear(&self) -> Result<(), DeleteItemError> {
+        let mut results = Vec::with_capacity(self.backends.len());
+        for backend in &self.backends {
+            results.push(backend.clear().await);
+        }
+        any_ok(results, "clear")
+    }
+
+    /// Delete the peer from every backend, returning whether it was recorded in the first one
+    /// that succeeded.
+    async fn delete_peer(&self, id: String) -> Result<bool, DeleteItemError> {
+        let mut results = Vec::with_capacity(self.backends.len());
+        for backend in &self.backends {
+            results.push(backend.delete_peer(id.clone()).await);
+        }
+        any_ok(results, "delete_peer")
+    }
+
+    /// Record the snapshot in every backend, failing only if every backend failed.
+    async fn record_peer_count(&self, snapshot: PeerCountSnapshot) -> Result<(), AddItemError> {
+        let mut results = Vec::with_capacity(self.backends.len());
+        for backend in &self.backends {
+            results.push(backend.record_peer_count(snapshot.clone()).await);
+        }
+        any_ok(results, "record_peer_count")
+    }
+
+    async fn count_history(
+        &self,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<PeerCountSnapshot>, ScanTableError> {
+        self.primary().count_history(since).await
+    }
+
+    /// Update every backend, returning whether the peer is recorded in the first one that
+    /// succeeded.
+    async fn update_location(
+        &self,
+        id: String,
+        country: String,
+        city: String,
+    ) -> Result<bool, UpdateItemError> {
+        let mut results = Vec::with_capacity(self.backends.len());
+        for backend in &self.backends {
+            results.push(
+                backend
+                    .update_location(id.clone(), country.clone(), city.clone())
+                    .await,
+            );
+        }
+        any_ok(results, "update_location")
+    }
+
+    /// Update every backend, returning whether the peer is recorded in the first one that
+    /// succeeded.
+    async fn update_chain_status(
+        &self,
+        id: String,
+        best_block: String,
+        best_block_number: Option<u64>,
+        total_difficulty: String,
+        last_seen: String,
+    ) -> Result<bool, UpdateItemError> {
+        let mut results = Vec::with_capacity(self.backends.len());
+        for backend in &self.backends {
+            results.push(
+                backend
+                    .update_chain_status(
+                        id.clone(),
+                        best_block.clone(),
+                        best_block_number,
+                        total_difficulty.clone(),
+                        last_seen.clone(),
+                    )
+                    .await,
+            );
+        }
+        any_ok(results, "update_chain_status")
+    }
+
+    async fn schema_info(&self) -> Result<SchemaInfo, QueryItemError> {
+        self.primary().schema_info().await
+    }
 }
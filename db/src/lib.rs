@@ -1,3 +1,9 @@
+//! Storage layer of the reth crawler.
+//!
+//! Other programs can depend on this crate to read the crawled peers through the [`PeerDB`]
+//! trait, from any of its backends: [`AwsPeerDB`], [`SqlPeerDB`] or [`InMemoryPeerDB`].
+//! [`CompositePeerDB`] writes to several of them at once.
+
 pub mod db;
 pub mod types;
 
@@ -5,8 +11,15 @@ use std::sync::Arc;
 use tokio::{fs::OpenOptions, io::AsyncWriteExt};
 
 // Re-exports
-pub use db::{AwsPeerDB, InMemoryPeerDB, PeerDB, SqlPeerDB};
-pub use types::PeerData;
+pub use db::{
+    handshake_latency_percentiles, latest_per_id, verify_peers, AwsPeerDB, CompositePeerDB,
+    InMemoryPeerDB, PeerDB, SqlPeerDB,
+};
+pub use types::{
+    AddItemError, Cidr, DeleteItemError, FieldChange, IntegrityReport, IntegrityViolation,
+    LatencyPercentiles, PeerCountSnapshot, PeerData, PeerDataBuilder, PeerDataError,
+    QueryItemError, ScanTableError, SchemaInfo, TableError, UpdateItemError, U256,
+};
 
 /// Helper function to append a peer to file
 pub async fn append_to_file(peer_data: PeerData) -> eyre::Result<()> {
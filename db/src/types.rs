@@ -1,29 +1,92 @@
+use chrono::{DateTime, NaiveDateTime};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    net::{IpAddr, SocketAddr},
+};
 use thiserror::Error;
+use tracing::warn;
+
+pub use reth_primitives::U256;
 
 use aws_sdk_dynamodb::{
     error::SdkError,
-    operation::{put_item::PutItemError, query::QueryError, scan::ScanError},
+    operation::{
+        batch_get_item::BatchGetItemError, batch_write_item::BatchWriteItemError,
+        describe_table::DescribeTableError, put_item::PutItemError, query::QueryError,
+        scan::ScanError,
+    },
     types::AttributeValue,
 };
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct PeerData {
     pub enode_url: String,
     pub id: String,
     pub address: String,
+    #[serde(default)]
+    pub is_ipv6: bool,
     pub tcp_port: u16,
+    pub udp_port: Option<u16>,
     pub client_version: String,
     pub eth_version: u8,
     pub capabilities: Vec<String>,
+    /// Whether `capabilities` include `snap/1` or a later snap version, see [`advertises_snap`].
+    #[serde(default)]
+    pub serves_snap: bool,
     pub chain: String,
     pub total_difficulty: String,
-    pub best_block: String, // TODO: convert this to a blocknum with a lookup
+    /// `total_difficulty` parsed as a number, `None` if it doesn't fit in a `u128`. See
+    /// [`PeerData::total_difficulty_u256`] for every total difficulty.
+    #[serde(default)]
+    pub total_difficulty_u128: Option<u128>,
+    pub best_block: String,
+    /// Number of `best_block`, if the peer answered our header request for it.
+    #[serde(default)]
+    pub best_block_number: Option<u64>,
     pub genesis_block_hash: String,
     pub last_seen: String,
     pub country: String,
     pub city: String,
+    /// How the crawler found the peer: `discv4`, `dns`, `inbound` or `static` for the peers of
+    /// `--static-peers-file`, or `recrawl` if the record was refreshed by dialing a known peer
+    /// again. Empty for peers recorded before sources were
+    /// tracked.
+    #[serde(default)]
+    pub discovery_source: String,
+    /// Hex of the RLP list of the peer's `Hello` and `Status` messages, kept to re-parse the
+    /// handshake offline. Only recorded on request, as it is large.
+    #[serde(default)]
+    pub raw_handshake: Option<String>,
+    /// Likely role of the node, e.g. `sentry` or `proxy`, as guessed by the crawler's heuristics.
+    /// `None` for regular nodes.
+    #[serde(default)]
+    pub node_role: Option<String>,
+    /// Time the crawler took to open the TCP connection to the peer, in milliseconds, through
+    /// the SOCKS5 proxy if any. `None` for inbound peers and peers recorded before it was measured.
+    #[serde(default)]
+    pub tcp_connect_ms: Option<u64>,
+    /// Time from the opened TCP connection to the peer's `Status`, covering the ECIES, `Hello` and
+    /// `Status` exchanges, in milliseconds. `None` like `tcp_connect_ms`.
+    #[serde(default)]
+    pub handshake_ms: Option<u64>,
+    /// Reason the peer gave for disconnecting during our handshake, e.g. `Too many peers`, on the
+    /// partial records of peers that accepted the connection but never completed a handshake.
+    /// `None` for the peers we fully handshaked.
+    #[serde(default)]
+    pub last_disconnect_reason: Option<String>,
+    /// Number of the autonomous system announcing the peer's address, e.g. `16509` for AWS, from
+    /// a MaxMind GeoLite2 ASN database. `None` if unknown or not looked up.
+    #[serde(default)]
+    pub asn: Option<u32>,
+    /// Organization running [`asn`](Self::asn), e.g. `Hetzner Online GmbH`.
+    #[serde(default)]
+    pub org: Option<String>,
+    /// Id of the crawl that last observed the peer, a UUID generated when the crawler starts
+    /// unless `--session-id` names it. Empty for peers recorded before sessions were tracked.
+    #[serde(default)]
+    pub session_id: String,
 }
 
 impl PeerData {
@@ -31,7 +94,9 @@ impl PeerData {
         enode_url: String,
         id: String,
         address: String,
+        is_ipv6: bool,
         tcp_port: u16,
+        udp_port: Option<u16>,
         client_version: String,
         capabilities: Vec<String>,
         last_seen: String,
@@ -39,49 +104,429 @@ impl PeerData {
         city: String,
         genesis_block_hash: String,
         best_block: String,
+        best_block_number: Option<u64>,
         total_difficulty: String,
         chain: String,
         eth_version: u8,
+        discovery_source: String,
+        raw_handshake: Option<String>,
+        node_role: Option<String>,
+        tcp_connect_ms: Option<u64>,
+        handshake_ms: Option<u64>,
+        serves_snap: bool,
+        last_disconnect_reason: Option<String>,
+        asn: Option<u32>,
+        org: Option<String>,
+        session_id: String,
     ) -> Self {
         Self {
             enode_url,
             id,
             address,
+            is_ipv6,
             tcp_port,
+            udp_port,
             client_version,
             capabilities,
             last_seen,
             country,
             city,
+            total_difficulty_u128: parse_total_difficulty(&total_difficulty),
             total_difficulty: total_difficulty,
             chain,
             best_block: best_block,
+            best_block_number,
             eth_version,
             genesis_block_hash: genesis_block_hash,
+            discovery_source,
+            raw_handshake,
+            node_role,
+            tcp_connect_ms,
+            handshake_ms,
+            serves_snap,
+            last_disconnect_reason,
+            asn,
+            org,
+            session_id,
         }
     }
 }
 
-impl From<&HashMap<String, AttributeValue>> for PeerData {
-    fn from(value: &HashMap<String, AttributeValue>) -> Self {
+impl PeerData {
+    pub fn builder() -> PeerDataBuilder {
+        PeerDataBuilder::default()
+    }
+
+    /// `total_difficulty` parsed as a 256 bits number, like the total difficulty of the `Status`
+    /// messages, `None` if it is malformed.
+    pub fn total_difficulty_u256(&self) -> Option<U256> {
+        parse_total_difficulty_u256(&self.total_difficulty)
+    }
+
+    /// Approximate size of the peer once stored, in bytes: the length of its text fields plus
+    /// a fixed allowance for its numbers and attribute names.
+    pub fn approximate_size(&self) -> usize {
+        const FIXED_SIZE: usize = 512;
+        FIXED_SIZE
+            + [
+                &self.enode_url,
+                &self.id,
+                &self.address,
+                &self.client_version,
+                &self.chain,
+                &self.total_difficulty,
+                &self.best_block,
+                &self.genesis_block_hash,
+                &self.last_seen,
+                &self.country,
+                &self.city,
+                &self.discovery_source,
+                &self.session_id,
+            ]
+            .iter()
+            .map(|field| field.len())
+            .sum::<usize>()
+            + self.capabilities.iter().map(String::len).sum::<usize>()
+            + self.raw_handshake.as_ref().map_or(0, String::len)
+            + self.node_role.as_ref().map_or(0, String::len)
+            + self.last_disconnect_reason.as_ref().map_or(0, String::len)
+            + self.org.as_ref().map_or(0, String::len)
+    }
+
+    /// Drop `raw_handshake`, then empty the largest of the fields advertised by the peer itself
+    /// (`capabilities`, `client_version` and `enode_url`) until its
+    /// [`approximate_size`](Self::approximate_size) is at most `max_size`. Returns the names of
+    /// the dropped fields.
+    pub fn truncate_to(&mut self, max_size: usize) -> Vec<&'static str> {
+        let mut dropped = Vec::new();
+        if self.approximate_size() > max_size && self.raw_handshake.take().is_some() {
+            dropped.push("raw_handshake");
+        }
+        while self.approximate_size() > max_size {
+            let largest = [
+                (
+                    "capabilities",
+                    self.capabilities.iter().map(String::len).sum(),
+                ),
+                ("client_version", self.client_version.len()),
+                ("enode_url", self.enode_url.len()),
+            ]
+            .into_iter()
+            .filter(|(_, size)| *size > 0)
+            .max_by_key(|(_, size)| *size);
+            match largest {
+                Some(("capabilities", _)) => self.capabilities.clear(),
+                Some(("client_version", _)) => self.client_version.clear(),
+                Some(("enode_url", _)) => self.enode_url.clear(),
+                _ => break,
+            }
+            dropped.extend(largest.map(|(field, _)| field));
+        }
+        dropped
+    }
+}
+
+/// Builds a [`PeerData`], checking that the peer identifies itself and can be dialed.
+///
+/// `is_ipv6` and `total_difficulty_u128` are derived from the address and total difficulty.
+#[derive(Clone, Debug, Default)]
+pub struct PeerDataBuilder {
+    enode_url: String,
+    id: String,
+    address: String,
+    tcp_port: u16,
+    udp_port: Option<u16>,
+    client_version: String,
+    eth_version: u8,
+    capabilities: Vec<String>,
+    chain: String,
+    total_difficulty: String,
+    best_block: String,
+    best_block_number: Option<u64>,
+    genesis_block_hash: String,
+    last_seen: String,
+    country: String,
+    city: String,
+    discovery_source: String,
+    raw_handshake: Option<String>,
+    node_role: Option<String>,
+    tcp_connect_ms: Option<u64>,
+    handshake_ms: Option<u64>,
+    last_disconnect_reason: Option<String>,
+    asn: Option<u32>,
+    org: Option<String>,
+    session_id: String,
+}
+
+impl PeerDataBuilder {
+    pub fn enode_url(mut self, enode_url: impl Into<String>) -> Self {
+        self.enode_url = enode_url.into();
+        self
+    }
+
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = id.into();
+        self
+    }
+
+    pub fn address(mut self, address: impl Into<String>) -> Self {
+        self.address = address.into();
+        self
+    }
+
+    pub fn tcp_port(mut self, tcp_port: u16) -> Self {
+        self.tcp_port = tcp_port;
+        self
+    }
+
+    pub fn udp_port(mut self, udp_port: Option<u16>) -> Self {
+        self.udp_port = udp_port;
+        self
+    }
+
+    pub fn client_version(mut self, client_version: impl Into<String>) -> Self {
+        self.client_version = client_version.into();
+        self
+    }
+
+    pub fn eth_version(mut self, eth_version: u8) -> Self {
+        self.eth_version = eth_version;
+        self
+    }
+
+    pub fn capabilities(mut self, capabilities: Vec<String>) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn chain(mut self, chain: impl Into<String>) -> Self {
+        self.chain = chain.into();
+        self
+    }
+
+    pub fn total_difficulty(mut self, total_difficulty: impl Into<String>) -> Self {
+        self.total_difficulty = total_difficulty.into();
+        self
+    }
+
+    pub fn best_block(mut self, best_block: impl Into<String>) -> Self {
+        self.best_block = best_block.into();
+        self
+    }
+
+    pub fn best_block_number(mut self, best_block_number: Option<u64>) -> Self {
+        self.best_block_number = best_block_number;
+        self
+    }
+
+    pub fn genesis_block_hash(mut self, genesis_block_hash: impl Into<String>) -> Self {
+        self.genesis_block_hash = genesis_block_hash.into();
+        self
+    }
+
+    pub fn last_seen(mut self, last_seen: impl Into<String>) -> Self {
+        self.last_seen = last_seen.into();
+        self
+    }
+
+    pub fn country(mut self, country: impl Into<String>) -> Self {
+        self.country = country.into();
+        self
+    }
+
+    pub fn city(mut self, city: impl Into<String>) -> Self {
+        self.city = city.into();
+        self
+    }
+
+    pub fn discovery_source(mut self, discovery_source: impl Into<String>) -> Self {
+        self.discovery_source = discovery_source.into();
+        self
+    }
+
+    pub fn raw_handshake(mut self, raw_handshake: Option<String>) -> Self {
+        self.raw_handshake = raw_handshake;
+        self
+    }
+
+    pub fn node_role(mut self, node_role: Option<String>) -> Self {
+        self.node_role = node_role;
+        self
+    }
+
+    pub fn tcp_connect_ms(mut self, tcp_connect_ms: Option<u64>) -> Self {
+        self.tcp_connect_ms = tcp_connect_ms;
+        self
+    }
+
+    pub fn handshake_ms(mut self, handshake_ms: Option<u64>) -> Self {
+        self.handshake_ms = handshake_ms;
+        self
+    }
+
+    pub fn last_disconnect_reason(mut self, last_disconnect_reason: Option<String>) -> Self {
+        self.last_disconnect_reason = last_disconnect_reason;
+        self
+    }
+
+    pub fn asn(mut self, asn: Option<u32>) -> Self {
+        self.asn = asn;
+        self
+    }
+
+    pub fn org(mut self, org: Option<String>) -> Self {
+        self.org = org;
+        self
+    }
+
+    pub fn session_id(mut self, session_id: impl Into<String>) -> Self {
+        self.session_id = session_id.into();
+        self
+    }
+
+    /// Build the peer, failing if its id is empty or not hex, its address isn't an IP address, its
+    /// TCP port is 0 or its enode URL is malformed. The id is normalized with
+    /// [`normalize_peer_id`]. A missing enode URL is rebuilt from the id, address and TCP port
+    /// when the id is a full 64 byte node id, and left empty otherwise. The control characters of
+    /// the free-text fields, like the client version, are removed with [`strip_control_chars`];
+    /// the raw handshake, if recorded, keeps the original.
+    pub fn build(self) -> Result<PeerData, PeerDataError> {
+        let id = normalize_peer_id(&self.id);
+        if id.is_empty() {
+            return Err(PeerDataError::EmptyId);
+        }
+        if !id.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(PeerDataError::InvalidId(self.id));
+        }
+        let ip = self
+            .address
+            .parse::<IpAddr>()
+            .map_err(|_| PeerDataError::InvalidAddress(self.address.clone()))?;
+        if self.tcp_port == 0 {
+            return Err(PeerDataError::InvalidPort);
+        }
+        let enode_url = if !self.enode_url.is_empty() {
+            if !is_valid_enode_url(&self.enode_url) {
+                return Err(PeerDataError::InvalidEnodeUrl(self.enode_url));
+            }
+            self.enode_url
+        } else if id.len() == NODE_ID_HEX_LEN {
+            format!("enode://{}@{}", id, SocketAddr::new(ip, self.tcp_port))
+        } else {
+            String::new()
+        };
+
+        Ok(PeerData {
+            enode_url,
+            id,
+            address: self.address,
+            is_ipv6: ip.is_ipv6(),
+            tcp_port: self.tcp_port,
+            udp_port: self.udp_port,
+            client_version: strip_control_chars(self.client_version),
+            eth_version: self.eth_version,
+            serves_snap: advertises_snap(&self.capabilities),
+            capabilities: self
+                .capabilities
+                .into_iter()
+                .map(strip_control_chars)
+                .collect(),
+            chain: self.chain,
+            total_difficulty_u128: parse_total_difficulty(&self.total_difficulty),
+            total_difficulty: self.total_difficulty,
+            best_block: self.best_block,
+            best_block_number: self.best_block_number,
+            genesis_block_hash: self.genesis_block_hash,
+            last_seen: self.last_seen,
+            country: strip_control_chars(self.country),
+            city: strip_control_chars(self.city),
+            discovery_source: self.discovery_source,
+            raw_handshake: self.raw_handshake,
+            node_role: self.node_role,
+            tcp_connect_ms: self.tcp_connect_ms,
+            handshake_ms: self.handshake_ms,
+            last_disconnect_reason: self.last_disconnect_reason.map(strip_control_chars),
+            asn: self.asn,
+            org: self.org.map(strip_control_chars),
+            session_id: self.session_id,
+        })
+    }
+}
+
+/// Start from an existing peer, e.g. to check an imported peer with [`PeerDataBuilder::build`].
+/// The derived fields are derived again.
+impl From<PeerData> for PeerDataBuilder {
+    fn from(peer_data: PeerData) -> Self {
+        Self {
+            enode_url: peer_data.enode_url,
+            id: peer_data.id,
+            address: peer_data.address,
+            tcp_port: peer_data.tcp_port,
+            udp_port: peer_data.udp_port,
+            client_version: peer_data.client_version,
+            eth_version: peer_data.eth_version,
+            capabilities: peer_data.capabilities,
+            chain: peer_data.chain,
+            total_difficulty: peer_data.total_difficulty,
+            best_block: peer_data.best_block,
+            best_block_number: peer_data.best_block_number,
+            genesis_block_hash: peer_data.genesis_block_hash,
+            last_seen: peer_data.last_seen,
+            country: peer_data.country,
+            city: peer_data.city,
+            discovery_source: peer_data.discovery_source,
+            raw_handshake: peer_data.raw_handshake,
+            node_role: peer_data.node_role,
+            tcp_connect_ms: peer_data.tcp_connect_ms,
+            handshake_ms: peer_data.handshake_ms,
+            last_disconnect_reason: peer_data.last_disconnect_reason,
+            asn: peer_data.asn,
+            org: peer_data.org,
+            session_id: peer_data.session_id,
+        }
+    }
+}
+
+/// Read a DynamoDB item. Fails if its ports are out of the `u16` range rather than truncating
+/// them; missing attributes take a default value.
+impl TryFrom<&HashMap<String, AttributeValue>> for PeerData {
+    type Error = PeerDataError;
+
+    fn try_from(value: &HashMap<String, AttributeValue>) -> Result<Self, Self::Error> {
+        let capabilities = as_string_vec(value.get("capabilities"));
+        // items written before the flag was stored derive it from their capabilities
+        let serves_snap = as_bool(value.get("serves_snap"), advertises_snap(&capabilities));
         let peer_data = PeerData::new(
             as_string(value.get("enode_url"), &"".to_string()),
             as_string(value.get("peer-id"), &"".to_string()),
             as_string(value.get("peer-ip"), &"".to_string()),
-            as_u16(value.get("port"), 30303),
+            as_bool(value.get("is_ipv6"), false),
+            as_port(value.get("port"))?.unwrap_or(30303),
+            as_port(value.get("udp_port"))?,
             as_string(value.get("client_version"), &"".to_string()),
-            as_string_vec(value.get("capabilities")),
+            capabilities,
             as_string(value.get("last_seen"), &"".to_string()),
             as_string(value.get("country"), &"".to_string()),
             as_string(value.get("city"), &"".to_string()),
             as_string(value.get("genesis_block_hash"), &"".to_string()),
             as_string(value.get("best_block"), &"".to_string()),
+            as_opt_u64(value.get("best_block_number")),
             as_string(value.get("total_difficulty"), &"".to_string()),
             as_string(value.get("chain"), &"".to_string()),
             as_u8(value.get("eth_version"), 0),
+            as_string(value.get("discovery_source"), &"".to_string()),
+            as_opt_string(value.get("raw_handshake")),
+            as_opt_string(value.get("node_role")),
+            as_opt_u64(value.get("tcp_connect_ms")),
+            as_opt_u64(value.get("handshake_ms")),
+            serves_snap,
+            as_opt_string(value.get("last_disconnect_reason")),
+            as_opt_u32(value.get("asn")),
+            as_opt_string(value.get("org")),
+            as_string(value.get("session_id"), &"".to_string()),
         );
 
-        peer_data
+        Ok(peer_data)
     }
 }
 
@@ -105,6 +550,42 @@ pub fn as_u16(val: Option<&AttributeValue>, default: u16) -> u16 {
     default
 }
 
+/// Read a port number attribute, `None` if it is missing or not a number.
+pub fn as_port(val: Option<&AttributeValue>) -> Result<Option<u16>, PeerDataError> {
+    match val.and_then(|v| v.as_n().ok()) {
+        Some(n) => parse_port(n).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Parse a port number, failing if it doesn't fit in a `u16`.
+pub fn parse_port(port: &str) -> Result<u16, PeerDataError> {
+    port.parse::<u16>()
+        .map_err(|_| PeerDataError::PortOutOfRange(port.to_string()))
+}
+
+pub fn as_opt_string(val: Option<&AttributeValue>) -> Option<String> {
+    val.and_then(|v| v.as_s().ok()).map(|s| s.to_owned())
+}
+
+pub fn as_opt_u32(val: Option<&AttributeValue>) -> Option<u32> {
+    if let Some(v) = val {
+        if let Ok(n) = v.as_n() {
+            return n.parse::<u32>().ok();
+        }
+    }
+    None
+}
+
+pub fn as_opt_u64(val: Option<&AttributeValue>) -> Option<u64> {
+    if let Some(v) = val {
+        if let Ok(n) = v.as_n() {
+            return n.parse::<u64>().ok();
+        }
+    }
+    None
+}
+
 pub fn as_u8(val: Option<&AttributeValue>, default: u8) -> u8 {
     if let Some(v) = val {
         if let Ok(n) = v.as_n() {
@@ -116,6 +597,15 @@ pub fn as_u8(val: Option<&AttributeValue>, default: u8) -> u8 {
     default
 }
 
+pub fn as_bool(val: Option<&AttributeValue>, default: bool) -> bool {
+    if let Some(v) = val {
+        if let Ok(b) = v.as_bool() {
+            return *b;
+        }
+    }
+    default
+}
+
 pub fn as_string_vec(val: Option<&AttributeValue>) -> Vec<String> {
     if let Some(val) = val {
         if let Ok(val) = val.as_l() {
@@ -128,14 +618,397 @@ pub fn as_string_vec(val: Option<&AttributeValue>) -> Vec<String> {
     vec![]
 }
 
+/// Parse a decimal total difficulty, returning `None` if it is malformed or overflows a `u128`.
+pub fn parse_total_difficulty(total_difficulty: &str) -> Option<u128> {
+    total_difficulty.parse::<u128>().ok()
+}
+
+/// Parse a decimal total difficulty into a [`U256`], returning `None` if it is malformed. Unlike
+/// [`parse_total_difficulty`] it never overflows, since the `Status` messages carry a `U256`.
+pub fn parse_total_difficulty_u256(total_difficulty: &str) -> Option<U256> {
+    U256::from_str_radix(total_difficulty, 10).ok()
+}
+
+/// A range of IP addresses in CIDR notation, e.g. `203.0.113.0/24` or `2001:db8::/32`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Parse `cidr`, failing unless it's an IP address followed by `/` and a prefix length of at
+    /// most 32 for IPv4 or 128 for IPv6. The address bits past the prefix are ignored.
+    pub fn parse(cidr: &str) -> Result<Self, QueryItemError> {
+        let invalid = || QueryItemError::InvalidCidrError(cidr.to_string());
+        let (network, prefix_len) = cidr.trim().split_once('/').ok_or_else(invalid)?;
+        let network: IpAddr = network.parse().map_err(|_| invalid())?;
+        if !prefix_len.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        let prefix_len: u8 = prefix_len.parse().map_err(|_| invalid())?;
+        let max_prefix_len = if network.is_ipv4() { 32 } else { 128 };
+        if prefix_len > max_prefix_len {
+            return Err(invalid());
+        }
+        Ok(Self {
+            network,
+            prefix_len,
+        })
+    }
+
+    /// Whether `address` is an IP address within the range. Addresses of the other IP version
+    /// never are.
+    pub fn contains(&self, address: &str) -> bool {
+        match (self.network, address.parse::<IpAddr>()) {
+            (IpAddr::V4(network), Ok(IpAddr::V4(ip))) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(ip) & mask == u32::from(network) & mask
+            }
+            (IpAddr::V6(network), Ok(IpAddr::V6(ip))) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(ip) & mask == u128::from(network) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Length of a node id, a 64 byte public key, in hex digits.
+const NODE_ID_HEX_LEN: usize = 128;
+
+/// Whether `enode_url` has the `enode://<128 hex digits>@<ip>:<port>` form, IPv6 addresses being
+/// in brackets, optionally followed by the `?discport=<port>` of peers whose UDP port differs.
+pub fn is_valid_enode_url(enode_url: &str) -> bool {
+    let Some((id, endpoint)) = enode_url
+        .strip_prefix("enode://")
+        .and_then(|rest| rest.split_once('@'))
+    else {
+        return false;
+    };
+    let (address, valid_query) = match endpoint.split_once('?') {
+        Some((address, query)) => (
+            address,
+            query
+                .strip_prefix("discport=")
+                .is_some_and(|port| port.parse::<u16>().is_ok()),
+        ),
+        None => (endpoint, true),
+    };
+    id.len() == NODE_ID_HEX_LEN
+        && id.chars().all(|c| c.is_ascii_hexdigit())
+        && address.parse::<SocketAddr>().is_ok()
+        && valid_query
+}
+
+/// Canonicalize a peer id to lowercase hex without a `0x` prefix, the form it is stored in, so
+/// that `0xABCD`, `abcd` and `ABCD` all refer to the same peer.
+pub fn normalize_peer_id(id: &str) -> String {
+    let id = id.trim();
+    id.strip_prefix("0x")
+        .or_else(|| id.strip_prefix("0X"))
+        .unwrap_or(id)
+        .to_ascii_lowercase()
+}
+
+/// Canonicalize an IP address to the form it is stored in, so that `2001:0db8:0:0::1`,
+/// `[2001:db8::1]` and `2001:DB8::1` all refer to the same address, `2001:db8::1`. Addresses that
+/// can't be parsed are only trimmed.
+pub fn normalize_ip(address: &str) -> String {
+    let address = address.trim();
+    let unbracketed = address
+        .strip_prefix('[')
+        .and_then(|address| address.strip_suffix(']'))
+        .unwrap_or(address);
+    match unbracketed.parse::<IpAddr>() {
+        Ok(ip) => ip.to_string(),
+        Err(_) => address.to_string(),
+    }
+}
+
+/// Remove the control characters of a free-text field, e.g. a NUL or an ANSI escape sequence in the
+/// client version a peer advertises, which would break the CSV exports and the log lines.
+pub fn strip_control_chars(text: String) -> String {
+    if text.chars().any(char::is_control) {
+        text.chars().filter(|c| !c.is_control()).collect()
+    } else {
+        text
+    }
+}
+
+/// Whether two hex encoded hashes are equal, ignoring case and `0x` prefixes.
+pub fn same_hash(a: &str, b: &str) -> bool {
+    normalize_peer_id(a) == normalize_peer_id(b)
+}
+
+/// Smallest difference between two observations of a peer's best block number that
+/// [`peer_changed`] reports, so that a peer merely following the chain isn't reported.
+pub const BEST_BLOCK_JUMP: u64 = 10_000;
+
+/// A field whose value differs between two observations of the same peer.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub old: String,
+    pub new: String,
+}
+
+/// The notable changes between the `existing` record of a peer and an `incoming` observation
+/// of it: its client, capabilities, protocol version, endpoint, network, and best block number
+/// when it moved by at least [`BEST_BLOCK_JUMP`] blocks.
+pub fn peer_changed(existing: &PeerData, incoming: &PeerData) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+    let mut compare = |field, old: String, new: String| {
+        if old != new {
+            changes.push(FieldChange { field, old, new });
+        }
+    };
+    compare(
+        "client_version",
+        existing.client_version.clone(),
+        incoming.client_version.clone(),
+    );
+    compare(
+        "capabilities",
+        existing.capabilities.join(","),
+        incoming.capabilities.join(","),
+    );
+    compare(
+        "eth_version",
+        existing.eth_version.to_string(),
+        incoming.eth_version.to_string(),
+    );
+    compare(
+        "address",
+        existing.address.clone(),
+        incoming.address.clone(),
+    );
+    compare(
+        "tcp_port",
+        existing.tcp_port.to_string(),
+        incoming.tcp_port.to_string(),
+    );
+    compare("chain", existing.chain.clone(), incoming.chain.clone());
+    if let (Some(old), Some(new)) = (existing.best_block_number, incoming.best_block_number) {
+        if old.abs_diff(new) >= BEST_BLOCK_JUMP {
+            compare("best_block_number", old.to_string(), new.to_string());
+        }
+    }
+    changes
+}
+
+/// Number of peers stored at `timestamp`, an entry of the peer count history.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerCountSnapshot {
+    pub timestamp: String,
+    pub count: u64,
+}
+
+/// p50, p90 and p99 of a set of latencies, in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyPercentiles {
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+}
+
+impl LatencyPercentiles {
+    /// Nearest-rank percentiles of `samples`, `None` if there are none.
+    pub fn from_samples(mut samples: Vec<u64>) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_unstable();
+        let percentile = |p: usize| samples[(samples.len() * p).div_ceil(100).max(1) - 1];
+        Some(Self {
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+        })
+    }
+}
+
+/// An invariant of the stored peers, broken by a record found by [`integrity_violations`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum IntegrityViolation {
+    /// The peer id is empty.
+    EmptyId,
+    /// The address isn't an IP address.
+    InvalidAddress,
+    /// The TCP port is 0.
+    InvalidTcpPort,
+    /// The enode URL doesn't pass [`is_valid_enode_url`].
+    MalformedEnodeUrl,
+    /// `last_seen` is neither a timestamp as the crawler writes them nor RFC 3339.
+    InvalidLastSeen,
+    /// A capability isn't `<name>/<version>`, e.g. after being split on the wrong separator, or
+    /// `serves_snap` disagrees with the capabilities.
+    InconsistentCapabilities,
+}
+
+impl fmt::Display for IntegrityViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::EmptyId => "empty id",
+            Self::InvalidAddress => "invalid address",
+            Self::InvalidTcpPort => "invalid tcp port",
+            Self::MalformedEnodeUrl => "malformed enode URL",
+            Self::InvalidLastSeen => "invalid last_seen",
+            Self::InconsistentCapabilities => "inconsistent capabilities",
+        })
+    }
+}
+
+/// The invariants `peer_data` breaks, empty if it is a valid record.
+pub fn integrity_violations(peer_data: &PeerData) -> Vec<IntegrityViolation> {
+    let mut violations = Vec::new();
+    if peer_data.id.is_empty() {
+        violations.push(IntegrityViolation::EmptyId);
+    }
+    if peer_data.address.parse::<IpAddr>().is_err() {
+        violations.push(IntegrityViolation::InvalidAddress);
+    }
+    if peer_data.tcp_port == 0 {
+        violations.push(IntegrityViolation::InvalidTcpPort);
+    }
+    if !is_valid_enode_url(&peer_data.enode_url) {
+        violations.push(IntegrityViolation::MalformedEnodeUrl);
+    }
+    if !is_valid_last_seen(&peer_data.last_seen) {
+        violations.push(IntegrityViolation::InvalidLastSeen);
+    }
+    if !peer_data
+        .capabilities
+        .iter()
+        .all(|cap| is_valid_capability(cap))
+        || peer_data.serves_snap != advertises_snap(&peer_data.capabilities)
+    {
+        violations.push(IntegrityViolation::InconsistentCapabilities);
+    }
+    violations
+}
+
+/// Whether `last_seen` is a UTC timestamp as the crawler writes them, e.g.
+/// `2023-10-20 12:00:00.123 UTC`, or an RFC 3339 timestamp.
+fn is_valid_last_seen(last_seen: &str) -> bool {
+    NaiveDateTime::parse_from_str(last_seen, "%Y-%m-%d %H:%M:%S%.f UTC").is_ok()
+        || DateTime::parse_from_rfc3339(last_seen).is_ok()
+}
+
+/// Whether `capability` is a `<name>/<version>` pair such as `eth/68`.
+fn is_valid_capability(capability: &str) -> bool {
+    capability.split_once('/').is_some_and(|(name, version)| {
+        !name.is_empty()
+            && !name.chars().any(|c| c.is_whitespace() || c == ',')
+            && version.parse::<u32>().is_ok()
+    })
+}
+
+/// What [`crate::verify_peers`] found in a db.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IntegrityReport {
+    /// Number of peers checked.
+    pub checked: u64,
+    /// Number of peers breaking each invariant. A peer may break several.
+    pub violations: BTreeMap<IntegrityViolation, u64>,
+    /// Ids of the peers breaking at least one invariant.
+    pub invalid_ids: Vec<String>,
+}
+
+/// Version of the stored peer schema, bumped whenever the fields of [`PeerData`] change.
+pub const SCHEMA_VERSION: u32 = 8;
+
+/// Which version of the crate wrote a db, and with which schema, so that consumers of a
+/// snapshot know which fields to expect.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SchemaInfo {
+    pub crate_version: String,
+    pub schema_version: u32,
+}
+
+impl SchemaInfo {
+    /// The schema written by this version of the crate.
+    pub fn current() -> Self {
+        Self {
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            schema_version: SCHEMA_VERSION,
+        }
+    }
+}
+
+/// Client families reported by [`client_family`].
+pub const CLIENT_FAMILIES: [&str; 5] = ["reth", "geth", "nethermind", "besu", "erigon"];
+
+/// Whether `capabilities` include the snap protocol, `snap/1` or a later version.
+pub fn advertises_snap(capabilities: &[String]) -> bool {
+    capabilities.iter().any(|cap| {
+        cap.strip_prefix("snap/")
+            .and_then(|version| version.parse::<u32>().ok())
+            .is_some_and(|version| version >= 1)
+    })
+}
+
+/// Normalize a client version such as `Geth/v1.13.4-stable/linux-amd64/go1.21.3` to its client
+/// family, e.g. `geth`. Clients outside of [`CLIENT_FAMILIES`] are `unknown`.
+pub fn client_family(client_version: &str) -> &'static str {
+    let name = client_version
+        .split('/')
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    CLIENT_FAMILIES
+        .into_iter()
+        .find(|family| name == *family)
+        .unwrap_or("unknown")
+}
+
+/// Parse `address` and tell whether it is an IPv6 address.
+///
+/// Returns `None` and logs a warning if `address` is not a valid IP address, so that malformed
+/// addresses end up in neither the IPv4 nor the IPv6 bucket.
+pub fn parse_is_ipv6(address: &str) -> Option<bool> {
+    match address.parse::<IpAddr>() {
+        Ok(ip) => Some(ip.is_ipv6()),
+        Err(_) => {
+            warn!("Malformed peer address: {}", address);
+            None
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum AddItemError {
     #[error("An error occurred adding a new item into the AWS database: {0}")]
     AwsAddItemError(#[from] SdkError<PutItemError>),
+    #[error("An error occurred adding a batch of items into the AWS database: {0}")]
+    AwsBatchAddItemError(#[from] SdkError<BatchWriteItemError>),
+    #[error("{0} items were left unprocessed by the AWS database")]
+    AwsUnprocessedItemsError(usize),
     #[error("An error occurred adding a new item into the in memory db")]
     InMemoryDbAddItemError(),
     #[error("An error occurred adding a new item into the SQL database: {0}")]
     SqlAddItemError(#[from] tokio_rusqlite::Error),
+    #[error("Failed to add peer {id} at {address}: {source}")]
+    PeerAddItemError {
+        id: String,
+        address: String,
+        source: Box<AddItemError>,
+    },
+}
+
+impl AddItemError {
+    /// Wrap the error with the id and address of the peer that couldn't be added.
+    pub fn with_peer(self, id: &str, address: &str) -> Self {
+        Self::PeerAddItemError {
+            id: id.to_string(),
+            address: address.to_string(),
+            source: Box::new(self),
+        }
+    }
 }
 
 #[derive(Debug, Error)]
@@ -152,18 +1025,78 @@ pub enum ScanTableError {
 pub enum QueryItemError {
     #[error("An error occurred querying the AWS database: {0}")]
     AwsQueryItemError(#[from] SdkError<QueryError>),
+    #[error("An error occurred scanning the AWS database: {0}")]
+    AwsScanItemError(#[from] SdkError<ScanError>),
+    #[error("An error occurred reading a batch of items from the AWS database: {0}")]
+    AwsBatchGetItemError(#[from] SdkError<BatchGetItemError>),
+    #[error("{0} keys were left unprocessed by the AWS database")]
+    AwsUnprocessedKeysError(usize),
+    #[error("An error occurred reading the key schema of the AWS table: {0}")]
+    AwsDescribeTableError(#[from] SdkError<DescribeTableError>),
     #[error("An error occurred querying the in memory database")]
     InMemoryDbQueryItemError(),
+    #[error("The database has no schema info")]
+    MissingSchemaInfoError(),
+    #[error("Not a valid CIDR range: {0}")]
+    InvalidCidrError(String),
     #[error("An error occurred querying the SQL database: {0}")]
     SqlQueryItemError(#[from] tokio_rusqlite::Error),
 }
 
 #[derive(Debug, Error)]
 pub enum DeleteItemError {
+    #[error("An error occurred reading the key schema of the AWS table: {0}")]
+    AwsDescribeTableError(#[from] SdkError<DescribeTableError>),
+    #[error("An error occurred scanning the items to delete from the AWS database: {0}")]
+    AwsScanError(#[from] SdkError<ScanError>),
+    #[error("An error occurred querying the items to delete from the AWS database: {0}")]
+    AwsQueryError(#[from] SdkError<QueryError>),
+    #[error("An error occurred deleting a batch of items from the AWS database: {0}")]
+    AwsBatchDeleteItemError(#[from] SdkError<BatchWriteItemError>),
+    #[error("{0} items were left undeleted by the AWS database")]
+    AwsUnprocessedItemsError(usize),
+    #[error("An error occurred deleting items from the in memory database")]
+    InMemoryDbDeleteItemError(),
     #[error("An error occurred deleting a new item into the SQL database: {0}")]
     SqlDeleteItemError(#[from] tokio_rusqlite::Error),
 }
 
+#[derive(Debug, Error)]
+pub enum UpdateItemError {
+    #[error("An error occurred querying the item to update from the AWS database: {0}")]
+    AwsQueryItemError(#[from] SdkError<QueryError>),
+    #[error("An error occurred writing an updated item into the AWS database: {0}")]
+    AwsPutItemError(#[from] SdkError<PutItemError>),
+    #[error("An error occurred updating an item of the in memory database")]
+    InMemoryDbUpdateItemError(),
+    #[error("An error occurred updating an item of the SQL database: {0}")]
+    SqlUpdateItemError(#[from] tokio_rusqlite::Error),
+}
+
+#[derive(Debug, Error)]
+pub enum TableError {
+    #[error("The AWS table `{0}` does not exist")]
+    AwsTableNotFoundError(String),
+    #[error("An error occurred describing the AWS table: {0}")]
+    AwsDescribeTableError(#[from] SdkError<DescribeTableError>),
+}
+
+#[derive(Debug, Error)]
+pub enum PeerDataError {
+    #[error("The peer id is empty")]
+    EmptyId,
+    #[error("The peer id is not hex: {0}")]
+    InvalidId(String),
+    #[error("The peer address is not a valid IP address: {0}")]
+    InvalidAddress(String),
+    #[error("The peer TCP port is 0")]
+    InvalidPort,
+    #[error("The peer enode URL is malformed: {0}")]
+    InvalidEnodeUrl(String),
+    #[error("The peer port is not between 0 and 65535: {0}")]
+    PortOutOfRange(String),
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ClientData {
     pub client_version: String,
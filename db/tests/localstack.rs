@@ -0,0 +1,362 @@
+//! Round trips through [`AwsPeerDB`] against the DynamoDB of a LocalStack instance, at
+//! `LOCALSTACK_ENDPOINT` or `http://localhost:4566` by default:
+//!
+//! ```bash
+//! docker run --rm -p 4566:4566 localstack/localstack
+//! cargo test -p reth-crawler-db --features localstack --test localstack
+//! ```
+//!
+//! Every test creates its own table, so the tests can run concurrently against one instance.
+#![cfg(feature = "localstack")]
+
+use aws_sdk_dynamodb::config::Region;
+use aws_sdk_dynamodb::types::{
+    AttributeDefinition, BillingMode, GlobalSecondaryIndex, KeySchemaElement, KeyType, Projection,
+    ProjectionType, ScalarAttributeType,
+};
+use aws_sdk_dynamodb::Client;
+use chrono::{Duration, Utc};
+use reth_crawler_db::types::{advertises_snap, parse_total_difficulty};
+use reth_crawler_db::{AwsPeerDB, PeerCountSnapshot, PeerDB, PeerData};
+use std::collections::HashSet;
+
+const DEFAULT_ENDPOINT: &str = "http://localhost:4566";
+
+fn endpoint() -> String {
+    std::env::var("LOCALSTACK_ENDPOINT").unwrap_or_else(|_| DEFAULT_ENDPOINT.to_string())
+}
+
+/// A db backed by a new table of the LocalStack instance, keyed like the crawler's tables.
+async fn localstack_db() -> AwsPeerDB {
+    localstack_db_with_sort_key(None).await
+}
+
+/// Like [`localstack_db`], with `sort_key` as the sort key of the table if any, like tables
+/// keeping one item per peer and region.
+async fn localstack_db_with_sort_key(sort_key: Option<&str>) -> AwsPeerDB {
+    // LocalStack accepts any credentials, but the SDK still needs some
+    for (name, value) in [
+        ("AWS_ACCESS_KEY_ID", "test"),
+        ("AWS_SECRET_ACCESS_KEY", "test"),
+        ("AWS_REGION", "us-east-1"),
+    ] {
+        if std::env::var_os(name).is_none() {
+            std::env::set_var(name, value);
+        }
+    }
+    let shared_config = aws_config::from_env()
+        .region(Region::new("us-east-1"))
+        .load()
+        .await;
+    let client = Client::from_conf(
+        aws_sdk_dynamodb::config::Builder::from(&shared_config)
+            .endpoint_url(endpoint())
+            .build(),
+    );
+    let table = format!("eth-peer-data-{:08x}", rand::random::<u32>());
+    let string_attribute = |name: &str| {
+        AttributeDefinition::builder()
+            .attribute_name(name)
+            .attribute_type(ScalarAttributeType::S)
+            .build()
+    };
+    let hash_key = |name: &str| {
+        KeySchemaElement::builder()
+            .attribute_name(name)
+            .key_type(KeyType::Hash)
+            .build()
+    };
+    let mut create_table = client
+        .create_table()
+        .table_name(&table)
+        .billing_mode(BillingMode::PayPerRequest)
+        .attribute_definitions(string_attribute("peer-id"))
+        .attribute_definitions(string_attribute("peer-ip"))
+        .key_schema(hash_key("peer-id"));
+    if let Some(sort_key) = sort_key {
+        create_table = create_table
+            .attribute_definitions(string_attribute(sort_key))
+            .key_schema(
+                KeySchemaElement::builder()
+                    .attribute_name(sort_key)
+                    .key_type(KeyType::Range)
+                    .build(),
+            );
+    }
+    create_table
+        .global_secondary_indexes(
+            GlobalSecondaryIndex::builder()
+                .index_name("peer-ip-index")
+                .key_schema(hash_key("peer-ip"))
+                .projection(
+                    Projection::builder()
+                        .projection_type(ProjectionType::All)
+                        .build(),
+                )
+                .build(),
+        )
+        .send()
+        .await
+        .unwrap();
+
+    let db = AwsPeerDB::new(Some(endpoint())).await.with_table(table);
+    db.check_table().await.unwrap();
+    db
+}
+
+fn peer(id: &str, address: &str, capabilities: &[&str]) -> PeerData {
+    let total_difficulty = "17179869184".to_string();
+    let capabilities: Vec<String> = capabilities.iter().map(|cap| cap.to_string()).collect();
+    PeerData {
+        enode_url: format!("enode://{id}@{address}:30303"),
+        id: id.to_string(),
+        address: address.to_string(),
+        is_ipv6: address.contains(':'),
+        tcp_port: 30303,
+        udp_port: Some(30303),
+        client_version: "Geth/v1.13.4-stable/linux-amd64/go1.21.3".to_string(),
+        eth_version: 68,
+        serves_snap: advertises_snap(&capabilities),
+        capabilities,
+        chain: "mainnet".to_string(),
+        total_difficulty_u128: parse_total_difficulty(&total_difficulty),
+        total_difficulty,
+        best_block: "0x1f3c".to_string(),
+        best_block_number: Some(18_000_000),
+        genesis_block_hash: "0xd4e5".to_string(),
+        // within the default window of the scans
+        last_seen: Utc::now().to_string(),
+        country: "Germany".to_string(),
+        city: "Berlin".to_string(),
+        discovery_source: "discv4".to_string(),
+        raw_handshake: None,
+        node_role: None,
+        tcp_connect_ms: Some(40),
+        handshake_ms: Some(120),
+        last_disconnect_reason: None,
+        asn: None,
+        org: None,
+        session_id: "4f0c3b6e-8d2a-4e51-9b7c-2a6f1d0e9c83".to_string(),
+    }
+}
+
+#[tokio::test]
+async fn node_by_id_returns_added_peer() {
+    let db = localstack_db().await;
+    let mut added = peer("a1", "10.0.0.1", &["eth/67", "eth/68", "snap/1"]);
+    added.raw_handshake = Some("f84b05".to_string());
+    added.node_role = Some("sentry".to_string());
+    added.asn = Some(24940);
+    added.org = Some("Hetzner Online GmbH".to_string());
+    db.add_peer(added.clone(), Some(0)).await.unwrap();
+
+    let found = db.node_by_id("a1".to_string()).await.unwrap().unwrap();
+    assert_eq!(found, vec![added]);
+}
+
+#[tokio::test]
+async fn optional_fields_round_trip_as_none() {
+    let db = localstack_db().await;
+    let mut added = peer("a1", "10.0.0.1", &[]);
+    added.udp_port = None;
+    added.best_block_number = None;
+    added.tcp_connect_ms = None;
+    added.handshake_ms = None;
+    db.add_peer(added.clone(), None).await.unwrap();
+
+    let found = db.node_by_id("a1".to_string()).await.unwrap().unwrap();
+    assert_eq!(found, vec![added]);
+}
+
+#[tokio::test]
+async fn node_by_ip_matches_address() {
+    let db = localstack_db().await;
+    db.add_peer(peer("a1", "10.0.0.1", &["eth/68"]), Some(0))
+        .await
+        .unwrap();
+    db.add_peer(peer("a2", "2001:db8::1", &["eth/68"]), Some(0))
+        .await
+        .unwrap();
+
+    let found = db.node_by_ip("2001:db8::1".to_string()).await.unwrap();
+    let ids: Vec<String> = found.unwrap().into_iter().map(|peer| peer.id).collect();
+    assert_eq!(ids, vec!["a2".to_string()]);
+}
+
+#[tokio::test]
+async fn node_by_ip_matches_any_form_of_a_v6_address() {
+    let db = localstack_db().await;
+    db.add_peer(
+        peer("a1", "2001:0db8:0000:0000:0000:0000:0000:0001", &["eth/68"]),
+        Some(0),
+    )
+    .await
+    .unwrap();
+
+    let found = db.node_by_ip("2001:db8::1".to_string()).await.unwrap();
+    let ids: Vec<String> = found.unwrap().into_iter().map(|peer| peer.id).collect();
+    assert_eq!(ids, vec!["a1".to_string()]);
+}
+
+#[tokio::test]
+async fn peers_exist_returns_the_known_ids() {
+    let db = localstack_db().await;
+    db.add_peer(peer("a1", "10.0.0.1", &["eth/68"]), Some(0))
+        .await
+        .unwrap();
+
+    let ids = ["0xA1", "a2"].map(String::from).to_vec();
+    let found = db.peers_exist(ids).await.unwrap();
+    assert_eq!(found, HashSet::from(["a1".to_string()]));
+}
+
+#[tokio::test]
+async fn peers_exist_reads_tables_with_a_sort_key() {
+    let db = localstack_db_with_sort_key(Some("source_region")).await;
+    db.add_peer(peer("a1", "10.0.0.1", &["eth/68"]), Some(0))
+        .await
+        .unwrap();
+
+    let ids = ["0xA1", "a2"].map(String::from).to_vec();
+    let found = db.peers_exist(ids).await.unwrap();
+    assert_eq!(found, HashSet::from(["a1".to_string()]));
+}
+
+#[tokio::test]
+async fn peers_by_session_matches_session_id() {
+    let db = localstack_db().await;
+    let mut other = peer("a2", "10.0.0.2", &["eth/68"]);
+    other.session_id = "other".to_string();
+    db.add_peers(vec![peer("a1", "10.0.0.1", &["eth/68"]), other], Some(0))
+        .await
+        .unwrap();
+
+    let found = db.peers_by_session("other".to_string()).await.unwrap();
+    let ids: Vec<String> = found.into_iter().map(|peer| peer.id).collect();
+    assert_eq!(ids, vec!["a2".to_string()]);
+}
+
+#[tokio::test]
+async fn all_peers_returns_every_peer() {
+    let db = localstack_db().await;
+    let peers: Vec<PeerData> = [("a1", "10.0.0.1"), ("a2", "10.0.0.2"), ("a3", "10.0.0.3")]
+        .into_iter()
+        .map(|(id, address)| peer(id, address, &["eth/68"]))
+        .collect();
+    db.add_peers(peers, Some(0)).await.unwrap();
+
+    assert_eq!(db.all_peers(None, false).await.unwrap().len(), 3);
+    assert_eq!(db.count_peers().await.unwrap(), 3);
+}
+
+#[tokio::test]
+async fn late_writes_do_not_overwrite_newer_peers() {
+    let db = localstack_db().await;
+    let newer = peer("a1", "10.0.0.1", &["eth/68"]);
+    let mut older = newer.clone();
+    older.last_seen = (Utc::now() - Duration::hours(1)).to_string();
+    older.client_version = "Geth/v1.13.3-stable/linux-amd64/go1.21.3".to_string();
+    db.add_peer(newer.clone(), Some(0)).await.unwrap();
+    db.add_peer(older, Some(0)).await.unwrap();
+
+    let found = db.node_by_id("a1".to_string()).await.unwrap().unwrap();
+    assert_eq!(found, vec![newer]);
+}
+
+#[tokio::test]
+async fn late_batch_writes_do_not_overwrite_newer_peers() {
+    let db = localstack_db().await;
+    let newer = peer("a1", "10.0.0.1", &["eth/68"]);
+    let mut older = newer.clone();
+    older.last_seen = (Utc::now() - Duration::hours(1)).to_string();
+    older.client_version = "Geth/v1.13.3-stable/linux-amd64/go1.21.3".to_string();
+    let mut other_newer = peer("a2", "10.0.0.2", &["eth/68"]);
+    other_newer.last_seen = (Utc::now() - Duration::minutes(1)).to_string();
+    let mut other_older = other_newer.clone();
+    other_older.last_seen = (Utc::now() - Duration::hours(2)).to_string();
+    // the newer observations land first, as from a faster worker
+    db.add_peers(vec![newer.clone(), other_newer.clone()], Some(0))
+        .await
+        .unwrap();
+    db.add_peers(vec![older, other_older], Some(0))
+        .await
+        .unwrap();
+
+    let found = db.node_by_id("a1".to_string()).await.unwrap().unwrap();
+    assert_eq!(found, vec![newer]);
+    let found = db.node_by_id("a2".to_string()).await.unwrap().unwrap();
+    assert_eq!(found, vec![other_newer]);
+}
+
+#[tokio::test]
+async fn update_location_sets_country_and_city() {
+    let db = localstack_db().await;
+    db.add_peer(peer("a1", "10.0.0.1", &["eth/68"]), Some(0))
+        .await
+        .unwrap();
+
+    let found = db
+        .update_location("a1".to_string(), "France".to_string(), "Paris".to_string())
+        .await
+        .unwrap();
+    assert!(found);
+    let updated = db.node_by_id("a1".to_string()).await.unwrap().unwrap();
+    assert_eq!(updated[0].country, "France");
+    assert_eq!(updated[0].city, "Paris");
+}
+
+#[tokio::test]
+async fn update_chain_status_sets_the_best_block_number() {
+    let db = localstack_db().await;
+    let added = peer("a1", "10.0.0.1", &["eth/68"]);
+    db.add_peer(added.clone(), Some(0)).await.unwrap();
+
+    let last_seen = (Utc::now() + Duration::minutes(1)).to_string();
+    let found = db
+        .update_chain_status(
+            "a1".to_string(),
+            "0x9a8b".to_string(),
+            Some(18_000_100),
+            "34359738368".to_string(),
+            last_seen.clone(),
+        )
+        .await
+        .unwrap();
+    assert!(found);
+    // an older chain status arriving late leaves the newer one
+    db.update_chain_status(
+        "a1".to_string(),
+        "0x1f3d".to_string(),
+        None,
+        "17179869185".to_string(),
+        added.last_seen.clone(),
+    )
+    .await
+    .unwrap();
+
+    let updated = db.node_by_id("a1".to_string()).await.unwrap().unwrap();
+    assert_eq!(updated[0].best_block, "0x9a8b");
+    assert_eq!(updated[0].best_block_number, Some(18_000_100));
+    assert_eq!(updated[0].last_seen, last_seen);
+}
+
+#[tokio::test]
+async fn clear_keeps_the_count_history() {
+    let db = localstack_db().await;
+    db.add_peer(peer("a1", "10.0.0.1", &["eth/68"]), Some(0))
+        .await
+        .unwrap();
+    let snapshot = PeerCountSnapshot {
+        timestamp: Utc::now().to_string(),
+        count: 1,
+    };
+    db.record_peer_count(snapshot.clone()).await.unwrap();
+
+    db.clear().await.unwrap();
+    assert_eq!(db.count_peers().await.unwrap(), 0);
+    let history = db
+        .count_history(Utc::now() - Duration::hours(1))
+        .await
+        .unwrap();
+    assert_eq!(history, vec![snapshot]);
+}
@@ -0,0 +1,1337 @@
+//! The same suite run against every `PeerDB` backend that doesn't need AWS.
+
+use aws_sdk_dynamodb::types::AttributeValue;
+use chrono::{TimeZone, Utc};
+use futures::StreamExt;
+use reth_crawler_db::types::{
+    advertises_snap, integrity_violations, is_valid_enode_url, parse_total_difficulty,
+    parse_total_difficulty_u256, peer_changed, same_hash,
+};
+use reth_crawler_db::{
+    handshake_latency_percentiles, latest_per_id, verify_peers, AddItemError, CompositePeerDB,
+    InMemoryPeerDB, IntegrityViolation, LatencyPercentiles, PeerCountSnapshot, PeerDB, PeerData,
+    PeerDataError, QueryItemError, SchemaInfo, SqlPeerDB, U256,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+fn peer(id: &str, address: &str, capabilities: &[&str]) -> PeerData {
+    let total_difficulty = "17179869184".to_string();
+    let capabilities: Vec<String> = capabilities.iter().map(|cap| cap.to_string()).collect();
+    PeerData {
+        enode_url: format!("enode://{id}@{address}:30303"),
+        id: id.to_string(),
+        address: address.to_string(),
+        is_ipv6: address.contains(':'),
+        tcp_port: 30303,
+        udp_port: Some(30303),
+        client_version: "Geth/v1.13.4-stable/linux-amd64/go1.21.3".to_string(),
+        eth_version: 68,
+        serves_snap: advertises_snap(&capabilities),
+        capabilities,
+        chain: "mainnet".to_string(),
+        total_difficulty_u128: parse_total_difficulty(&total_difficulty),
+        total_difficulty,
+        best_block: "0x1f3c".to_string(),
+        best_block_number: Some(18_000_000),
+        genesis_block_hash: "0xd4e5".to_string(),
+        last_seen: "2023-10-20 12:00:00 UTC".to_string(),
+        country: "Germany".to_string(),
+        city: "Berlin".to_string(),
+        discovery_source: "discv4".to_string(),
+        raw_handshake: None,
+        node_role: None,
+        tcp_connect_ms: Some(40),
+        handshake_ms: Some(120),
+        last_disconnect_reason: None,
+        asn: None,
+        org: None,
+        session_id: "4f0c3b6e-8d2a-4e51-9b7c-2a6f1d0e9c83".to_string(),
+    }
+}
+
+async fn backends() -> Vec<(&'static str, Box<dyn PeerDB>)> {
+    vec![
+        ("in memory", Box::new(InMemoryPeerDB::new())),
+        ("sqlite", Box::new(SqlPeerDB::new_in_memory().await)),
+    ]
+}
+
+#[tokio::test]
+async fn node_by_id_returns_added_peer() {
+    for (backend, db) in backends().await {
+        let added = peer("a1", "10.0.0.1", &["eth/67", "eth/68"]);
+        db.add_peer(added.clone(), Some(0)).await.unwrap();
+
+        let found = db.node_by_id("a1".to_string()).await.unwrap().unwrap();
+        assert_eq!(found, vec![added], "{backend}");
+    }
+}
+
+#[tokio::test]
+async fn all_peers_returns_every_peer() {
+    for (backend, db) in backends().await {
+        for (id, address) in [("a1", "10.0.0.1"), ("a2", "10.0.0.2"), ("a3", "10.0.0.3")] {
+            db.add_peer(peer(id, address, &["eth/68"]), Some(0))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            db.all_peers(None, false).await.unwrap().len(),
+            3,
+            "{backend}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn node_by_ip_matches_any_form_of_a_v6_address() {
+    for (backend, db) in backends().await {
+        db.add_peer(
+            peer("a1", "2001:0DB8:0000:0000:0000:0000:0000:0001", &["eth/68"]),
+            Some(0),
+        )
+        .await
+        .unwrap();
+
+        for ip in ["2001:db8::1", "[2001:db8::1]", "2001:db8:0::0001"] {
+            let found = db.node_by_ip(ip.to_string()).await.unwrap().unwrap();
+            assert_eq!(found.len(), 1, "{backend} {ip}");
+            assert_eq!(found[0].address, "2001:db8::1", "{backend} {ip}");
+        }
+    }
+}
+
+#[tokio::test]
+async fn require_geo_excludes_peers_without_country() {
+    for (backend, db) in backends().await {
+        db.add_peer(peer("a1", "10.0.0.1", &["eth/68"]), Some(0))
+            .await
+            .unwrap();
+        let mut unlocated = peer("a2", "10.0.0.2", &["eth/68"]);
+        unlocated.country = String::new();
+        unlocated.city = String::new();
+        db.add_peer(unlocated, Some(0)).await.unwrap();
+
+        let ids: Vec<_> = db
+            .all_peers(None, true)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|peer| peer.id)
+            .collect();
+        assert_eq!(ids, vec!["a1"], "{backend}");
+        let ids: Vec<_> = db
+            .peers_stream(true)
+            .map(|peer| peer.unwrap().id)
+            .collect()
+            .await;
+        assert_eq!(ids, vec!["a1"], "{backend}");
+        assert_eq!(
+            db.all_peers(None, false).await.unwrap().len(),
+            2,
+            "{backend}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn node_by_ip_matches_address() {
+    for (backend, db) in backends().await {
+        db.add_peer(peer("a1", "10.0.0.1", &["eth/68"]), Some(0))
+            .await
+            .unwrap();
+        db.add_peer(peer("a2", "10.0.0.2", &["eth/68"]), Some(0))
+            .await
+            .unwrap();
+
+        let found = db
+            .node_by_ip("10.0.0.2".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        let ids: Vec<_> = found.iter().map(|peer| peer.id.as_str()).collect();
+        assert_eq!(ids, vec!["a2"], "{backend}");
+    }
+}
+
+#[tokio::test]
+async fn raw_handshake_round_trips() {
+    for (backend, db) in backends().await {
+        let mut added = peer("a1", "10.0.0.1", &["eth/68"]);
+        added.raw_handshake = Some("f84b05".to_string());
+        db.add_peer(added.clone(), Some(0)).await.unwrap();
+
+        let found = db.node_by_id("a1".to_string()).await.unwrap().unwrap();
+        assert_eq!(found, vec![added], "{backend}");
+    }
+}
+
+#[tokio::test]
+async fn handshake_latency_percentiles_cover_measured_peers() {
+    for (backend, db) in backends().await {
+        assert_eq!(
+            handshake_latency_percentiles(db.as_ref()).await.unwrap(),
+            None,
+            "{backend}"
+        );
+        for ms in 1..=100 {
+            let mut added = peer(&format!("{ms:x}"), "10.0.0.1", &["eth/68"]);
+            added.handshake_ms = Some(ms);
+            db.add_peer(added, Some(0)).await.unwrap();
+        }
+        let mut unmeasured = peer("ff", "10.0.0.2", &["eth/68"]);
+        unmeasured.tcp_connect_ms = None;
+        unmeasured.handshake_ms = None;
+        db.add_peer(unmeasured.clone(), Some(0)).await.unwrap();
+
+        assert_eq!(
+            handshake_latency_percentiles(db.as_ref()).await.unwrap(),
+            Some(LatencyPercentiles {
+                p50: 50,
+                p90: 90,
+                p99: 99
+            }),
+            "{backend}"
+        );
+        let found = db.node_by_id("ff".to_string()).await.unwrap().unwrap();
+        assert_eq!(found, vec![unmeasured], "{backend}");
+    }
+}
+
+#[test]
+fn add_errors_name_the_peer() {
+    let err = AddItemError::InMemoryDbAddItemError().with_peer("a1", "10.0.0.1");
+    assert_eq!(
+        err.to_string(),
+        "Failed to add peer a1 at 10.0.0.1: An error occurred adding a new item into the in memory db"
+    );
+    assert!(std::error::Error::source(&err).is_some());
+}
+
+#[test]
+fn items_without_handshake_timings_read_as_none() {
+    let item = HashMap::from([
+        ("peer-id".to_string(), AttributeValue::S("a1".to_string())),
+        (
+            "peer-ip".to_string(),
+            AttributeValue::S("10.0.0.1".to_string()),
+        ),
+    ]);
+    let peer_data = PeerData::try_from(&item).unwrap();
+    assert_eq!(peer_data.tcp_connect_ms, None);
+    assert_eq!(peer_data.handshake_ms, None);
+}
+
+#[tokio::test]
+async fn update_location_sets_country_and_city() {
+    for (backend, db) in backends().await {
+        let mut added = peer("a1", "10.0.0.1", &["eth/68"]);
+        added.country = String::new();
+        added.city = String::new();
+        db.add_peer(added.clone(), Some(0)).await.unwrap();
+
+        let found = db
+            .update_location(
+                "0xA1".to_string(),
+                "France".to_string(),
+                "Paris".to_string(),
+            )
+            .await
+            .unwrap();
+        assert!(found, "{backend}");
+        let updated = db.node_by_id("a1".to_string()).await.unwrap().unwrap();
+        assert_eq!(updated[0].country, "France", "{backend}");
+        assert_eq!(updated[0].city, "Paris", "{backend}");
+        assert_eq!(updated[0].client_version, added.client_version, "{backend}");
+
+        let missing = db
+            .update_location("b2".to_string(), "France".to_string(), "Paris".to_string())
+            .await
+            .unwrap();
+        assert!(!missing, "{backend}");
+    }
+}
+
+#[tokio::test]
+async fn update_chain_status_only_changes_chain_status() {
+    for (backend, db) in backends().await {
+        let added = peer("a1", "10.0.0.1", &["eth/68"]);
+        db.add_peer(added.clone(), Some(0)).await.unwrap();
+
+        let found = db
+            .update_chain_status(
+                "0xA1".to_string(),
+                "0x9a8b".to_string(),
+                Some(18_000_100),
+                "34359738368".to_string(),
+                "2023-10-21 12:00:00 UTC".to_string(),
+            )
+            .await
+            .unwrap();
+        assert!(found, "{backend}");
+        let updated = db.node_by_id("a1".to_string()).await.unwrap().unwrap();
+        let mut expected = added.clone();
+        expected.best_block = "0x9a8b".to_string();
+        expected.best_block_number = Some(18_000_100);
+        expected.total_difficulty = "34359738368".to_string();
+        expected.total_difficulty_u128 = Some(34_359_738_368);
+        expected.last_seen = "2023-10-21 12:00:00 UTC".to_string();
+        assert_eq!(updated, vec![expected], "{backend}");
+
+        let missing = db
+            .update_chain_status(
+                "b2".to_string(),
+                "0x9a8b".to_string(),
+                None,
+                "34359738368".to_string(),
+                "2023-10-21 12:00:00 UTC".to_string(),
+            )
+            .await
+            .unwrap();
+        assert!(!missing, "{backend}");
+
+        // a peer that didn't answer the header request has no known number for its new block
+        db.update_chain_status(
+            "a1".to_string(),
+            "0x9a8c".to_string(),
+            None,
+            "34359738369".to_string(),
+            "2023-10-22 12:00:00 UTC".to_string(),
+        )
+        .await
+        .unwrap();
+        let updated = db.node_by_id("a1".to_string()).await.unwrap().unwrap();
+        assert_eq!(updated[0].best_block_number, None, "{backend}");
+
+        // a late result of an earlier recrawl doesn't roll the chain status back
+        let found = db
+            .update_chain_status(
+                "a1".to_string(),
+                "0x1111".to_string(),
+                Some(17_000_000),
+                "1".to_string(),
+                "2023-10-21 18:00:00 UTC".to_string(),
+            )
+            .await
+            .unwrap();
+        assert!(found, "{backend}");
+        let unchanged = db.node_by_id("a1".to_string()).await.unwrap().unwrap();
+        assert_eq!(unchanged, updated, "{backend}");
+    }
+}
+
+#[tokio::test]
+async fn stale_peers_are_the_oldest_first() {
+    for (backend, db) in backends().await {
+        for (id, address, last_seen) in [
+            ("a1", "10.0.0.1", "2023-10-20 12:00:00 UTC"),
+            ("a2", "10.0.0.2", "2023-10-18 12:00:00 UTC"),
+            ("a3", "10.0.0.3", "2023-10-19 12:00:00 UTC"),
+        ] {
+            let mut peer_data = peer(id, address, &["eth/68"]);
+            peer_data.last_seen = last_seen.to_string();
+            db.add_peer(peer_data, Some(0)).await.unwrap();
+        }
+
+        let ids: Vec<_> = db
+            .stale_peers(2)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|peer| peer.id)
+            .collect();
+        assert_eq!(ids, vec!["a2", "a3"], "{backend}");
+    }
+}
+
+#[tokio::test]
+async fn peers_stream_yields_every_peer() {
+    for (backend, db) in backends().await {
+        // more than a sqlite page
+        let added: Vec<_> = (0..1_500)
+            .map(|i| peer(&format!("{i:04x}"), "10.0.0.1", &["eth/68"]))
+            .collect();
+        db.add_peers(added, Some(0)).await.unwrap();
+
+        let mut ids: Vec<_> = db
+            .peers_stream(false)
+            .map(|peer| peer.unwrap().id)
+            .collect()
+            .await;
+        ids.sort();
+        let expected: Vec<_> = (0..1_500).map(|i| format!("{i:04x}")).collect();
+        assert_eq!(ids, expected, "{backend}");
+    }
+}
+
+#[tokio::test]
+async fn peers_seen_between_returns_every_peer_in_the_window() {
+    for (backend, db) in backends().await {
+        // more than the default page size
+        let added: Vec<_> = (0..60)
+            .map(|i| peer(&format!("{i:04x}"), "10.0.0.1", &["eth/68"]))
+            .collect();
+        db.add_peers(added, Some(0)).await.unwrap();
+        let mut earlier = peer("ffff", "10.0.0.2", &["eth/68"]);
+        earlier.last_seen = "2023-10-18 12:00:00 UTC".to_string();
+        db.add_peer(earlier, Some(0)).await.unwrap();
+
+        let start = Utc.with_ymd_and_hms(2023, 10, 20, 0, 0, 0).unwrap();
+        let end = Utc.with_ymd_and_hms(2023, 10, 21, 0, 0, 0).unwrap();
+        let mut ids: Vec<_> = db
+            .peers_seen_between(start, end, None)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|peer| peer.id)
+            .collect();
+        ids.sort();
+        let expected: Vec<_> = (0..60).map(|i| format!("{i:04x}")).collect();
+        assert_eq!(ids, expected, "{backend}");
+    }
+}
+
+#[tokio::test]
+async fn nodes_by_role_matches_flagged_peers() {
+    for (backend, db) in backends().await {
+        for (id, address, role) in [
+            ("a1", "10.0.0.1", Some("sentry")),
+            ("a2", "10.0.0.1", Some("proxy")),
+            ("a3", "10.0.0.3", None),
+        ] {
+            let mut peer_data = peer(id, address, &["eth/68"]);
+            peer_data.node_role = role.map(str::to_string);
+            db.add_peer(peer_data, Some(0)).await.unwrap();
+        }
+
+        let found = db.nodes_by_role("sentry".to_string()).await.unwrap();
+        let ids: Vec<_> = found.iter().map(|peer| peer.id.as_str()).collect();
+        assert_eq!(ids, vec!["a1"], "{backend}");
+        assert_eq!(found[0].node_role.as_deref(), Some("sentry"), "{backend}");
+    }
+}
+
+#[tokio::test]
+async fn peers_by_session_returns_the_peers_of_one_crawl() {
+    for (backend, db) in backends().await {
+        for (id, address, session_id) in [
+            ("a1", "10.0.0.1", "first"),
+            ("a2", "10.0.0.2", "first"),
+            ("a3", "10.0.0.3", "second"),
+        ] {
+            let mut peer_data = peer(id, address, &["eth/68"]);
+            peer_data.session_id = session_id.to_string();
+            db.add_peer(peer_data, Some(0)).await.unwrap();
+        }
+        // the second crawl observes a2 again, which replaces its record
+        let mut observed_again = peer("a2", "10.0.0.2", &["eth/68"]);
+        observed_again.session_id = "second".to_string();
+        observed_again.last_seen = "2023-10-21 12:00:00 UTC".to_string();
+        db.add_peer(observed_again, Some(0)).await.unwrap();
+
+        let ids = |peers: Vec<PeerData>| {
+            let mut ids: Vec<String> = peers.into_iter().map(|peer| peer.id).collect();
+            ids.sort();
+            ids
+        };
+        let first = db.peers_by_session("first".to_string()).await.unwrap();
+        assert_eq!(ids(first), vec!["a1".to_string()], "{backend}");
+        let second = db.peers_by_session("second".to_string()).await.unwrap();
+        assert_eq!(
+            ids(second),
+            vec!["a2".to_string(), "a3".to_string()],
+            "{backend}"
+        );
+        assert_eq!(db.count_peers().await.unwrap(), 3, "{backend}");
+    }
+}
+
+#[tokio::test]
+async fn late_writes_do_not_overwrite_newer_peers() {
+    for (backend, db) in backends().await {
+        let mut newer = peer("a1", "10.0.0.1", &["eth/68"]);
+        newer.last_seen = "2023-10-21 12:00:00 UTC".to_string();
+        newer.best_block_number = Some(18_100_000);
+        let mut older = peer("a1", "10.0.0.1", &["eth/68"]);
+        older.last_seen = "2023-10-20 12:00:00.5 UTC".to_string();
+        let mut newest = peer("a1", "10.0.0.9", &["eth/68"]);
+        newest.last_seen = "2023-10-22 12:00:00 UTC".to_string();
+
+        db.add_peer(newer.clone(), Some(0)).await.unwrap();
+        db.add_peer(older.clone(), Some(0)).await.unwrap();
+        let found = db.node_by_id("a1".to_string()).await.unwrap().unwrap();
+        assert_eq!(found, vec![newer.clone()], "{backend}: add_peer");
+
+        db.add_peers(vec![older], Some(0)).await.unwrap();
+        let found = db.node_by_id("a1".to_string()).await.unwrap().unwrap();
+        assert_eq!(found, vec![newer], "{backend}: add_peers");
+
+        db.add_peers(vec![newest.clone()], Some(0)).await.unwrap();
+        let found = db.node_by_id("a1".to_string()).await.unwrap().unwrap();
+        assert_eq!(found, vec![newest], "{backend}: newer write");
+    }
+}
+
+#[test]
+fn snap_is_detected_from_versioned_capabilities() {
+    let capabilities = |caps: &[&str]| caps.iter().map(|cap| cap.to_string()).collect::<Vec<_>>();
+    assert!(advertises_snap(&capabilities(&["eth/68", "snap/1"])));
+    assert!(advertises_snap(&capabilities(&["snap/2"])));
+    assert!(!advertises_snap(&capabilities(&["eth/68", "snap/0"])));
+    assert!(!advertises_snap(&capabilities(&["snap", "wit/0"])));
+}
+
+#[tokio::test]
+async fn snap_servers_are_counted() {
+    for (backend, db) in backends().await {
+        for (id, caps) in [
+            ("a1", &["eth/68", "snap/1"][..]),
+            ("a2", &["eth/68"][..]),
+            ("a3", &["snap/2"][..]),
+        ] {
+            db.add_peer(peer(id, "10.0.0.1", caps), Some(0))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(db.snap_server_count().await.unwrap(), 2, "{backend}");
+        let found = db.node_by_id("a1".to_string()).await.unwrap().unwrap();
+        assert!(found[0].serves_snap, "{backend}");
+    }
+}
+
+#[tokio::test]
+async fn distinct_counts_tell_hosts_from_nodes() {
+    for (backend, db) in backends().await {
+        // two nodes on one host, and a third node elsewhere
+        for (id, address) in [("a1", "10.0.0.1"), ("a2", "10.0.0.1"), ("a3", "10.0.0.2")] {
+            db.add_peer(peer(id, address, &["eth/68"]), Some(0))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(db.distinct_ip_count().await.unwrap(), 2, "{backend}");
+        assert_eq!(db.distinct_peer_id_count().await.unwrap(), 3, "{backend}");
+    }
+}
+
+#[tokio::test]
+async fn clear_deletes_every_peer() {
+    for (backend, db) in backends().await {
+        for (id, address) in [("a1", "10.0.0.1"), ("a2", "10.0.0.2")] {
+            db.add_peer(peer(id, address, &["eth/68"]), Some(0))
+                .await
+                .unwrap();
+        }
+
+        db.clear().await.unwrap();
+        assert!(
+            db.all_peers(None, false).await.unwrap().is_empty(),
+            "{backend}"
+        );
+        assert!(
+            !db.peer_exists("a1".to_string()).await.unwrap(),
+            "{backend}"
+        );
+        // the schema metadata survives
+        assert!(db.schema_info().await.is_ok(), "{backend}");
+    }
+}
+
+#[tokio::test]
+async fn count_history_returns_snapshots_since() {
+    for (backend, db) in backends().await {
+        let snapshots: Vec<PeerCountSnapshot> = [
+            ("2023-10-20 12:10:00 UTC", 12),
+            ("2023-10-20 12:00:00 UTC", 10),
+            ("2023-10-20 12:05:00 UTC", 11),
+        ]
+        .into_iter()
+        .map(|(timestamp, count)| PeerCountSnapshot {
+            timestamp: timestamp.to_string(),
+            count,
+        })
+        .collect();
+        for snapshot in &snapshots {
+            db.record_peer_count(snapshot.clone()).await.unwrap();
+        }
+
+        let since = "2023-10-20T12:05:00Z".parse().unwrap();
+        let history = db.count_history(since).await.unwrap();
+        assert_eq!(
+            history,
+            vec![snapshots[2].clone(), snapshots[0].clone()],
+            "{backend}"
+        );
+        // the history isn't counted as peers, and survives a clear
+        assert_eq!(db.count_peers().await.unwrap(), 0, "{backend}");
+        db.clear().await.unwrap();
+        assert_eq!(db.count_history(since).await.unwrap().len(), 2, "{backend}");
+    }
+}
+
+#[tokio::test]
+async fn empty_capabilities_round_trip() {
+    for (backend, db) in backends().await {
+        db.add_peer(peer("a1", "10.0.0.1", &[]), Some(0))
+            .await
+            .unwrap();
+
+        let found = db.node_by_id("a1".to_string()).await.unwrap().unwrap();
+        assert!(found[0].capabilities.is_empty(), "{backend}");
+    }
+}
+
+#[tokio::test]
+async fn nodes_by_capability_matches_whole_capabilities() {
+    for (backend, db) in backends().await {
+        db.add_peer(peer("a1", "10.0.0.1", &["eth/68", "snap/1"]), Some(0))
+            .await
+            .unwrap();
+        db.add_peer(peer("a2", "10.0.0.2", &["eth/66"]), Some(0))
+            .await
+            .unwrap();
+
+        let found = db
+            .nodes_by_capability("snap/1".to_string(), None)
+            .await
+            .unwrap();
+        let ids: Vec<_> = found.iter().map(|peer| peer.id.as_str()).collect();
+        assert_eq!(ids, vec!["a1"], "{backend}");
+
+        for partial in ["snap", "eth/6", "68"] {
+            let found = db
+                .nodes_by_capability(partial.to_string(), None)
+                .await
+                .unwrap();
+            assert!(found.is_empty(), "{backend}: {partial}");
+        }
+    }
+}
+
+#[test]
+fn latest_per_id_keeps_the_latest_record_of_each_peer() {
+    let record = |id: &str, address: &str, last_seen: &str| PeerData {
+        last_seen: last_seen.to_string(),
+        ..peer(id, address, &["eth/68"])
+    };
+    // a1 was written from two regions, the later write from another address
+    let peers = vec![
+        record("a1", "10.0.0.1", "2023-10-20 12:00:00 UTC"),
+        record("a2", "10.0.0.2", "2023-10-20 11:00:00 UTC"),
+        record("a1", "10.0.0.9", "2023-10-20 13:00:00 UTC"),
+    ];
+
+    let mut latest = latest_per_id(peers.clone());
+    latest.sort_by(|a, b| a.id.cmp(&b.id));
+    assert_eq!(latest, vec![peers[2].clone(), peers[1].clone()]);
+    // the order of the records doesn't matter
+    let mut latest = latest_per_id(peers.iter().rev().cloned());
+    latest.sort_by(|a, b| a.id.cmp(&b.id));
+    assert_eq!(latest, vec![peers[2].clone(), peers[1].clone()]);
+}
+
+#[tokio::test]
+async fn latest_per_peer_returns_each_peer_once() {
+    for (backend, db) in backends().await {
+        for (id, address) in [("a1", "10.0.0.1"), ("a2", "10.0.0.2"), ("a1", "10.0.0.1")] {
+            db.add_peer(peer(id, address, &["eth/68"]), Some(0))
+                .await
+                .unwrap();
+        }
+
+        let mut ids: Vec<String> = db
+            .latest_per_peer(None)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|peer_data| peer_data.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec!["a1", "a2"], "{backend}");
+    }
+}
+
+#[tokio::test]
+async fn nodes_by_cidr_matches_v4_ranges() {
+    for (backend, db) in backends().await {
+        for (id, address) in [
+            ("a1", "203.0.113.7"),
+            ("a2", "203.0.113.200"),
+            ("a3", "203.0.114.1"),
+            ("a4", "2001:db8::1"),
+        ] {
+            db.add_peer(peer(id, address, &["eth/68"]), Some(0))
+                .await
+                .unwrap();
+        }
+
+        for (cidr, expected) in [
+            ("203.0.113.0/24", vec!["a1", "a2"]),
+            // bits past the prefix are ignored
+            ("203.0.113.77/25", vec!["a1"]),
+            ("203.0.112.0/22", vec!["a1", "a2", "a3"]),
+            ("203.0.113.7/32", vec!["a1"]),
+            ("0.0.0.0/0", vec!["a1", "a2", "a3"]),
+            ("198.51.100.0/24", vec![]),
+        ] {
+            let mut ids: Vec<String> = db
+                .nodes_by_cidr(cidr.to_string(), None)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|peer_data| peer_data.id)
+                .collect();
+            ids.sort();
+            assert_eq!(ids, expected, "{backend}: {cidr}");
+        }
+    }
+}
+
+#[tokio::test]
+async fn invalid_cidrs_are_rejected() {
+    for (backend, db) in backends().await {
+        for cidr in [
+            "203.0.113.0",
+            "203.0.113.0/33",
+            "203.0.113.0/-1",
+            "203.0.113.0/+8",
+            "203.0.113/24",
+            "example.com/24",
+            "2001:db8::/129",
+        ] {
+            let result = db.nodes_by_cidr(cidr.to_string(), None).await;
+            assert!(
+                matches!(result, Err(QueryItemError::InvalidCidrError(_))),
+                "{backend}: {cidr}"
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn peer_ids_are_normalized() {
+    for (backend, db) in backends().await {
+        for id in ["0xABCDEF", "abcdef", "AbCdEf", " 0Xabcdef "] {
+            let peer_data = PeerData::builder()
+                .id(id)
+                .address("10.0.0.1")
+                .tcp_port(30303)
+                .build()
+                .unwrap();
+            assert_eq!(peer_data.id, "abcdef", "{backend}: {id}");
+            db.add_peer(peer_data, Some(0)).await.unwrap();
+        }
+
+        assert_eq!(
+            db.all_peers(None, false).await.unwrap().len(),
+            1,
+            "{backend}"
+        );
+        let found = db
+            .node_by_id("0xAbCdEf".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.len(), 1, "{backend}");
+        assert!(
+            db.peer_exists("ABCDEF".to_string()).await.unwrap(),
+            "{backend}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn peers_exist_returns_the_known_ids() {
+    for (backend, db) in backends().await {
+        db.add_peers(
+            vec![
+                peer("a1", "10.0.0.1", &["eth/68"]),
+                peer("a2", "10.0.0.2", &["eth/68"]),
+            ],
+            Some(0),
+        )
+        .await
+        .unwrap();
+
+        let ids = ["0xA1", "a2", "a3"].map(String::from).to_vec();
+        let found = db.peers_exist(ids).await.unwrap();
+        assert_eq!(
+            found,
+            HashSet::from(["a1".to_string(), "a2".to_string()]),
+            "{backend}"
+        );
+        assert!(
+            db.peers_exist(Vec::new()).await.unwrap().is_empty(),
+            "{backend}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn peers_below_difficulty_compares_totals_above_u128() {
+    // u128::MAX, u128::MAX + 1 and 2^200
+    let above_u128 = "340282366920938463463374607431768211456";
+    let huge = "1606938044258990275541962092341162602522202993782792835301376";
+    for (backend, db) in backends().await {
+        for (id, total_difficulty) in [
+            ("a1", "17179869184"),
+            ("a2", "340282366920938463463374607431768211455"),
+            ("a3", above_u128),
+            ("a4", huge),
+            ("a5", "0x11"),
+        ] {
+            let mut peer_data = peer(id, "10.0.0.1", &["eth/68"]);
+            peer_data.total_difficulty = total_difficulty.to_string();
+            peer_data.total_difficulty_u128 = parse_total_difficulty(total_difficulty);
+            db.add_peer(peer_data, Some(0)).await.unwrap();
+        }
+
+        let threshold = parse_total_difficulty_u256(above_u128).unwrap();
+        let mut below: Vec<String> = db
+            .peers_below_difficulty(threshold)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|peer| peer.id)
+            .collect();
+        below.sort();
+        assert_eq!(below, vec!["a1".to_string(), "a2".to_string()], "{backend}");
+
+        let mut below: Vec<String> = db
+            .peers_below_difficulty(U256::MAX)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|peer| peer.id)
+            .collect();
+        below.sort();
+        assert_eq!(
+            below,
+            ["a1", "a2", "a3", "a4"].map(String::from),
+            "{backend}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn control_characters_are_stripped_from_client_versions() {
+    for (backend, db) in backends().await {
+        let peer_data = PeerData::builder()
+            .id("a1")
+            .address("10.0.0.1")
+            .tcp_port(30303)
+            .client_version("Geth/v1.13.4\0-stable\x1b[31m/linux\r\n")
+            .capabilities(vec!["eth/68\x07".to_string()])
+            .raw_handshake(Some("f84b05".to_string()))
+            .build()
+            .unwrap();
+        assert_eq!(peer_data.client_version, "Geth/v1.13.4-stable[31m/linux");
+        assert_eq!(peer_data.capabilities, vec!["eth/68".to_string()]);
+        db.add_peer(peer_data, Some(0)).await.unwrap();
+
+        let found = db.node_by_id("a1".to_string()).await.unwrap().unwrap();
+        assert_eq!(
+            found[0].client_version, "Geth/v1.13.4-stable[31m/linux",
+            "{backend}"
+        );
+        assert_eq!(
+            found[0].raw_handshake.as_deref(),
+            Some("f84b05"),
+            "{backend}"
+        );
+    }
+}
+
+#[test]
+fn non_hex_peer_ids_are_rejected() {
+    let built = PeerData::builder()
+        .id("0x1234…abcd")
+        .address("10.0.0.1")
+        .tcp_port(30303)
+        .build();
+    assert!(built.is_err());
+}
+
+#[test]
+fn enode_urls_are_validated() {
+    let id = "ab".repeat(64);
+    for valid in [
+        format!("enode://{id}@10.0.0.1:30303"),
+        format!("enode://{id}@10.0.0.1:30303?discport=30301"),
+        format!("enode://{id}@[2001:db8::1]:30303"),
+    ] {
+        assert!(is_valid_enode_url(&valid), "{valid}");
+    }
+    for malformed in [
+        "".to_string(),
+        format!("enr://{id}@10.0.0.1:30303"),
+        format!("enode://{}@10.0.0.1:30303", &id[2..]),
+        format!("enode://{}@10.0.0.1:30303", "zz".repeat(64)),
+        format!("enode://{id}@10.0.0.1"),
+        format!("enode://{id}@10.0.0.1:70000"),
+        format!("enode://{id}@2001:db8::1:30303"),
+        format!("enode://{id}@example.com:30303"),
+        format!("enode://{id}@10.0.0.1:30303?discport=x"),
+    ] {
+        assert!(!is_valid_enode_url(&malformed), "{malformed}");
+    }
+
+    let built = PeerData::builder()
+        .enode_url(format!("enode://{id}@10.0.0.1"))
+        .id(&id)
+        .address("10.0.0.1")
+        .tcp_port(30303)
+        .build();
+    assert!(matches!(built, Err(PeerDataError::InvalidEnodeUrl(_))));
+}
+
+#[test]
+fn missing_enode_urls_are_rebuilt() {
+    let id = "ab".repeat(64);
+    for (address, enode_url) in [
+        ("10.0.0.1", format!("enode://{id}@10.0.0.1:30303")),
+        ("2001:db8::1", format!("enode://{id}@[2001:db8::1]:30303")),
+    ] {
+        let built = PeerData::builder()
+            .id(&id)
+            .address(address)
+            .tcp_port(30303)
+            .build()
+            .unwrap();
+        assert_eq!(built.enode_url, enode_url);
+        assert!(is_valid_enode_url(&built.enode_url));
+    }
+
+    // a truncated id can't make a valid enode URL
+    let built = PeerData::builder()
+        .id("a1")
+        .address("10.0.0.1")
+        .tcp_port(30303)
+        .build()
+        .unwrap();
+    assert!(built.enode_url.is_empty());
+}
+
+#[tokio::test]
+async fn boundary_ports_round_trip() {
+    let zero_port = PeerData::builder()
+        .id("a1")
+        .address("10.0.0.1")
+        .tcp_port(0)
+        .build();
+    assert!(matches!(zero_port, Err(PeerDataError::InvalidPort)));
+
+    for (backend, db) in backends().await {
+        let added = PeerData::builder()
+            .id("a1")
+            .address("10.0.0.1")
+            .tcp_port(65535)
+            .udp_port(Some(65535))
+            .build()
+            .unwrap();
+        db.add_peer(added.clone(), Some(0)).await.unwrap();
+
+        let found = db.node_by_id("a1".to_string()).await.unwrap().unwrap();
+        assert_eq!(found[0].tcp_port, 65535, "{backend}");
+        assert_eq!(found[0].udp_port, Some(65535), "{backend}");
+    }
+}
+
+#[test]
+fn out_of_range_item_ports_are_rejected() {
+    let item = |port: &str| {
+        HashMap::from([
+            ("peer-id".to_string(), AttributeValue::S("a1".to_string())),
+            (
+                "peer-ip".to_string(),
+                AttributeValue::S("10.0.0.1".to_string()),
+            ),
+            ("port".to_string(), AttributeValue::N(port.to_string())),
+        ])
+    };
+
+    assert_eq!(PeerData::try_from(&item("65535")).unwrap().tcp_port, 65535);
+    for port in ["65536", "-1"] {
+        assert!(
+            matches!(
+                PeerData::try_from(&item(port)),
+                Err(PeerDataError::PortOutOfRange(_))
+            ),
+            "{port}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn country_distribution_counts_unknown_countries() {
+    for (backend, db) in backends().await {
+        for (id, address, country) in [
+            ("a1", "10.0.0.1", "Germany"),
+            ("a2", "10.0.0.2", "Germany"),
+            ("a3", "10.0.0.3", "France"),
+            ("a4", "10.0.0.4", ""),
+            ("a5", "10.0.0.5", "unknown"),
+        ] {
+            let mut peer_data = peer(id, address, &["eth/68"]);
+            peer_data.country = country.to_string();
+            db.add_peer(peer_data, Some(0)).await.unwrap();
+        }
+
+        let distribution = db.country_distribution().await.unwrap();
+        assert_eq!(distribution.len(), 3, "{backend}");
+        assert_eq!(distribution["Germany"], 2, "{backend}");
+        assert_eq!(distribution["France"], 1, "{backend}");
+        assert_eq!(distribution["unknown"], 2, "{backend}");
+    }
+}
+
+#[tokio::test]
+async fn capability_distribution_counts_each_capability() {
+    for (backend, db) in backends().await {
+        for (id, capabilities) in [
+            ("a1", &["eth/67", "eth/68", "snap/1"][..]),
+            ("a2", &["eth/68", "snap/1"][..]),
+            ("a3", &["eth/66", "eth/66"][..]),
+            ("a4", &[][..]),
+        ] {
+            db.add_peer(peer(id, "10.0.0.1", capabilities), Some(0))
+                .await
+                .unwrap();
+        }
+
+        let distribution = db.capability_distribution().await.unwrap();
+        assert_eq!(
+            distribution,
+            HashMap::from([
+                ("eth/66".to_string(), 1),
+                ("eth/67".to_string(), 1),
+                ("eth/68".to_string(), 2),
+                ("snap/1".to_string(), 2),
+            ]),
+            "{backend}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn disconnect_reason_distribution_counts_partial_peers() {
+    for (backend, db) in backends().await {
+        for (id, reason) in [
+            ("a1", Some("Too many peers")),
+            ("a2", Some("Too many peers")),
+            ("a3", Some("Useless peer")),
+            ("a4", None),
+        ] {
+            let mut peer_data = peer(id, "10.0.0.1", &["eth/68"]);
+            peer_data.last_disconnect_reason = reason.map(String::from);
+            db.add_peer(peer_data, Some(0)).await.unwrap();
+        }
+
+        let distribution = db.disconnect_reason_distribution().await.unwrap();
+        assert_eq!(
+            distribution,
+            HashMap::from([
+                ("Too many peers".to_string(), 2),
+                ("Useless peer".to_string(), 1),
+            ]),
+            "{backend}"
+        );
+        let found = db.node_by_id("a3".to_string()).await.unwrap().unwrap();
+        assert_eq!(
+            found[0].last_disconnect_reason.as_deref(),
+            Some("Useless peer"),
+            "{backend}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn asn_distribution_counts_each_autonomous_system() {
+    for (backend, db) in backends().await {
+        for (id, asn, org) in [
+            ("a1", Some(16509), Some("Amazon.com, Inc.")),
+            ("a2", Some(16509), Some("Amazon.com, Inc.")),
+            ("a3", Some(24940), Some("Hetzner Online GmbH")),
+            ("a4", Some(64512), None),
+            ("a5", None, None),
+        ] {
+            let mut peer_data = peer(id, "10.0.0.1", &["eth/68"]);
+            peer_data.asn = asn;
+            peer_data.org = org.map(String::from);
+            db.add_peer(peer_data, Some(0)).await.unwrap();
+        }
+
+        let distribution = db.asn_distribution().await.unwrap();
+        assert_eq!(
+            distribution,
+            HashMap::from([
+                ("AS16509 Amazon.com, Inc.".to_string(), 2),
+                ("AS24940 Hetzner Online GmbH".to_string(), 1),
+                ("AS64512".to_string(), 1),
+            ]),
+            "{backend}"
+        );
+        let found = db.node_by_id("a3".to_string()).await.unwrap().unwrap();
+        assert_eq!(found[0].asn, Some(24940), "{backend}");
+        assert_eq!(
+            found[0].org.as_deref(),
+            Some("Hetzner Online GmbH"),
+            "{backend}"
+        );
+    }
+}
+
+#[test]
+fn genesis_hashes_match_regardless_of_formatting() {
+    let mainnet = "0xd4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3";
+    assert!(same_hash(mainnet, mainnet));
+    assert!(same_hash(
+        mainnet,
+        "D4E56740F876AEF8C010B86A40D5F56745A118D0906A34E69AEC8C0DB1CB8FA3"
+    ));
+
+    let sepolia = "0x25a5cc106eea7138acab33231d7160d69cb777ee0c2c553fcddf5138993e6dd9";
+    assert!(!same_hash(mainnet, sepolia));
+}
+
+#[test]
+fn peer_changed_reports_notable_changes() {
+    let existing = peer("a1", "10.0.0.1", &["eth/67", "eth/68"]);
+    let mut incoming = existing.clone();
+    incoming.last_seen = "2023-10-21 12:00:00 UTC".to_string();
+    incoming.best_block_number = Some(18_000_100);
+    assert!(peer_changed(&existing, &incoming).is_empty());
+
+    incoming.client_version = "Geth/v1.13.5-stable/linux-amd64/go1.21.4".to_string();
+    incoming.capabilities.push("snap/1".to_string());
+    incoming.best_block_number = Some(18_100_000);
+    let fields: Vec<_> = peer_changed(&existing, &incoming)
+        .into_iter()
+        .map(|change| change.field)
+        .collect();
+    assert_eq!(
+        fields,
+        vec!["client_version", "capabilities", "best_block_number"]
+    );
+}
+
+#[test]
+fn oversized_peers_are_truncated() {
+    const MAX_SIZE: usize = 400 * 1024;
+    let mut oversized = peer("a1", "10.0.0.1", &["eth/68"]);
+    oversized.capabilities = (0..100_000).map(|i| format!("cap{i}/1")).collect();
+    assert!(oversized.approximate_size() > MAX_SIZE);
+
+    let dropped = oversized.truncate_to(MAX_SIZE);
+    assert_eq!(dropped, vec!["capabilities"]);
+    assert!(oversized.approximate_size() <= MAX_SIZE);
+    assert_eq!(oversized.id, "a1");
+    assert_eq!(
+        oversized.client_version,
+        "Geth/v1.13.4-stable/linux-amd64/go1.21.3"
+    );
+
+    let mut with_handshake = peer("a3", "10.0.0.3", &["eth/68"]);
+    with_handshake.raw_handshake = Some("f8".repeat(MAX_SIZE));
+    assert_eq!(with_handshake.truncate_to(MAX_SIZE), vec!["raw_handshake"]);
+    assert_eq!(with_handshake.capabilities, vec!["eth/68"]);
+
+    let mut small = peer("a2", "10.0.0.2", &["eth/68"]);
+    assert!(small.truncate_to(MAX_SIZE).is_empty());
+    assert_eq!(small.capabilities, vec!["eth/68"]);
+}
+
+#[tokio::test]
+async fn composite_writes_to_every_backend() {
+    let memory = Arc::new(InMemoryPeerDB::new());
+    let sqlite = Arc::new(SqlPeerDB::new_in_memory().await);
+    let backends: Vec<Arc<dyn PeerDB>> = vec![memory.clone(), sqlite.clone()];
+    let db = CompositePeerDB::new(backends);
+    db.add_peer(peer("a1", "10.0.0.1", &["eth/68"]), Some(0))
+        .await
+        .unwrap();
+    db.add_peers(
+        vec![
+            peer("a2", "10.0.0.2", &["eth/68"]),
+            peer("a3", "10.0.0.3", &["eth/68"]),
+        ],
+        Some(0),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(memory.count_peers().await.unwrap(), 3);
+    assert_eq!(sqlite.count_peers().await.unwrap(), 3);
+    assert_eq!(db.count_peers().await.unwrap(), 3);
+}
+
+#[tokio::test]
+async fn schema_info_is_recorded() {
+    for (backend, db) in backends().await {
+        assert_eq!(
+            db.schema_info().await.unwrap(),
+            SchemaInfo::current(),
+            "{backend}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn sqlite_skips_malformed_rows() {
+    let path =
+        std::env::temp_dir().join(format!("reth-crawler-malformed-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let db = SqlPeerDB::open(&path).await;
+    db.add_peer(peer("a1", "10.0.0.1", &["eth/68"]), Some(0))
+        .await
+        .unwrap();
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    // `country` is nullable in the schema but not in `PeerData`
+    conn.execute(
+        "INSERT INTO eth_peer_data (id, ip, client_version, enode_url, port, chain, genesis_hash, best_block, total_difficulty, country, city, last_seen, capabilities) VALUES ('a2', '10.0.0.2', 'Geth', '', 30303, 'mainnet', '', '', '0', NULL, NULL, '', '')",
+        [],
+    )
+    .unwrap();
+    // ports must fit in a `u16`
+    conn.execute(
+        "INSERT INTO eth_peer_data (id, ip, client_version, enode_url, port, chain, genesis_hash, best_block, total_difficulty, country, city, last_seen, capabilities) VALUES ('a3', '10.0.0.3', 'Geth', '', 65536, 'mainnet', '', '', '0', 'Germany', 'Berlin', '', '')",
+        [],
+    )
+    .unwrap();
+
+    let ids: Vec<_> = db
+        .all_peers(None, false)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|peer| peer.id)
+        .collect();
+    assert_eq!(ids, vec!["a1"]);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn sqlite_rows_without_serves_snap_derive_it() {
+    let path = std::env::temp_dir().join(format!("reth-crawler-snap-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let db = SqlPeerDB::open(&path).await;
+    let conn = rusqlite::Connection::open(&path).unwrap();
+    // as written before `serves_snap` was stored
+    conn.execute(
+        "INSERT INTO eth_peer_data (id, ip, client_version, enode_url, port, chain, genesis_hash, best_block, total_difficulty, country, city, last_seen, capabilities) VALUES ('a1', '10.0.0.1', 'Geth', '', 30303, 'mainnet', '', '', '0', 'Germany', 'Berlin', '', 'eth/68,snap/1')",
+        [],
+    )
+    .unwrap();
+
+    assert_eq!(db.snap_server_count().await.unwrap(), 1);
+    let found = db.node_by_id("a1".to_string()).await.unwrap().unwrap();
+    assert!(found[0].serves_snap);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn concurrent_batches_are_all_written() {
+    let path =
+        std::env::temp_dir().join(format!("reth-crawler-concurrent-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let backends: Vec<(&str, Box<dyn PeerDB>)> = vec![
+        ("in memory", Box::new(InMemoryPeerDB::new())),
+        (
+            "sqlite",
+            Box::new(SqlPeerDB::open_with_pool(&path, 4).await),
+        ),
+    ];
+    for (backend, db) in backends {
+        let batches = (0..8).map(|batch| {
+            let peers = (0..50)
+                .map(|i| peer(&format!("{batch:x}{i:02x}"), "10.0.0.1", &["eth/68"]))
+                .collect();
+            db.add_peers(peers, Some(0))
+        });
+        for result in futures::future::join_all(batches).await {
+            result.unwrap();
+        }
+        assert_eq!(db.count_peers().await.unwrap(), 400, "{backend}");
+    }
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn integrity_violations_flag_corrupt_fields() {
+    let valid = peer(&"ab".repeat(64), "10.0.0.1", &["eth/68", "snap/1"]);
+    assert_eq!(integrity_violations(&valid), vec![]);
+    let mut rfc3339 = valid.clone();
+    rfc3339.last_seen = "2023-10-20T12:00:00Z".to_string();
+    assert_eq!(integrity_violations(&rfc3339), vec![]);
+
+    let mut corrupt = peer("", "not-an-ip", &["eth/68,snap/1"]);
+    corrupt.tcp_port = 0;
+    corrupt.last_seen = "yesterday".to_string();
+    assert_eq!(
+        integrity_violations(&corrupt),
+        vec![
+            IntegrityViolation::EmptyId,
+            IntegrityViolation::InvalidAddress,
+            IntegrityViolation::InvalidTcpPort,
+            IntegrityViolation::MalformedEnodeUrl,
+            IntegrityViolation::InvalidLastSeen,
+            IntegrityViolation::InconsistentCapabilities,
+        ]
+    );
+
+    let mut snap_mismatch = valid.clone();
+    snap_mismatch.serves_snap = false;
+    assert_eq!(
+        integrity_violations(&snap_mismatch),
+        vec![IntegrityViolation::InconsistentCapabilities]
+    );
+}
+
+#[tokio::test]
+async fn verify_peers_reports_invalid_peers() {
+    for (backend, db) in backends().await {
+        let valid_id = "ab".repeat(64);
+        db.add_peer(peer(&valid_id, "10.0.0.1", &["eth/68"]), Some(0))
+            .await
+            .unwrap();
+        let mut bad_address = peer(&"cd".repeat(64), "10.0.0.2", &["eth/68"]);
+        bad_address.address = "not-an-ip".to_string();
+        db.add_peer(bad_address, Some(0)).await.unwrap();
+        // capabilities joined by a comma across versions read back inconsistent either way
+        db.add_peer(
+            peer(&"ef".repeat(64), "10.0.0.3", &["eth/68,snap/1"]),
+            Some(0),
+        )
+        .await
+        .unwrap();
+
+        let report = verify_peers(db.as_ref()).await.unwrap();
+        assert_eq!(report.checked, 3, "{backend}");
+        assert_eq!(
+            report.violations,
+            BTreeMap::from([
+                (IntegrityViolation::InvalidAddress, 1),
+                (IntegrityViolation::InconsistentCapabilities, 1),
+            ]),
+            "{backend}"
+        );
+        let mut invalid_ids = report.invalid_ids.clone();
+        invalid_ids.sort();
+        assert_eq!(
+            invalid_ids,
+            vec!["cd".repeat(64), "ef".repeat(64)],
+            "{backend}"
+        );
+
+        for id in report.invalid_ids {
+            assert!(db.delete_peer(id).await.unwrap(), "{backend}");
+        }
+        assert!(!db.delete_peer("cd".repeat(64)).await.unwrap(), "{backend}");
+        let remaining = verify_peers(db.as_ref()).await.unwrap();
+        assert_eq!(remaining.checked, 1, "{backend}");
+        assert!(remaining.invalid_ids.is_empty(), "{backend}");
+    }
+}